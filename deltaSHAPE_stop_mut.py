@@ -10,7 +10,7 @@ import pandas as pd
 
 def open_csv(filename, front, back):
     # Read the CSV file
-    df = pd.read_csv(filename)
+    df = pd.read_csv(filename, comment='#')
     
     # Check if the file contains fewer than 4 columns
     if df.shape[1] < 4:
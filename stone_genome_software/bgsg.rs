@@ -1,9 +1,17 @@
-use std::collections::HashMap;
+mod config;
+mod faidx;
+mod provenance;
+
+use config::Config;
+use faidx::Fasta;
+use provenance::Block;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
 use std::{char, str};
 use std::sync::{Arc, Mutex};
-use std::io::{BufReader, BufRead, Write};
+use std::io::{BufReader, BufRead, BufWriter, Write};
 use std::time::Instant;
 use std::collections::HashSet;
 use rand::Rng;
@@ -119,6 +127,7 @@ struct Record {
     strand: String,
     position: usize,
     info: String,  
+    ref_base: Option<char>,
 }
 
 #[derive(Debug)]
@@ -128,6 +137,8 @@ struct BedRecord {
     strand: String,
     name: String,
     tname: String,
+    // 转录本长度, 默认 end - start + 1, --len 给出时以 .len 为准
+    length: usize,
 }
 
 fn get_chr_nc_for_species(species: &str, chr: &str) -> String {
@@ -140,7 +151,8 @@ fn get_chr_nc_for_species(species: &str, chr: &str) -> String {
     }
 }
 
-fn read_bed_file(file_path: &str,species: &str) -> HashMap<String, Vec<BedRecord>> {
+// aliases collects the bed chromosome names renamed for the species
+fn read_bed_file(file_path: &str,species: &str, aliases: &mut BTreeMap<String, String>) -> HashMap<String, Vec<BedRecord>> {
     let file = File::open(file_path).unwrap();
     let reader = BufReader::new(file);
 
@@ -150,7 +162,7 @@ fn read_bed_file(file_path: &str,species: &str) -> HashMap<String, Vec<BedRecord
         let line = line.unwrap();
         let fields: Vec<&str> = line.split_whitespace().collect();
 
-        if fields.len() >= 1 {
+        if !fields.is_empty() {
             let chr = fields[0].to_string();                // 
             let start: usize = fields[1].parse().unwrap();  
             let end: usize = fields[2].parse().unwrap();    // 
@@ -159,7 +171,10 @@ fn read_bed_file(file_path: &str,species: &str) -> HashMap<String, Vec<BedRecord
             let tname = fields[5].to_string();
 
 
-            let nc_chr = get_chr_nc_for_species(&species, &chr);
+            let nc_chr = get_chr_nc_for_species(species, &chr);
+            if nc_chr != chr {
+                aliases.insert(chr.clone(), nc_chr.clone());
+            }
 
             let record = BedRecord {
                 start,
@@ -167,9 +182,10 @@ fn read_bed_file(file_path: &str,species: &str) -> HashMap<String, Vec<BedRecord
                 strand,
                 name,
                 tname,
+                length: end + 1 - start,
             };
 
-            records_map.entry(nc_chr).or_insert_with(Vec::new).push(record);
+            records_map.entry(nc_chr).or_default().push(record);
         }
     }
 
@@ -177,30 +193,64 @@ fn read_bed_file(file_path: &str,species: &str) -> HashMap<String, Vec<BedRecord
 }
 
 
-fn read_txt(file_path: &str) -> HashMap<String, Vec<Record>> {
+// .len: transcriptid length
+fn read_len(file_path: &str) -> HashMap<String, usize> {
+    let file = File::open(file_path).unwrap();
+    let reader = BufReader::new(file);
+    let mut lengths = HashMap::new();
+    for line in reader.lines() {
+        let line = line.unwrap();
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() >= 2 {
+            if let Ok(len) = fields[1].parse::<usize>() {
+                lengths.insert(fields[0].to_string(), len);
+            }
+        }
+    }
+    lengths
+}
+
+fn read_txt(file_path: &str, fasta: Option<&Fasta>) -> HashMap<String, Vec<Record>> {
     let file = File::open(file_path).unwrap();
     let reader = BufReader::new(file);
 
     let mut records_map: HashMap<String, Vec<Record>> = HashMap::new();
+    // merge --fasta 多出 RefBase,BaseFlag 两列, info 只保留前20列
+    let mut ref_idx = None;
 
-    for (i, line) in reader.lines().enumerate() {
-        let line = line.unwrap();
-        if i == 0 { continue; } 
+    // merge 写在表头前的 #stone 行跳过
+    let lines = reader.lines().map(|l| l.unwrap()).filter(|l| !l.starts_with('#'));
+    for (i, line) in lines.enumerate() {
+        if i == 0 {
+            ref_idx = line.split(',').position(|h| h.trim() == "RefBase");
+            continue;
+        } 
 
         let fields: Vec<&str> = line.split(',').collect();
 
         let chrid = fields[0].to_string();
         let strand = fields[1].to_string();
         let position = fields[2].parse::<usize>().unwrap();
-        let info = fields[3..].join(","); 
+        let info = match ref_idx {
+            Some(r) => fields[3..r.min(fields.len())].join(","),
+            None => fields[3..].join(","),
+        };
+        // RF的碱基是正链, 参考碱基也取正链
+        let ref_base = match ref_idx {
+            Some(r) if fasta.is_none() => fields.get(r).and_then(|b| b.chars().next()).filter(|b| *b != 'N').map(|b| {
+                if strand == "-" { faidx::complement(b) } else { b }
+            }),
+            _ => fasta.and_then(|f| f.base(&chrid, position)).filter(|b| *b != 'N'),
+        };
 
         let rec = Record {
             strand,
             position,
             info,
+            ref_base,
         };
 
-        records_map.entry(chrid).or_insert_with(Vec::new).push(rec);
+        records_map.entry(chrid).or_default().push(rec);
     }
 
     records_map
@@ -215,26 +265,137 @@ struct Cli {
     bedpath: String,
     #[arg(short,long)]
     output: String,
+    /// falls back to [bgsg] species in --config
+    #[arg(short,long)]
+    species: Option<String>,
+    /// samtools-indexed reference, fills missing RF bases and counts disagreements
     #[arg(short,long)]
-    species: String,
+    fasta: Option<String>,
+    /// base_A..base_T of positions whose base cannot be resolved: zero, na or drop
+    #[arg(short,long, default_value = "zero")]
+    unresolved: String,
+    /// write <PROFILES>/<transcriptid>.fa and a sequence-aligned <transcriptid>.map per transcript
+    #[arg(short,long)]
+    profiles: Option<String>,
+    /// signal of the .map profiles: mut or stop
+    #[arg(long, default_value = "mut")]
+    profile_signal: String,
+    /// emit every position of each transcript, positions without data get depth 0 and NA counts
+    #[arg(long)]
+    fill: bool,
+    /// transcript lengths (transcriptid length), default the bed interval
+    #[arg(short,long)]
+    len: Option<String>,
+    #[arg(short,long, default_value_t = 32)]
+    thread: usize,
+    /// stone.toml, [reference] and [bgsg] fill in options not given on the command line
+    #[arg(long)]
+    config: Option<String>,
 }
 
 
 fn main() {
     let start = Instant::now();
-    let cli = Cli::parse();
-    let species = &cli.species;
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let defaulted = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    cli.fasta = cli.fasta.or(config.str("reference", "fasta"));
+    cli.profiles = cli.profiles.or(config.str("bgsg", "profiles"));
+    cli.len = cli.len.or(config.str("bgsg", "len"));
+    cli.fill = cli.fill || config.bool("bgsg", "fill").unwrap_or(false);
+    if defaulted("unresolved") {
+        cli.unresolved = config.str("bgsg", "unresolved").unwrap_or(cli.unresolved);
+    }
+    if defaulted("profile_signal") {
+        cli.profile_signal = config.str("bgsg", "profile_signal").unwrap_or(cli.profile_signal);
+    }
+    if defaulted("thread") {
+        cli.thread = config.usize("bgsg", "thread").unwrap_or(cli.thread);
+    }
+    let species = &cli.species.clone().or(config.str("bgsg", "species")).unwrap_or_else(|| {
+        eprintln!("--species is required (or [bgsg] species in --config)");
+        std::process::exit(1);
+    });
+    if !["zero", "na", "drop"].contains(&cli.unresolved.as_str()) {
+        eprintln!("--unresolved must be zero, na or drop");
+        std::process::exit(1);
+    }
+    if !["mut", "stop"].contains(&cli.profile_signal.as_str()) {
+        eprintln!("--profile-signal must be mut or stop");
+        std::process::exit(1);
+    }
+    if let Some(dir) = &cli.profiles {
+        std::fs::create_dir_all(dir).expect("Unable to create profile directory");
+    }
     println!("load file");
-    let mergeset = Arc::new(read_txt(&cli.mergepath));
-    let bedset = Arc::new(read_bed_file(&cli.bedpath,species));
-    let file = Arc::new(Mutex::new(File::create(cli.output).expect("Unable to create file")));
+    let fasta = cli.fasta.as_ref().map(|f| Fasta::open(f).expect("Unable to open fasta"));
+    let mergeset = Arc::new(read_txt(&cli.mergepath, fasta.as_ref()));
+    // ChrID -> checked, RF mismatch, filled from reference, unresolved
+    let basecheck: Mutex<BTreeMap<String, [usize; 4]>> = Mutex::new(BTreeMap::new());
+    let mut aliases = BTreeMap::new();
+    let mut bedset = read_bed_file(&cli.bedpath,species, &mut aliases);
+    if let Some(len_path) = &cli.len {
+        // bed start and end are 1-based and inclusive, the .len length counts the same positions
+        let lengths = read_len(len_path);
+        let mut differ = 0;
+        let mut one_short = 0;
+        for bed_record in bedset.values_mut().flatten() {
+            let len = lengths.get(&bed_record.tname).or_else(|| lengths.get(get_key_prefix(&bed_record.tname)));
+            if let Some(&len) = len {
+                if len != bed_record.length {
+                    differ += 1;
+                }
+                if len + 1 == bed_record.length {
+                    one_short += 1;
+                }
+                bed_record.length = len;
+            }
+        }
+        if differ > 0 {
+            println!("{} transcripts have a .len length different from their bed interval (end - start + 1)", differ);
+        }
+        if one_short > 0 {
+            println!("{} of them are one position short, as the length of a 0-based half-open interval would be", one_short);
+        }
+    }
+    let bedset = Arc::new(bedset);
+    let file = Arc::new(Mutex::new(File::create(&cli.output).expect("Unable to create file")));
     
     {
         let mut file_guard = file.lock().unwrap();
-        writeln!(file_guard, "ChrID,geneid,transcriptid,position,pipe_truncation_Strand,rf_mutation_Base,rf_mutation_Count,rf_mutation_Depth,rf_mutation_AC,rf_mutation_AG,rf_mutation_AT,rf_mutation_CA,rf_mutation_CG,rf_mutation_CT,rf_mutation_GA,rf_mutation_GC,rf_mutation_GT,rf_mutation_TA,rf_mutation_TC,rf_mutation_TG,rf_mutation_ins,rf_mutation_del,pipe_truncation_Base,pipe_truncation_count,pipe_truncation_BD,base_A,base_C,base_G,base_T,modified_string").expect("Unable to write header");
+        let effective = [
+            ("bgsg.species", species.to_string()),
+            ("reference.fasta", cli.fasta.clone().unwrap_or_else(|| "-".to_string())),
+            ("bgsg.unresolved", cli.unresolved.clone()),
+            ("bgsg.fill", cli.fill.to_string()),
+            ("bgsg.len", cli.len.clone().unwrap_or_else(|| "-".to_string())),
+            ("bgsg.profiles", cli.profiles.clone().unwrap_or_else(|| "-".to_string())),
+            ("bgsg.profile_signal", cli.profile_signal.clone()),
+            ("bgsg.thread", cli.thread.to_string()),
+        ];
+        let mut block = Block::new("bgsg", Cli::command().get_version().unwrap_or("unknown"));
+        block.input(&cli.mergepath).input(&cli.bedpath);
+        if let Some(len_path) = &cli.len {
+            block.input(len_path);
+        }
+        if let Some(f) = &cli.fasta {
+            block.reference(f);
+        }
+        let alias_table: Vec<String> = aliases.iter().map(|(from, to)| format!("{}={}", from, to)).collect();
+        block.set("alias", format!("{}\t{}", species, alias_table.join(",")));
+        block.extend(config::settings(&config, &effective));
+        // history of the merged file, then this run
+        for line in provenance::upstream(&[&cli.mergepath], '#').into_iter().chain(block.lines('#')) {
+            writeln!(file_guard, "{}", line).expect("Unable to write header");
+        }
+        writeln!(file_guard, "ChrID,geneid,transcriptid,position,transcript_position,pipe_truncation_Strand,rf_mutation_Base,rf_mutation_Count,rf_mutation_Depth,rf_mutation_AC,rf_mutation_AG,rf_mutation_AT,rf_mutation_CA,rf_mutation_CG,rf_mutation_CT,rf_mutation_GA,rf_mutation_GC,rf_mutation_GT,rf_mutation_TA,rf_mutation_TC,rf_mutation_TG,rf_mutation_ins,rf_mutation_del,pipe_truncation_Base,pipe_truncation_count,pipe_truncation_BD,base_A,base_C,base_G,base_T,modified_string").expect("Unable to write header");
     }
 
-    let thread_count = 32;
+    let thread_count = cli.thread;
 
     println!("parallel");
     let pool = ThreadPoolBuilder::new().num_threads(thread_count).build().unwrap();
@@ -246,7 +407,12 @@ fn main() {
 
                 if bed_key_prefix == merge_key_prefix {
                     let mut local_result = String::new();
-                    process_records(bed_key_prefix, bed_records, records, &mut local_result);
+                    let counts = process_records(bed_key_prefix, bed_records, records, &cli, fasta.as_ref(), &mut local_result);
+                    let mut check = basecheck.lock().unwrap();
+                    let total = check.entry(bed_key_prefix.to_string()).or_insert([0; 4]);
+                    for (t, c) in total.iter_mut().zip(counts) {
+                        *t += c;
+                    }
 
 
                     let mut file_guard = file.lock().unwrap();
@@ -258,37 +424,223 @@ fn main() {
         });
     });
 
+    let basecheck = basecheck.into_inner().unwrap();
+    if basecheck.values().any(|c| c[0] > 0 || c[3] > 0) {
+        println!("ChrID\tChecked\tRF_Mismatch\tFilled\tUnresolved");
+        for (chr_id, c) in basecheck.iter().filter(|(_, c)| c[0] > 0 || c[3] > 0) {
+            println!("{}\t{}\t{}\t{}\t{}", chr_id, c[0], c[1], c[2], c[3]);
+        }
+    }
+    let unresolved: usize = basecheck.values().map(|c| c[3]).sum();
+    println!("unresolved bases: {} ({})", unresolved, cli.unresolved);
+
     let duration = start.elapsed();
     println!("gene time elapsed: {:?}", duration);
 }
 
 
-fn process_records(bedkeyprefix: &str, bed_records: &Vec<BedRecord>, records: &Vec<Record>, result: &mut String) {
+fn process_records(bedkeyprefix: &str, bed_records: &[BedRecord], records: &[Record], cli: &Cli, fasta: Option<&Fasta>, result: &mut String) -> [usize; 4] {
     let mut rng = rand::thread_rng();
+    let mut counts = [0; 4];
     for bed_record in bed_records {
         let start = bed_record.start;
         let end = bed_record.end;
+        let minus = bed_record.strand == "-";
 
         let mut filtered_records: Vec<&Record> = records.iter()
             .filter(|r| r.strand == bed_record.strand && r.position >= start && r.position <= end)
             .collect();
         let mut seen = HashSet::new();
         filtered_records.retain(|r| seen.insert((&r.strand, r.position)));
-        for i in filtered_records {
+        // 按转录本 5'->3' 输出
+        filtered_records.sort_by_key(|r| r.position);
+        if minus {
+            filtered_records.reverse();
+        }
+
+        let len = bed_record.length;
+        let to_transcript = |p: usize| if minus { end + 1 - p } else { p + 1 - start };
+        // 转录本到 .len 长度为止, 超出的行逐行报告后丢弃
+        filtered_records.retain(|r| {
+            let transcript_position = to_transcript(r.position);
+            if transcript_position > len {
+                eprintln!(
+                    "{}: {} {} (transcript position {}) is past the .len length {}, row dropped",
+                    bed_record.tname, bedkeyprefix, r.position, transcript_position, len
+                );
+            }
+            transcript_position <= len
+        });
+        // (基因组位置, 转录本位置, 记录), --fill 时没有数据的位置记录为空
+        let rows: Vec<(usize, usize, Option<&Record>)> = if cli.fill {
+            let mut slots: Vec<Option<&Record>> = vec![None; len];
+            for r in filtered_records {
+                slots[to_transcript(r.position) - 1] = Some(r);
+            }
+            slots
+                .into_iter()
+                .enumerate()
+                .filter_map(|(k, r)| {
+                    let position = if minus { (end + 1).checked_sub(k + 1)? } else { start + k };
+                    Some((position, k + 1, r))
+                })
+                .collect()
+        } else {
+            filtered_records.into_iter().map(|r| (r.position, to_transcript(r.position), Some(r))).collect()
+        };
+
+        let mut profile: Vec<(char, Option<(f64, f64)>)> = vec![('N', None); if cli.profiles.is_some() { len } else { 0 }];
+        for (position, transcript_position, record) in rows {
             let rnum:u8= rng.gen_range(0..=1);
-            let chars_as_string: String = parse_and_extend_info(&i.info);
-            result.push_str(&format!("{},{},{},{},{},{},{}\n", bedkeyprefix, bed_record.name, bed_record.tname, i.position, bed_record.strand, chars_as_string, rnum));
+            let i = match record {
+                Some(i) => i,
+                None => {
+                    let base = fasta.and_then(|f| f.stranded_base(bedkeyprefix, if minus { '-' } else { '+' }, position)).unwrap_or('N');
+                    result.push_str(&format!("{},{},{},{},{},{},{},NA,0,{},{},NA,0,NA,NA,NA,NA,{}\n", bedkeyprefix, bed_record.name, bed_record.tname, position, transcript_position, bed_record.strand, base, ["NA"; 14].join(","), base, rnum));
+                    continue;
+                }
+            };
+            let (info, genome_base) = if minus {
+                (complement_info(&i.info), i.ref_base.map(faidx::complement))
+            } else {
+                (i.info.clone(), i.ref_base)
+            };
+            if let Some(p) = profile.get_mut(transcript_position - 1) {
+                *p = profile_site(&info, genome_base, &cli.profile_signal);
+            }
+            let chars_as_string: String = match parse_and_extend_info(&info, genome_base, &mut counts) {
+                Some(s) => s,
+                None => match cli.unresolved.as_str() {
+                    "na" => format!("{},NA,NA,NA,NA", info),
+                    "drop" => continue,
+                    _ => format!("{},0,0,0,0", info),
+                },
+            };
+            result.push_str(&format!("{},{},{},{},{},{},{},{}\n", bedkeyprefix, bed_record.name, bed_record.tname, i.position, transcript_position, bed_record.strand, chars_as_string, rnum));
+        }
+        if let Some(dir) = &cli.profiles {
+            write_profile(dir, bedkeyprefix, bed_record, &profile, fasta).expect("Unable to write profile");
+        }
+
+    }
+    counts
+}
+
+const MUTATIONS: [&str; 12] = ["AC", "AG", "AT", "CA", "CG", "CT", "GA", "GC", "GT", "TA", "TC", "TG"];
+
+// RF 的碱基和突变方向是基因组正链, 负链转录本取互补; icSHAPE-pipe 的碱基本来就是转录本方向
+fn complement_info(info: &str) -> String {
+    let mut fields: Vec<String> = info.split(',').map(String::from).collect();
+    if fields.len() < 15 {
+        return info.to_string();
+    }
+    fields[0] = fields[0].chars().map(faidx::complement).collect();
+    let old = fields[3..15].to_vec();
+    for (k, m) in MUTATIONS.iter().enumerate() {
+        let src: String = m.chars().map(faidx::complement).collect();
+        fields[3 + k] = old[MUTATIONS.iter().position(|x| *x == src).unwrap()].clone();
+    }
+    fields.join(",")
+}
+
+// 转录本方向的碱基和 (信号, 泊松误差)
+fn profile_site(info: &str, genome_base: Option<char>, signal: &str) -> (char, Option<(f64, f64)>) {
+    let fields: Vec<&str> = info.split(',').collect();
+    let (count, depth) = if signal == "stop" { (18, 19) } else { (1, 2) };
+    let num = |i: usize| fields.get(i).and_then(|v| v.parse::<f64>().ok());
+    let value = match (num(count), num(depth)) {
+        (Some(c), Some(d)) if d > 0.0 => Some((c / d, c.sqrt() / d)),
+        _ => None,
+    };
+    let base = fields
+        .first()
+        .and_then(|b| b.chars().next())
+        .and_then(|b| resolve_base(b, genome_base))
+        .or_else(|| fields.get(17).and_then(|b| b.chars().next()).and_then(|b| resolve_base(b, None)))
+        .unwrap_or('N');
+    (base, value)
+}
+
+// 每个转录本一个与序列逐位对应的 fasta 和 .map (position, value, stderr, base; 无数据为 -999)
+fn write_profile(dir: &str, chr: &str, bed_record: &BedRecord, profile: &[(char, Option<(f64, f64)>)], fasta: Option<&Fasta>) -> std::io::Result<()> {
+    let minus = bed_record.strand == "-";
+    let seq: String = profile
+        .iter()
+        .enumerate()
+        .map(|(k, (base, _))| {
+            let position = if minus { bed_record.end - k } else { bed_record.start + k };
+            fasta
+                .and_then(|f| f.stranded_base(chr, if minus { '-' } else { '+' }, position))
+                .unwrap_or(*base)
+        })
+        .collect();
+    let mut fa = BufWriter::new(File::create(format!("{}/{}.fa", dir, bed_record.tname))?);
+    writeln!(fa, ">{} {}:{}-{}({})", bed_record.tname, chr, bed_record.start, bed_record.end, bed_record.strand)?;
+    for chunk in seq.as_bytes().chunks(60) {
+        writeln!(fa, "{}", str::from_utf8(chunk).unwrap())?;
+    }
+    fa.flush()?;
+
+    let mut map = BufWriter::new(File::create(format!("{}/{}.map", dir, bed_record.tname))?);
+    for (k, (b, (_, value))) in seq.chars().zip(profile).enumerate() {
+        match value {
+            Some((v, e)) => writeln!(map, "{}\t{:.6}\t{:.6}\t{}", k + 1, v, e, b)?,
+            None => writeln!(map, "{}\t-999\t0\t{}", k + 1, b)?,
         }
-        
+    }
+    map.flush()
+}
+
+// IUPAC 简并碱基可能代表的碱基
+fn iupac_bases(code: char) -> &'static str {
+    match code {
+        'R' => "AG",
+        'Y' => "CT",
+        'S' => "CG",
+        'W' => "AT",
+        'K' => "GT",
+        'M' => "AC",
+        'B' => "CGT",
+        'D' => "AGT",
+        'H' => "ACT",
+        'V' => "ACG",
+        'N' => "ACGT",
+        _ => "",
+    }
+}
 
+// U 按 T 处理, 简并碱基和 N 只有参考基因组给出一个相容的碱基时才能确定
+fn resolve_base(base: char, genome_base: Option<char>) -> Option<char> {
+    match base.to_ascii_uppercase() {
+        b @ ('A' | 'C' | 'G' | 'T') => Some(b),
+        'U' => Some('T'),
+        b => genome_base
+            .map(|g| if g == 'U' { 'T' } else { g.to_ascii_uppercase() })
+            .filter(|g| iupac_bases(b).contains(*g)),
     }
 }
 
-fn parse_and_extend_info(info: &str) -> String {
+// counts: checked, RF mismatch, filled from reference, unresolved; None when the base cannot be resolved
+fn parse_and_extend_info(info: &str, genome_base: Option<char>, counts: &mut [usize; 4]) -> Option<String> {
     let fields: Vec<&str> = info.split(',').collect();
 
-    // 提取ref碱基和测序深度及突变数
-    let ref_base:char = fields[0].chars().next().unwrap();
+    // 提取ref碱基和测序深度及突变数, RF没有碱基时用参考基因组的碱基
+    let rf_base = fields[0].chars().next().unwrap_or('N');
+    let ref_base = match resolve_base(rf_base, genome_base) {
+        Some(b) => b,
+        None => {
+            counts[3] += 1;
+            return None;
+        }
+    };
+    if let Some(g) = genome_base.and_then(|g| resolve_base(g, None)) {
+        counts[0] += 1;
+        if resolve_base(rf_base, None).is_none() {
+            counts[2] += 1;
+        } else if ref_base != g {
+            counts[1] += 1;
+        }
+    }
     let mutation_count: usize = fields[1].parse().unwrap_or(0);
     let total_depth: usize = fields[2].parse().unwrap_or(0);
 
@@ -300,10 +652,10 @@ fn parse_and_extend_info(info: &str) -> String {
     let mut t_count = 0;
 
 
-    let a_indices = vec![3, 4, 5]; 
-    let c_indices = vec![6, 7, 8]; 
-    let g_indices = vec![9, 10, 11]; 
-    let t_indices = vec![12, 13, 14]; 
+    let a_indices = [3, 4, 5]; 
+    let c_indices = [6, 7, 8]; 
+    let g_indices = [9, 10, 11]; 
+    let t_indices = [12, 13, 14]; 
 
     match ref_base {
         'A' => {
@@ -343,6 +695,6 @@ fn parse_and_extend_info(info: &str) -> String {
         t_count
     );
 
-    extended_info
+    Some(extended_info)
 }
 
@@ -0,0 +1,156 @@
+#![allow(dead_code)]
+// stone.toml shared by the genome workflow tools. Every key is listed in SCHEMA; a value given on
+// the command line wins over the file, the file over the built-in default.
+use std::fs;
+use std::io;
+
+pub enum Kind {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Choice(&'static [&'static str]),
+}
+
+pub const SCHEMA: &[(&str, &str, Kind)] = &[
+    ("reference", "fasta", Kind::Str),
+    ("zip", "thread", Kind::Int),
+    ("zip", "strand", Kind::Choice(&["+", "-"])),
+    ("bgsg", "species", Kind::Str),
+    ("bgsg", "thread", Kind::Int),
+    ("bgsg", "unresolved", Kind::Choice(&["zero", "na", "drop"])),
+    ("bgsg", "fill", Kind::Bool),
+    ("bgsg", "len", Kind::Str),
+    ("bgsg", "profiles", Kind::Str),
+    ("bgsg", "profile_signal", Kind::Choice(&["mut", "stop"])),
+    ("mbreport", "depth", Kind::Int),
+    ("mbreport", "max_ratio", Kind::Float),
+    ("model_input", "len", Kind::Str),
+    ("model_input", "strand", Kind::Choice(&["+", "-"])),
+    ("model_input", "threads", Kind::Int),
+    ("model_input", "suffix", Kind::Str),
+    ("run", "threads", Kind::Int),
+    ("run", "sample_threads", Kind::Int),
+    ("run", "bin_dir", Kind::Str),
+    ("run", "bed", Kind::Str),
+    ("serve", "host", Kind::Str),
+    ("serve", "port", Kind::Int),
+    ("serve", "threads", Kind::Int),
+    ("serve", "max_rows", Kind::Int),
+    ("model", "depth_threshold", Kind::Int),
+    ("model", "rf_mutation_Count", Kind::Int),
+    ("model", "pipe_truncation_count", Kind::Int),
+    ("model", "rate_mut", Kind::Float),
+    ("model", "rate_stop", Kind::Float),
+    ("model", "acc_threshold", Kind::Float),
+    ("model", "filter_acc", Kind::Bool),
+];
+
+#[derive(Default)]
+pub struct Config {
+    pub path: Option<String>,
+    table: toml::Table,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn check(path: &str, section: &str, key: &str, value: &toml::Value) -> io::Result<()> {
+    let kind = SCHEMA
+        .iter()
+        .find(|(s, k, _)| *s == section && *k == key)
+        .map(|(_, _, kind)| kind)
+        .ok_or_else(|| {
+            let known: Vec<&str> = SCHEMA.iter().filter(|(s, _, _)| *s == section).map(|(_, k, _)| *k).collect();
+            invalid(format!("{}: unknown key {}.{} (known: {})", path, section, key, known.join(", ")))
+        })?;
+    let ok = match kind {
+        Kind::Int => value.as_integer().map(|i| i >= 0).unwrap_or(false),
+        Kind::Float => value.as_float().is_some() || value.as_integer().is_some(),
+        Kind::Bool => value.as_bool().is_some(),
+        Kind::Str => value.as_str().is_some(),
+        Kind::Choice(choices) => value.as_str().map(|v| choices.contains(&v)).unwrap_or(false),
+    };
+    if ok {
+        return Ok(());
+    }
+    let expected = match kind {
+        Kind::Int => "a non-negative integer".to_string(),
+        Kind::Float => "a number".to_string(),
+        Kind::Bool => "true or false".to_string(),
+        Kind::Str => "a string".to_string(),
+        Kind::Choice(choices) => format!("one of {}", choices.join(", ")),
+    };
+    Err(invalid(format!("{}: {}.{} = {} should be {}", path, section, key, value, expected)))
+}
+
+fn to_string(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+impl Config {
+    /// Reads and validates a config file, no file gives an empty config.
+    pub fn load(path: Option<&str>) -> io::Result<Config> {
+        let path = match path {
+            Some(p) => p,
+            None => return Ok(Config::default()),
+        };
+        let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        let table: toml::Table = text.parse().map_err(|e| invalid(format!("{}: {}", path, e)))?;
+        for (section, body) in &table {
+            let body = body
+                .as_table()
+                .ok_or_else(|| invalid(format!("{}: {} should be a [section]", path, section)))?;
+            if !SCHEMA.iter().any(|(s, _, _)| s == section) {
+                return Err(invalid(format!("{}: unknown section [{}]", path, section)));
+            }
+            for (key, value) in body {
+                check(path, section, key, value)?;
+            }
+        }
+        Ok(Config { path: Some(path.to_string()), table })
+    }
+
+    fn value(&self, section: &str, key: &str) -> Option<&toml::Value> {
+        self.table.get(section)?.get(key)
+    }
+
+    pub fn str(&self, section: &str, key: &str) -> Option<String> {
+        self.value(section, key).and_then(|v| v.as_str()).map(String::from)
+    }
+
+    pub fn usize(&self, section: &str, key: &str) -> Option<usize> {
+        self.value(section, key).and_then(|v| v.as_integer()).map(|i| i as usize)
+    }
+
+    pub fn float(&self, section: &str, key: &str) -> Option<f64> {
+        self.value(section, key).and_then(|v| v.as_float().or_else(|| v.as_integer().map(|i| i as f64)))
+    }
+
+    pub fn bool(&self, section: &str, key: &str) -> Option<bool> {
+        self.value(section, key).and_then(|v| v.as_bool())
+    }
+
+    /// "section.key=value" for every key set in a section, sorted.
+    pub fn section(&self, section: &str) -> Vec<String> {
+        let mut out: Vec<String> = match self.table.get(section).and_then(|s| s.as_table()) {
+            Some(t) => t.iter().map(|(k, v)| format!("{}.{}={}", section, k, to_string(v))).collect(),
+            None => Vec::new(),
+        };
+        out.sort();
+        out
+    }
+}
+
+/// Provenance entries of the config file and the effective "section.key" settings of a tool.
+pub fn settings(config: &Config, effective: &[(&str, String)]) -> Vec<(String, String)> {
+    let mut entries = vec![("config".to_string(), config.path.clone().unwrap_or_else(|| "-".to_string()))];
+    for (key, value) in effective {
+        entries.push(("setting".to_string(), format!("{}={}", key, value)));
+    }
+    entries
+}
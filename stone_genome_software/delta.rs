@@ -0,0 +1,473 @@
+mod provenance;
+
+use clap::{CommandFactory, Parser};
+use provenance::Block;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name="delta", author="hyf", version="1.0", about="deltaSHAPE differential reactivity between two conditions, genome wide", long_about = None)]
+struct Cli {
+    /// bgsg or scored output of the first condition
+    #[arg(long)]
+    data1: String,
+    /// bgsg or scored output of the second condition, subtracted from data1
+    #[arg(long)]
+    data2: String,
+    /// reactivity column, or mut/stop to use the bgsg count rates
+    #[arg(short,long, default_value = "norm_model")]
+    column: String,
+    /// standard error column, defaults to the Poisson error for mut/stop and 0 otherwise
+    #[arg(short,long)]
+    errcol: Option<String>,
+    /// BED file of called sites
+    #[arg(short,long)]
+    out: String,
+    /// per nucleotide table like deltaSHAPE's differences.txt
+    #[arg(long)]
+    table: Option<String>,
+    #[arg(long, default_value_t = 0)]
+    mask5: usize,
+    #[arg(long, default_value_t = 0)]
+    mask3: usize,
+    #[arg(short,long, default_value_t = 1)]
+    pad: usize,
+    #[arg(short = 'z', long = "Zcoeff", default_value_t = 1.96)]
+    zcoeff: f64,
+    #[arg(short = 't', long = "Zthresh", default_value_t = 0.0, allow_negative_numbers = true)]
+    zthresh: f64,
+    #[arg(short = 's', long = "SSthresh", default_value_t = 1.0)]
+    ssthresh: f64,
+    #[arg(short = 'f', long = "FindSite", default_value = "2,3")]
+    findsite: String,
+    /// sort the table by decreasing deltaSHAPE magnitude
+    #[arg(long)]
+    magrank: bool,
+    /// write every nucleotide to the table, insignificant changes as zero
+    #[arg(long)]
+    all: bool,
+    #[arg(long, default_value_t = 8)]
+    thread: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Site {
+    chr_id: String,
+    strand: char,
+    position: usize,
+    base: char,
+    value: f64,
+    err: f64,
+}
+
+// one transcript laid out 5'->3', gaps and masked positions are NaN
+#[derive(Debug)]
+struct Profile {
+    chr_id: String,
+    strand: char,
+    positions: Vec<usize>,
+    seq: Vec<char>,
+    data: Vec<f64>,
+    errs: Vec<f64>,
+}
+
+#[derive(Debug)]
+struct Row {
+    nuc: usize,
+    position: usize,
+    base: char,
+    delta: f64,
+    z_factor: f64,
+    z_score: f64,
+    s_data1: f64,
+    s_data2: f64,
+    diff: f64,
+    data1: f64,
+    data2: f64,
+}
+
+#[derive(Debug)]
+struct DeltaResult {
+    transcript: String,
+    chr_id: String,
+    strand: char,
+    rows: Vec<Row>,
+    // (first nuc index, last nuc index, mean deltaSHAPE)
+    sites: Vec<(usize, usize, f64)>,
+}
+
+fn parse_value(s: Option<&&str>) -> f64 {
+    match s {
+        Some(v) => v.trim().parse::<f64>().ok().filter(|x| *x != -999.0).unwrap_or(f64::NAN),
+        None => f64::NAN,
+    }
+}
+
+fn read_sites(file_path: &str, column: &str, errcol: &Option<String>) -> io::Result<HashMap<String, Vec<Site>>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut lines = reader.lines();
+    let header = match provenance::csv_header(&mut lines)? {
+        Some(h) => h,
+        None => return Ok(HashMap::new()),
+    };
+    let idx = |name: &str| header.iter().position(|h| h == name);
+    let first = |names: &[&str]| names.iter().find_map(|n| idx(n));
+
+    let missing = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no {} column", file_path, what));
+    let chr_idx = idx("ChrID").ok_or_else(|| missing("ChrID"))?;
+    // bgsg output is keyed by transcript, scored files by ChrID
+    let key_idx = idx("transcriptid").unwrap_or(chr_idx);
+    let strand_idx = first(&["pipe_truncation_Strand", "Strand"]);
+    let pos_idx = first(&["position", "pipe_truncation_ChrPos", "Position"]).ok_or_else(|| missing("position"))?;
+    let base_idx = first(&["rf_mutation_Base", "pipe_truncation_Base", "Base1"]);
+
+    // (count, depth) pairs for the rate signals
+    let rate_idx = match column {
+        "mut" => Some((idx("rf_mutation_Count").ok_or_else(|| missing("rf_mutation_Count"))?,
+                       idx("rf_mutation_Depth").ok_or_else(|| missing("rf_mutation_Depth"))?)),
+        "stop" => Some((idx("pipe_truncation_count").ok_or_else(|| missing("pipe_truncation_count"))?,
+                        idx("pipe_truncation_BD").ok_or_else(|| missing("pipe_truncation_BD"))?)),
+        _ => None,
+    };
+    let value_idx = match rate_idx {
+        Some(_) => None,
+        None => Some(idx(column).ok_or_else(|| missing(column))?),
+    };
+    let err_idx = match errcol {
+        Some(e) => Some(idx(e).ok_or_else(|| missing(e))?),
+        None => None,
+    };
+
+    let mut map: HashMap<String, Vec<Site>> = HashMap::new();
+    let mut truncated = 0;
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts = provenance::fields(&line);
+        let (name, chr_id) = match (parts.get(key_idx), parts.get(chr_idx)) {
+            (Some(name), Some(chr_id)) => (name, chr_id),
+            _ => {
+                truncated += 1;
+                continue;
+            }
+        };
+        let position: usize = match parts.get(pos_idx).and_then(|p| p.parse::<f64>().ok()) {
+            Some(p) => p as usize,
+            None => continue,
+        };
+        let strand = strand_idx.and_then(|i| parts.get(i)).and_then(|s| s.chars().next()).unwrap_or('+');
+        let base = base_idx.and_then(|i| parts.get(i)).and_then(|s| s.chars().next()).unwrap_or('N');
+
+        let (value, mut err) = match rate_idx {
+            Some((ci, di)) => {
+                let count = parse_value(parts.get(ci));
+                let depth = parse_value(parts.get(di));
+                if depth > 0.0 && !count.is_nan() {
+                    (count / depth, count.sqrt() / depth)
+                } else {
+                    (f64::NAN, f64::NAN)
+                }
+            }
+            None => (parse_value(parts.get(value_idx.unwrap())), 0.0),
+        };
+        if let Some(ei) = err_idx {
+            err = parse_value(parts.get(ei));
+        }
+
+        let key = format!("{}({})", name, strand);
+        map.entry(key).or_default().push(Site {
+            chr_id: chr_id.to_string(),
+            strand,
+            position,
+            base,
+            value,
+            err,
+        });
+    }
+    if truncated > 0 {
+        eprintln!("{}: {} rows without the transcript or ChrID field skipped", file_path, truncated);
+    }
+    Ok(map)
+}
+
+fn build_profile(sites1: &[Site], sites2: &[Site], front: usize, back: usize) -> (Profile, Vec<f64>, Vec<f64>) {
+    let first = sites1.first().or(sites2.first()).unwrap();
+    let strand = first.strand;
+    let lo = sites1.iter().chain(sites2).map(|s| s.position).min().unwrap();
+    let hi = sites1.iter().chain(sites2).map(|s| s.position).max().unwrap();
+    let len = hi - lo + 1;
+
+    let mut seq = vec!['N'; len];
+    let mut data1 = vec![f64::NAN; len];
+    let mut errs1 = vec![f64::NAN; len];
+    let mut data2 = vec![f64::NAN; len];
+    let mut errs2 = vec![f64::NAN; len];
+    for s in sites1 {
+        data1[s.position - lo] = s.value;
+        errs1[s.position - lo] = s.err;
+        seq[s.position - lo] = s.base;
+    }
+    for s in sites2 {
+        data2[s.position - lo] = s.value;
+        errs2[s.position - lo] = s.err;
+        if seq[s.position - lo] == 'N' {
+            seq[s.position - lo] = s.base;
+        }
+    }
+    let mut positions: Vec<usize> = (lo..=hi).collect();
+    if strand == '-' {
+        for v in [&mut data1, &mut errs1, &mut data2, &mut errs2] {
+            v.reverse();
+        }
+        seq.reverse();
+        positions.reverse();
+    }
+    // the errors too, or the smoothed error of the positions next to the ends keeps the masked ones
+    for v in [&mut data1, &mut errs1, &mut data2, &mut errs2] {
+        mask_ends(v, front, back);
+    }
+
+    let profile = Profile {
+        chr_id: first.chr_id.clone(),
+        strand,
+        positions,
+        seq,
+        data: data1,
+        errs: errs1,
+    };
+    (profile, data2, errs2)
+}
+
+// deltaSHAPE masks primer ends with -999, which is NaN from here on
+fn mask_ends(data: &mut [f64], front: usize, back: usize) {
+    let len = data.len();
+    for v in data.iter_mut().take(front.min(len)) {
+        *v = f64::NAN;
+    }
+    for v in data.iter_mut().skip(len.saturating_sub(back)) {
+        *v = f64::NAN;
+    }
+}
+
+fn smooth(data: &[f64], err: &[f64], pad: usize) -> (Vec<f64>, Vec<f64>) {
+    let len = data.len();
+    let mut new_data = vec![f64::NAN; len];
+    let mut new_err = vec![f64::NAN; len];
+    if len > 2 * pad {
+        for i in pad..len - pad {
+            let window = &data[i - pad..=i + pad];
+            let valid: Vec<f64> = window.iter().cloned().filter(|x| !x.is_nan()).collect();
+            if !valid.is_empty() {
+                new_data[i] = valid.iter().sum::<f64>() / valid.len() as f64;
+            }
+            let total: f64 = err[i - pad..=i + pad].iter().filter(|x| !x.is_nan()).map(|x| x * x).sum();
+            new_err[i] = total.sqrt() / window.len() as f64;
+        }
+    }
+    for i in 0..len {
+        if data[i].is_nan() {
+            new_data[i] = f64::NAN;
+            new_err[i] = f64::NAN;
+        }
+    }
+    (new_data, new_err)
+}
+
+fn z_factor(data1: &[f64], data2: &[f64], err1: &[f64], err2: &[f64], factor: f64) -> Vec<f64> {
+    (0..data1.len())
+        .map(|i| {
+            if data1[i].is_nan() || data2[i].is_nan() {
+                return f64::NAN;
+            }
+            let top = factor * (err2[i] + err1[i]);
+            let bot = (data2[i] - data1[i]).abs();
+            if bot == 0.0 { f64::NAN } else { 1.0 - top / bot }
+        })
+        .collect()
+}
+
+fn calc_z_scores(diffs: &[f64]) -> Vec<f64> {
+    let valid: Vec<f64> = diffs.iter().cloned().filter(|x| !x.is_nan()).collect();
+    if valid.is_empty() {
+        return vec![f64::NAN; diffs.len()];
+    }
+    let mean = valid.iter().sum::<f64>() / valid.len() as f64;
+    let sigma = (valid.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / valid.len() as f64).sqrt();
+    diffs.iter().map(|x| (x - mean) / sigma).collect()
+}
+
+fn analyse(transcript: &str, sites1: &[Site], sites2: &[Site], cli: &Cli, site_pad: usize, site_min: usize) -> DeltaResult {
+    let (profile, data2, errs2) = build_profile(sites1, sites2, cli.mask5, cli.mask3);
+    let data1 = &profile.data;
+    let errs1 = &profile.errs;
+    let len = data1.len();
+
+    let (s_data1, s_err1) = smooth(data1, errs1, cli.pad);
+    let (s_data2, s_err2) = smooth(&data2, &errs2, cli.pad);
+    let diff: Vec<f64> = (0..len).map(|i| data1[i] - data2[i]).collect();
+    let s_diff = smooth(&diff, errs1, cli.pad).0;
+    let z_factors = z_factor(&s_data1, &s_data2, &s_err1, &s_err2, cli.zcoeff);
+    let z_scores = calc_z_scores(&s_diff);
+
+    // windows of 2*site_pad+1 nucleotides need site_min passing nucleotides
+    let mut sig = vec![false; len];
+    if len > 2 * site_pad {
+        for i in site_pad..len - site_pad {
+            let maybes: Vec<usize> = (i - site_pad..=i + site_pad)
+                .filter(|&j| z_factors[j] > cli.zthresh && z_scores[j].abs() >= cli.ssthresh)
+                .collect();
+            if maybes.len() >= site_min {
+                for k in maybes {
+                    sig[k] = true;
+                }
+            }
+        }
+    }
+
+    // consecutive significant nucleotides with the same sign form one site
+    let mut sites = Vec::new();
+    let mut i = 0;
+    while i < len {
+        if !sig[i] {
+            i += 1;
+            continue;
+        }
+        let positive = s_diff[i] >= 0.0;
+        let mut j = i;
+        while j + 1 < len && sig[j + 1] && (s_diff[j + 1] >= 0.0) == positive {
+            j += 1;
+        }
+        let mean = s_diff[i..=j].iter().sum::<f64>() / (j - i + 1) as f64;
+        sites.push((i, j, mean));
+        i = j + 1;
+    }
+
+    let rows = (0..len)
+        .filter(|&i| sig[i] || cli.all)
+        .map(|i| Row {
+            nuc: i + 1,
+            position: profile.positions[i],
+            base: profile.seq[i],
+            delta: if sig[i] { s_diff[i] } else { 0.0 },
+            z_factor: z_factors[i],
+            z_score: z_scores[i],
+            s_data1: s_data1[i],
+            s_data2: s_data2[i],
+            diff: diff[i],
+            data1: data1[i],
+            data2: data2[i],
+        })
+        .collect();
+
+    DeltaResult {
+        transcript: transcript.to_string(),
+        chr_id: profile.chr_id.clone(),
+        strand: profile.strand,
+        rows,
+        sites: sites.into_iter().map(|(a, b, m)| {
+            let (x, y) = (profile.positions[a], profile.positions[b]);
+            (x.min(y), x.max(y), m)
+        }).collect(),
+    }
+}
+
+fn fmt(v: f64) -> String {
+    if v.is_nan() { "-999".to_string() } else { v.to_string() }
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let cli = Cli::parse();
+
+    if cli.zthresh > 1.0 {
+        eprintln!("ERROR: Z-factor can never exceed 1. Change -t/--Zthresh value accordingly.");
+        std::process::exit(1);
+    }
+    let findsite: Vec<usize> = cli.findsite.split(',').filter_map(|s| s.trim().parse().ok()).collect();
+    if findsite.len() != 2 || findsite[0] * 2 + 1 < findsite[1] {
+        eprintln!("ERROR: Binding site window size and hit minimum are incompatible.\nDouble-check the -f --FindSite flag.");
+        std::process::exit(1);
+    }
+    let (site_pad, site_min) = (findsite[0], findsite[1]);
+
+    println!("load file");
+    let (set1, set2) = rayon::join(
+        || read_sites(&cli.data1, &cli.column, &cli.errcol),
+        || read_sites(&cli.data2, &cli.column, &cli.errcol),
+    );
+    let set1 = set1?;
+    let set2 = set2?;
+
+    let mut keys: Vec<&String> = set1.keys().filter(|k| set2.contains_key(*k)).collect();
+    keys.sort();
+    println!("{} transcripts in both conditions", keys.len());
+
+    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
+    let results: Vec<DeltaResult> = pool.install(|| {
+        keys.par_iter()
+            .map(|k| {
+                let transcript = k.rsplit_once('(').map(|x| x.0).unwrap_or(k);
+                analyse(transcript, &set1[*k], &set2[*k], &cli, site_pad, site_min)
+            })
+            .collect()
+    });
+
+    let mut block = Block::new("delta", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&cli.data1).input(&cli.data2);
+    block.set("column", cli.column.clone());
+    if let Some(e) = &cli.errcol {
+        block.set("errcol", e.clone());
+    }
+    block.set(
+        "thresholds",
+        format!("mask5={},mask3={},pad={},Zcoeff={},Zthresh={},SSthresh={},FindSite={}", cli.mask5, cli.mask3, cli.pad, cli.zcoeff, cli.zthresh, cli.ssthresh, cli.findsite),
+    );
+    let comments: Vec<String> = provenance::upstream(&[&cli.data1, &cli.data2], '#').into_iter().chain(block.lines('#')).collect();
+
+    let mut bed = BufWriter::new(File::create(&cli.out)?);
+    for line in &comments {
+        writeln!(bed, "{}", line)?;
+    }
+    let mut nsite = 0;
+    for r in &results {
+        for (k, (start, end, mean)) in r.sites.iter().enumerate() {
+            // BED is 0-based half open
+            writeln!(bed, "{}\t{}\t{}\t{}_site{}\t{:.6}\t{}", r.chr_id, start - 1, end, r.transcript, k + 1, mean, r.strand)?;
+            nsite += 1;
+        }
+    }
+    bed.flush()?;
+
+    if let Some(table) = &cli.table {
+        let mut writer = BufWriter::new(File::create(table)?);
+        for line in &comments {
+            writeln!(writer, "{}", line)?;
+        }
+        writeln!(writer, "Transcript\tStrand\tNuc\tPosition\tSeq\tDeltaSHAPE\tZ-factor\tStd_Score\tSmoothed_Data1\tSmoothed_Data2\tUnsmoothed_Diff\tData1\tData2")?;
+        for r in results {
+            let mut rows = r.rows;
+            if cli.magrank {
+                rows.sort_by(|a, b| b.delta.abs().partial_cmp(&a.delta.abs()).unwrap_or(std::cmp::Ordering::Equal));
+            }
+            for row in rows {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    r.transcript, r.strand, row.nuc, row.position, row.base, fmt(row.delta), fmt(row.z_factor), fmt(row.z_score),
+                    fmt(row.s_data1), fmt(row.s_data2), fmt(row.diff), fmt(row.data1), fmt(row.data2)
+                )?;
+            }
+        }
+        writer.flush()?;
+    }
+
+    println!("{} sites called", nsite);
+    println!("Total runtime {:?}", now.elapsed());
+    Ok(())
+}
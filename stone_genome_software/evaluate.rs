@@ -0,0 +1,413 @@
+mod provenance;
+
+use clap::{CommandFactory, Parser};
+use provenance::Block;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name="evaluate", author="hyf", version="1.0", about="ROC/PR evaluation of score columns against a dot-bracket or CT reference", long_about = None)]
+struct Cli {
+    /// score csv files, one per dataset (comma separated or repeated)
+    #[arg(short,long, num_args = 1.., value_delimiter = ',', required = true)]
+    scores: Vec<String>,
+    /// reference structure, .dot (>name / sequence / structure) or .ct
+    #[arg(short,long)]
+    reference: String,
+    #[arg(short,long)]
+    output: String,
+    /// optional csv with the ROC curve points of every transcript and column
+    #[arg(long)]
+    roc: Option<String>,
+    /// only evaluate these reference bases, e.g. AC for DMS
+    #[arg(short,long)]
+    bases: Option<String>,
+    /// score columns to evaluate, default is every numeric column
+    #[arg(short,long, value_delimiter = ',')]
+    columns: Vec<String>,
+    #[arg(long, default_value = "ChrID")]
+    idcol: String,
+    /// position column, default tries transcript_position, pipe_truncation_ChrPos, ChrPos and position
+    #[arg(long)]
+    poscol: Option<String>,
+    #[arg(short,long, default_value_t = 4)]
+    thread: usize,
+}
+
+// columns that are never treated as scores even if they are numeric
+const NOT_SCORE: [&str; 9] = [
+    "ChrID", "geneid", "transcriptid", "position", "ChrPos",
+    "pipe_truncation_ChrPos", "pipe_truncation_Strand", "modified_string", "transcript_position",
+];
+const POS_COLS: [&str; 4] = ["transcript_position", "pipe_truncation_ChrPos", "ChrPos", "position"];
+
+#[derive(Debug)]
+struct Reference {
+    sequence: Vec<u8>,
+    // 1 unpaired, 0 paired, same convention as modified_string
+    unpaired: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Evaluation {
+    dataset: String,
+    transcript: String,
+    column: String,
+    n: usize,
+    positives: usize,
+    auc: f64,
+    pr_auc: f64,
+    roc: Vec<(f64, f64, f64)>,
+}
+
+fn read_dot(file_path: &str) -> io::Result<HashMap<String, Reference>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut refs = HashMap::new();
+    let mut name: Option<String> = None;
+    let mut lines: Vec<String> = Vec::new();
+
+    fn flush(name: &Option<String>, lines: &mut Vec<String>, refs: &mut HashMap<String, Reference>) {
+        if let Some(name) = name {
+            // sequence and structure may be wrapped, split them by the alphabet used
+            let mut seq = String::new();
+            let mut db = String::new();
+            for l in lines.iter() {
+                if l.chars().any(|c| c.is_ascii_alphabetic()) {
+                    seq.push_str(l);
+                } else {
+                    db.push_str(l);
+                }
+            }
+            // RNAfold style energy after the structure
+            let db = db.split_whitespace().next().unwrap_or("").to_string();
+            if !seq.is_empty() && seq.len() == db.len() {
+                refs.insert(name.clone(), Reference {
+                    sequence: seq.into_bytes(),
+                    unpaired: db.bytes().map(|c| if c == b'.' || c == b':' || c == b',' || c == b'_' || c == b'-' { 1 } else { 0 }).collect(),
+                });
+            } else {
+                eprintln!("skip reference {}: sequence length {} structure length {}", name, seq.len(), db.len());
+            }
+        }
+        lines.clear();
+    }
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            flush(&name, &mut lines, &mut refs);
+            name = Some(header.split_whitespace().next().unwrap_or("").to_string());
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    flush(&name, &mut lines, &mut refs);
+    Ok(refs)
+}
+
+fn read_ct(file_path: &str) -> io::Result<HashMap<String, Reference>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut refs = HashMap::new();
+    let mut name = String::new();
+    let mut remain = 0usize;
+    let mut seq = Vec::new();
+    let mut unpaired = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if remain == 0 {
+            // header: length, then the title (energy lines like "ENERGY = -1.0 name" included)
+            remain = fields[0].parse().unwrap_or(0);
+            name = fields.last().unwrap_or(&"").to_string();
+            seq.clear();
+            unpaired.clear();
+            continue;
+        }
+        if fields.len() < 6 {
+            continue;
+        }
+        seq.push(fields[1].as_bytes()[0]);
+        unpaired.push(if fields[4] == "0" { 1 } else { 0 });
+        remain -= 1;
+        if remain == 0 {
+            refs.insert(name.clone(), Reference { sequence: seq.clone(), unpaired: unpaired.clone() });
+        }
+    }
+    Ok(refs)
+}
+
+fn read_reference(file_path: &str) -> io::Result<HashMap<String, Reference>> {
+    let lower = file_path.to_lowercase();
+    if lower.ends_with(".ct") {
+        read_ct(file_path)
+    } else {
+        read_dot(file_path)
+    }
+}
+
+// rank based AUC (Mann-Whitney U with averaged ties), same value as sklearn roc_auc_score
+fn roc_auc(pairs: &[(f64, u8)]) -> f64 {
+    let mut sorted: Vec<(f64, u8)> = pairs.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let pos = sorted.iter().filter(|p| p.1 == 1).count() as f64;
+    let neg = sorted.len() as f64 - pos;
+    if pos == 0.0 || neg == 0.0 {
+        return f64::NAN;
+    }
+    let mut rank_sum = 0.0;
+    let mut i = 0;
+    while i < sorted.len() {
+        let mut j = i;
+        while j + 1 < sorted.len() && sorted[j + 1].0 == sorted[i].0 {
+            j += 1;
+        }
+        let avg_rank = (i + j) as f64 / 2.0 + 1.0;
+        rank_sum += avg_rank * sorted[i..=j].iter().filter(|p| p.1 == 1).count() as f64;
+        i = j + 1;
+    }
+    (rank_sum - pos * (pos + 1.0) / 2.0) / (pos * neg)
+}
+
+// ROC points (threshold, fpr, tpr) at every distinct score, plus average precision as PR-AUC
+fn roc_pr(pairs: &[(f64, u8)]) -> (Vec<(f64, f64, f64)>, f64) {
+    let mut sorted: Vec<(f64, u8)> = pairs.to_vec();
+    sorted.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let pos = sorted.iter().filter(|p| p.1 == 1).count() as f64;
+    let neg = sorted.len() as f64 - pos;
+
+    let mut points = vec![(f64::INFINITY, 0.0, 0.0)];
+    let mut tp = 0.0;
+    let mut fp = 0.0;
+    let mut ap = 0.0;
+    let mut last_recall = 0.0;
+    let mut i = 0;
+    while i < sorted.len() {
+        let threshold = sorted[i].0;
+        while i < sorted.len() && sorted[i].0 == threshold {
+            if sorted[i].1 == 1 { tp += 1.0 } else { fp += 1.0 }
+            i += 1;
+        }
+        let fpr = if neg > 0.0 { fp / neg } else { f64::NAN };
+        let tpr = if pos > 0.0 { tp / pos } else { f64::NAN };
+        points.push((threshold, fpr, tpr));
+        if pos > 0.0 {
+            let recall = tp / pos;
+            ap += (recall - last_recall) * tp / (tp + fp);
+            last_recall = recall;
+        }
+    }
+    (points, if pos > 0.0 { ap } else { f64::NAN })
+}
+
+fn evaluate_file(file_path: &str, refs: &HashMap<String, Reference>, cli: &Cli) -> io::Result<Vec<Evaluation>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut lines = reader.lines();
+    let header = match provenance::csv_header(&mut lines)? {
+        Some(h) => h,
+        None => return Ok(Vec::new()),
+    };
+    let col_index = |name: &str| header.iter().position(|h| h == name);
+
+    let pos_idx = match &cli.poscol {
+        Some(p) => col_index(p),
+        None => POS_COLS.iter().find_map(|p| col_index(p)),
+    };
+    let pos_idx = pos_idx.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no position column found"))?;
+    let id_idx = col_index(&cli.idcol);
+    // transcript level files carry no ChrID, they must be matched with a single reference
+    let single_ref = if id_idx.is_none() && refs.len() == 1 { refs.keys().next().cloned() } else { None };
+    if id_idx.is_none() && single_ref.is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("no {} column and more than one reference", cli.idcol)));
+    }
+
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for line in lines {
+        let line = line?;
+        if !line.trim().is_empty() && !line.starts_with('#') {
+            rows.push(provenance::fields(&line).into_iter().map(String::from).collect());
+        }
+    }
+
+    let candidate: Vec<usize> = if cli.columns.is_empty() {
+        (0..header.len())
+            .filter(|&i| i != pos_idx && Some(i) != id_idx && !NOT_SCORE.contains(&header[i].as_str()))
+            .filter(|&i| {
+                let mut seen = false;
+                for r in &rows {
+                    match r.get(i).map(|s| s.as_str()) {
+                        None | Some("") | Some("NA") | Some("NULL") | Some("nan") => {},
+                        Some(v) => {
+                            if v.parse::<f64>().is_err() {
+                                return false;
+                            }
+                            seen = true;
+                        }
+                    }
+                }
+                seen
+            })
+            .collect()
+    } else {
+        cli.columns.iter().filter_map(|c| {
+            let idx = col_index(c);
+            if idx.is_none() {
+                eprintln!("{}: column {} not found", file_path, c);
+            }
+            idx
+        }).collect()
+    };
+
+    let allowed: Option<Vec<u8>> = cli.bases.as_ref().map(|b| {
+        b.to_uppercase().bytes().flat_map(|c| if c == b'T' || c == b'U' { vec![b'T', b'U'] } else { vec![c] }).collect()
+    });
+
+    // transcript -> column -> (score, label)
+    let mut pairs: HashMap<String, Vec<Vec<(f64, u8)>>> = HashMap::new();
+    for r in &rows {
+        let transcript = match id_idx {
+            Some(i) => r.get(i).cloned().unwrap_or_default(),
+            None => single_ref.clone().unwrap(),
+        };
+        let reference = match refs.get(&transcript) {
+            Some(x) => x,
+            None => continue,
+        };
+        let position: usize = match r.get(pos_idx).and_then(|p| p.parse::<f64>().ok()) {
+            Some(p) if p >= 1.0 => p as usize,
+            _ => continue,
+        };
+        if position > reference.unpaired.len() {
+            continue;
+        }
+        if let Some(allowed) = &allowed {
+            if !allowed.contains(&reference.sequence[position - 1].to_ascii_uppercase()) {
+                continue;
+            }
+        }
+        let label = reference.unpaired[position - 1];
+        let entry = pairs.entry(transcript).or_insert_with(|| vec![Vec::new(); candidate.len()]);
+        for (k, &ci) in candidate.iter().enumerate() {
+            if let Some(v) = r.get(ci).and_then(|s| s.parse::<f64>().ok()) {
+                if v.is_finite() && v != -999.0 {
+                    entry[k].push((v, label));
+                }
+            }
+        }
+    }
+
+    // the file name, the whole path when it has none ("..")
+    let dataset = Path::new(file_path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| file_path.to_string());
+    let mut result = Vec::new();
+    let mut transcripts: Vec<&String> = pairs.keys().collect();
+    transcripts.sort();
+    for transcript in transcripts {
+        for (k, &ci) in candidate.iter().enumerate() {
+            let p = &pairs[transcript][k];
+            let (roc, pr_auc) = roc_pr(p);
+            result.push(Evaluation {
+                dataset: dataset.clone(),
+                transcript: transcript.clone(),
+                column: header[ci].clone(),
+                n: p.len(),
+                positives: p.iter().filter(|x| x.1 == 1).count(),
+                auc: roc_auc(p),
+                pr_auc,
+                roc,
+            });
+        }
+    }
+    Ok(result)
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let cli = Cli::parse();
+    println!("load reference");
+    let refs = read_reference(&cli.reference)?;
+    if refs.is_empty() {
+        eprintln!("no structure found in {}", cli.reference);
+        std::process::exit(1);
+    }
+
+    println!("evaluate {} files", cli.scores.len());
+    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
+    let results: Vec<io::Result<Vec<Evaluation>>> = pool.install(|| {
+        cli.scores.par_iter().map(|f| evaluate_file(f, &refs, &cli)).collect()
+    });
+
+    let mut block = Block::new("evaluate", Cli::command().get_version().unwrap_or("unknown"));
+    for f in &cli.scores {
+        block.input(f);
+    }
+    block.input(&cli.reference);
+    if let Some(b) = &cli.bases {
+        block.set("bases", b.clone());
+    }
+    if !cli.columns.is_empty() {
+        block.set("columns", cli.columns.join(","));
+    }
+    let inputs: Vec<&str> = cli.scores.iter().chain([&cli.reference]).map(|s| s.as_str()).collect();
+    let comments: Vec<String> = provenance::upstream(&inputs, '#').into_iter().chain(block.lines('#')).collect();
+
+    let mut writer = BufWriter::new(File::create(&cli.output)?);
+    for line in &comments {
+        writeln!(writer, "{}", line)?;
+    }
+    writeln!(writer, "Dataset,Transcript,Column,N,Positives,AUC,PR_AUC")?;
+    let mut roc_writer = match &cli.roc {
+        Some(p) => {
+            let mut w = BufWriter::new(File::create(p)?);
+            for line in &comments {
+                writeln!(w, "{}", line)?;
+            }
+            writeln!(w, "Dataset,Transcript,Column,Threshold,FPR,TPR")?;
+            Some(w)
+        }
+        None => None,
+    };
+
+    // the other files are still written, the exit status reports the ones that failed
+    let mut failed = 0;
+    for (file, res) in cli.scores.iter().zip(results) {
+        let evaluations = match res {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                failed += 1;
+                continue;
+            }
+        };
+        for e in evaluations {
+            writeln!(writer, "{},{},{},{},{},{:.6},{:.6}", e.dataset, e.transcript, e.column, e.n, e.positives, e.auc, e.pr_auc)?;
+            if let Some(w) = roc_writer.as_mut() {
+                for (threshold, fpr, tpr) in &e.roc {
+                    writeln!(w, "{},{},{},{},{:.6},{:.6}", e.dataset, e.transcript, e.column, threshold, fpr, tpr)?;
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    if let Some(mut w) = roc_writer {
+        w.flush()?;
+    }
+    if failed > 0 {
+        eprintln!("{} of {} score files failed", failed, cli.scores.len());
+        std::process::exit(1);
+    }
+
+    println!("Total runtime {:?}", now.elapsed());
+    Ok(())
+}
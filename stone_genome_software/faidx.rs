@@ -0,0 +1,104 @@
+#![allow(dead_code)]
+// Random access to a samtools-indexed FASTA (<fasta>.fai next to it), shared by merge and bgsg.
+use memmap2::Mmap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+struct FaiEntry {
+    length: usize,
+    offset: usize,
+    line_bases: usize,
+    line_width: usize,
+}
+
+pub struct Fasta {
+    mmap: Mmap,
+    index: HashMap<String, FaiEntry>,
+    // NC_000001 -> NC_000001.11, ChrIDs are matched with or without the version
+    prefix: HashMap<String, String>,
+}
+
+fn get_key_prefix(key: &str) -> &str {
+    key.split('.').next().unwrap_or(key)
+}
+
+pub fn complement(base: char) -> char {
+    match base.to_ascii_uppercase() {
+        'A' => 'T',
+        'T' | 'U' => 'A',
+        'C' => 'G',
+        'G' => 'C',
+        'R' => 'Y',
+        'Y' => 'R',
+        'K' => 'M',
+        'M' => 'K',
+        'B' => 'V',
+        'V' => 'B',
+        'D' => 'H',
+        'H' => 'D',
+        'S' => 'S',
+        'W' => 'W',
+        _ => 'N',
+    }
+}
+
+impl Fasta {
+    pub fn open(file_path: &str) -> io::Result<Fasta> {
+        let fai = format!("{}.fai", file_path);
+        let reader = BufReader::new(File::open(&fai).map_err(|e| {
+            io::Error::new(e.kind(), format!("{}: {} (index the fasta with samtools faidx)", fai, e))
+        })?);
+        let mut index = HashMap::new();
+        let mut prefix = HashMap::new();
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 5 {
+                continue;
+            }
+            let parse = |s: &str| {
+                s.parse::<usize>()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{}: bad line {}", fai, line)))
+            };
+            let entry = FaiEntry {
+                length: parse(fields[1])?,
+                offset: parse(fields[2])?,
+                line_bases: parse(fields[3])?,
+                line_width: parse(fields[4])?,
+            };
+            prefix.insert(get_key_prefix(fields[0]).to_string(), fields[0].to_string());
+            index.insert(fields[0].to_string(), entry);
+        }
+        let file = File::open(file_path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Fasta { mmap, index, prefix })
+    }
+
+    fn entry(&self, chr: &str) -> Option<&FaiEntry> {
+        self.index
+            .get(chr)
+            .or_else(|| self.prefix.get(get_key_prefix(chr)).and_then(|name| self.index.get(name)))
+    }
+
+    pub fn contains(&self, chr: &str) -> bool {
+        self.entry(chr).is_some()
+    }
+
+    /// Upper-case plus strand base at a 1-based position, None off the end or for unknown sequences.
+    pub fn base(&self, chr: &str, position: usize) -> Option<char> {
+        let entry = self.entry(chr)?;
+        if position == 0 || position > entry.length || entry.line_bases == 0 {
+            return None;
+        }
+        let p = position - 1;
+        let at = entry.offset + (p / entry.line_bases) * entry.line_width + p % entry.line_bases;
+        self.mmap.get(at).map(|b| b.to_ascii_uppercase() as char)
+    }
+
+    /// Base read in the transcript direction, complemented on the minus strand.
+    pub fn stranded_base(&self, chr: &str, strand: char, position: usize) -> Option<char> {
+        let base = self.base(chr, position)?;
+        Some(if strand == '-' { complement(base) } else { base })
+    }
+}
@@ -0,0 +1,299 @@
+mod nnfold;
+mod provenance;
+
+use clap::{CommandFactory, Parser};
+use nnfold::{clean_sequence, ct_lines, deigan, dot_bracket, load_params, mfe, partition, Model, Partition};
+use provenance::Block;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name="fold", author="hyf", version="1.0", about="MFE folding with reactivity pseudo-energy constraints", long_about = None)]
+struct Cli {
+    /// sequences to fold
+    #[arg(short,long)]
+    fasta: String,
+    /// STONE scored csv, bgsg-like csv or a .shape/.map file (position value)
+    #[arg(short,long)]
+    reactivity: Option<String>,
+    /// reactivity column of a csv input
+    #[arg(short,long, default_value = "norm_model")]
+    column: String,
+    /// Deigan slope m in kcal/mol
+    #[arg(short = 'm', long, default_value_t = 1.8)]
+    slope: f64,
+    /// Deigan intercept b in kcal/mol
+    #[arg(short = 'b', long, default_value_t = -0.6, allow_negative_numbers = true)]
+    intercept: f64,
+    /// RNAfold 2.0 parameter file, default the built-in params/turner2004.par
+    #[arg(short,long)]
+    params: Option<String>,
+    /// output prefix, writes <prefix>.dot and <prefix>.ct
+    #[arg(short,long)]
+    output: String,
+    #[arg(short,long, default_value_t = 4)]
+    thread: usize,
+    /// also run the partition function, writes <prefix>_<name>.dp, <prefix>_pp.csv and <prefix>_lsle.bed
+    #[arg(long)]
+    partition: bool,
+    /// smallest probability written to the dot plot
+    #[arg(long, default_value_t = 0.001)]
+    cutoff: f64,
+    /// window for low SHAPE / low entropy regions
+    #[arg(long, default_value_t = 55)]
+    lsle_window: usize,
+    #[arg(long, default_value_t = 0.08)]
+    entropy_cutoff: f64,
+}
+
+struct Folded {
+    name: String,
+    seq: Vec<u8>,
+    reactivity: Option<Vec<f64>>,
+    pairs: Vec<Option<usize>>,
+    energy: f64,
+    partition: Option<Partition>,
+}
+
+fn read_fasta(file_path: &str) -> io::Result<Vec<(String, String)>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut records: Vec<(String, String)> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('>') {
+            records.push((header.split_whitespace().next().unwrap_or("").to_string(), String::new()));
+        } else if let Some((_, seq)) = records.last_mut() {
+            // .dot files carry the structure after the sequence, keep only letters
+            if line.chars().all(|c| c.is_ascii_alphabetic()) {
+                seq.push_str(line);
+            }
+        }
+    }
+    Ok(records)
+}
+
+// transcript -> 1-based position -> reactivity, "" holds files without transcript ids
+fn read_reactivity(file_path: &str, column: &str) -> io::Result<HashMap<String, HashMap<usize, f64>>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut map: HashMap<String, HashMap<usize, f64>> = HashMap::new();
+    let lower = file_path.to_lowercase();
+
+    if lower.ends_with(".shape") || lower.ends_with(".map") {
+        let entry = map.entry(String::new()).or_default();
+        for line in reader.lines() {
+            let line = line?;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 2 {
+                continue;
+            }
+            if let (Ok(p), Ok(v)) = (parts[0].parse::<usize>(), parts[1].parse::<f64>()) {
+                entry.insert(p, v);
+            }
+        }
+        return Ok(map);
+    }
+
+    let mut lines = reader.lines();
+    let header = match provenance::csv_header(&mut lines)? {
+        Some(h) => h,
+        None => return Ok(map),
+    };
+    let idx = |name: &str| header.iter().position(|h| h == name);
+    let id_idx = idx("transcriptid").or_else(|| idx("ChrID"));
+    let pos_idx = ["transcript_position", "pipe_truncation_ChrPos", "ChrPos", "position"]
+        .iter()
+        .find_map(|c| idx(c))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no position column", file_path)))?;
+    let val_idx = idx(column)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no {} column", file_path, column)))?;
+
+    for line in lines {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let parts = provenance::fields(&line);
+        let position = match parts.get(pos_idx).and_then(|p| p.parse::<f64>().ok()) {
+            Some(p) => p as usize,
+            None => continue,
+        };
+        let value = match parts.get(val_idx).and_then(|v| v.parse::<f64>().ok()) {
+            Some(v) => v,
+            None => continue,
+        };
+        let id = id_idx.and_then(|i| parts.get(i)).map(|s| s.to_string()).unwrap_or_default();
+        map.entry(id).or_default().insert(position, value);
+    }
+    Ok(map)
+}
+
+fn profile_for(name: &str, len: usize, reactivity: &HashMap<String, HashMap<usize, f64>>) -> Option<Vec<f64>> {
+    let values = reactivity.get(name).or_else(|| reactivity.get(""))?;
+    Some((1..=len).map(|p| *values.get(&p).unwrap_or(&f64::NAN)).collect())
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut v: Vec<f64> = values.iter().cloned().filter(|x| !x.is_nan()).collect();
+    if v.is_empty() {
+        return f64::NAN;
+    }
+    v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let m = v.len() / 2;
+    if v.len() & 1 == 0 { (v[m - 1] + v[m]) / 2.0 } else { v[m] }
+}
+
+// windows whose median reactivity is below the transcript median and whose
+// median entropy is below the cutoff, overlapping windows merged (Superfold lSlE)
+fn lsle_regions(reactivity: &[f64], entropy: &[f64], window: usize, cutoff: f64) -> Vec<(usize, usize)> {
+    let n = entropy.len();
+    let global = median(reactivity);
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    if n < window || global.is_nan() {
+        return regions;
+    }
+    for s in 0..=n - window {
+        let e = s + window;
+        if median(&reactivity[s..e]) < global && median(&entropy[s..e]) < cutoff {
+            match regions.last_mut() {
+                Some(last) if s <= last.1 => last.1 = e,
+                _ => regions.push((s, e)),
+            }
+        }
+    }
+    regions
+}
+
+// the .dp files keep the two line head RNAstructure and Superfold expect, without provenance
+fn write_partition(cli: &Cli, folded: &[Folded], comments: &[String]) -> io::Result<()> {
+    let mut table = BufWriter::new(File::create(format!("{}_pp.csv", cli.output))?);
+    for line in comments {
+        writeln!(table, "{}", line)?;
+    }
+    writeln!(table, "Transcript,Position,Base,Reactivity,MFE_Pair,PairProb,Entropy")?;
+    let mut bed = BufWriter::new(File::create(format!("{}_lsle.bed", cli.output))?);
+    for line in comments {
+        writeln!(bed, "{}", line)?;
+    }
+
+    for f in folded {
+        let pf = f.partition.as_ref().unwrap();
+        // RNAstructure/Superfold probability plot text
+        let mut dp = BufWriter::new(File::create(format!("{}_{}.dp", cli.output, f.name))?);
+        writeln!(dp, "{}", pf.n)?;
+        writeln!(dp, "i\tj\t-log10(Probability)")?;
+        for i in 0..pf.n {
+            for j in i + 1..pf.n {
+                let p = pf.prob(i, j);
+                if p >= cli.cutoff {
+                    writeln!(dp, "{}\t{}\t{:.5}", i + 1, j + 1, -p.log10())?;
+                }
+            }
+        }
+        dp.flush()?;
+
+        let pairing = pf.pairing();
+        let entropy = pf.entropy();
+        for i in 0..pf.n {
+            let r = f.reactivity.as_ref().map(|r| r[i]).filter(|x| !x.is_nan());
+            writeln!(
+                table,
+                "{},{},{},{},{},{:.6},{:.6}",
+                f.name,
+                i + 1,
+                f.seq[i] as char,
+                r.map(|x| x.to_string()).unwrap_or_else(|| "NA".to_string()),
+                f.pairs[i].map(|j| j + 1).unwrap_or(0),
+                pairing[i],
+                entropy[i]
+            )?;
+        }
+        if let Some(r) = &f.reactivity {
+            for (s, e) in lsle_regions(r, &entropy, cli.lsle_window, cli.entropy_cutoff) {
+                writeln!(bed, "{}\t{}\t{}", f.name, s, e)?;
+            }
+        }
+    }
+    table.flush()?;
+    bed.flush()?;
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let cli = Cli::parse();
+    let params = load_params(cli.params.as_deref())?;
+    let records = read_fasta(&cli.fasta)?;
+    let reactivity = match &cli.reactivity {
+        Some(r) => read_reactivity(r, &cli.column)?,
+        None => HashMap::new(),
+    };
+    println!("fold {} sequences", records.len());
+
+    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
+    let folded: Vec<Folded> = pool.install(|| {
+        records
+            .par_iter()
+            .map(|(name, seq)| {
+                let seq = clean_sequence(seq);
+                let profile = profile_for(name, seq.len(), &reactivity);
+                let pseudo = profile.as_ref().map(|r| deigan(r, cli.slope, cli.intercept));
+                let model = Model::new(seq.clone(), &params, pseudo);
+                let result = mfe(&model);
+                let pf = if cli.partition { Some(partition(&model, result.energy)) } else { None };
+                Folded {
+                    name: name.clone(),
+                    seq,
+                    reactivity: profile,
+                    pairs: result.pairs,
+                    energy: result.energy,
+                    partition: pf,
+                }
+            })
+            .collect()
+    });
+
+    let mut block = Block::new("fold", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&cli.fasta);
+    match &cli.params {
+        Some(p) => block.input(p),
+        None => block.set("params", nnfold::DEFAULT_PARAMS_NAME),
+    };
+    if let Some(r) = &cli.reactivity {
+        block.input(r).set("column", cli.column.clone());
+        block.set("deigan", format!("{}\t{}", cli.slope, cli.intercept));
+    }
+    if cli.partition {
+        block.set("partition", format!("cutoff={},lsle_window={},entropy_cutoff={}", cli.cutoff, cli.lsle_window, cli.entropy_cutoff));
+    }
+    let inputs: Vec<&str> = [Some(&cli.fasta), cli.reactivity.as_ref()].into_iter().flatten().map(|s| s.as_str()).collect();
+    let comments: Vec<String> = provenance::upstream(&inputs, '#').into_iter().chain(block.lines('#')).collect();
+
+    // the .ct keeps the length on its first line, which is what the CT readers take it from
+    let mut dot = BufWriter::new(File::create(format!("{}.dot", cli.output))?);
+    let mut ct = BufWriter::new(File::create(format!("{}.ct", cli.output))?);
+    for line in &comments {
+        writeln!(dot, "{}", line)?;
+    }
+    for f in &folded {
+        writeln!(dot, ">{}", f.name)?;
+        writeln!(dot, "{}", String::from_utf8_lossy(&f.seq))?;
+        writeln!(dot, "{} ({:.2})", dot_bracket(&f.pairs), f.energy)?;
+        for row in ct_lines(&f.name, &f.seq, &f.pairs, f.energy) {
+            writeln!(ct, "{}", row)?;
+        }
+    }
+    dot.flush()?;
+    ct.flush()?;
+
+    if cli.partition {
+        write_partition(&cli, &folded, &comments)?;
+    }
+
+    println!("Total runtime {:?}", now.elapsed());
+    Ok(())
+}
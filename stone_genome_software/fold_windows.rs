@@ -0,0 +1,340 @@
+mod nnfold;
+mod provenance;
+
+use clap::{CommandFactory, Parser};
+use nnfold::{clean_sequence, deigan, dot_bracket, load_params, mfe, partition, Model};
+use provenance::Block;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name="fold-windows", author="hyf", version="1.0", about="windowed partition function folding of long transcripts from bgsg or scored output", long_about = None)]
+struct Cli {
+    /// bgsg output or a scored csv
+    #[arg(short,long)]
+    input: String,
+    /// reactivity column, or mut/stop to use the bgsg count rates
+    #[arg(short,long, default_value = "norm_model")]
+    column: String,
+    /// transcript sequences, or chromosomes for genomic positions; otherwise the reference base column of the input is used
+    #[arg(short,long)]
+    fasta: Option<String>,
+    #[arg(short,long, default_value_t = 600)]
+    window: usize,
+    #[arg(short,long, default_value_t = 100)]
+    step: usize,
+    /// maximum distance between paired nucleotides
+    #[arg(long, default_value_t = 600)]
+    maxspan: usize,
+    /// pairs above this averaged probability form the consensus structure
+    #[arg(long, default_value_t = 0.5)]
+    min_prob: f64,
+    #[arg(short = 'm', long, default_value_t = 1.8)]
+    slope: f64,
+    #[arg(short = 'b', long, default_value_t = -0.6, allow_negative_numbers = true)]
+    intercept: f64,
+    /// RNAfold 2.0 parameter file, default the built-in params/turner2004.par
+    #[arg(short,long)]
+    params: Option<String>,
+    /// output prefix, writes <prefix>.dot and <prefix>_pp.csv
+    #[arg(short,long)]
+    output: String,
+    #[arg(short,long, default_value_t = 8)]
+    thread: usize,
+}
+
+const BAR_LAB: &str = "-\\|/";
+
+// one transcript 5'->3'
+#[derive(Debug)]
+struct Transcript {
+    name: String,
+    strand: char,
+    // genomic (or input) position of every nucleotide
+    positions: Vec<usize>,
+    seq: Vec<u8>,
+    reactivity: Vec<f64>,
+}
+
+fn complement(b: u8) -> u8 {
+    match b {
+        b'A' => b'U',
+        b'U' | b'T' => b'A',
+        b'C' => b'G',
+        b'G' => b'C',
+        _ => b'N',
+    }
+}
+
+fn read_fasta(file_path: &str) -> io::Result<HashMap<String, Vec<u8>>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut map: HashMap<String, Vec<u8>> = HashMap::new();
+    let mut name = String::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(h) = line.strip_prefix('>') {
+            name = h.split_whitespace().next().unwrap_or("").to_string();
+            map.insert(name.clone(), Vec::new());
+        } else if line.chars().all(|c| c.is_ascii_alphabetic()) {
+            if let Some(seq) = map.get_mut(&name) {
+                seq.extend(clean_sequence(line));
+            }
+        }
+    }
+    Ok(map)
+}
+
+// position -> (base, reactivity) of one transcript
+type Positions = HashMap<usize, (u8, f64)>;
+
+fn read_transcripts(file_path: &str, column: &str, fasta: &Option<HashMap<String, Vec<u8>>>) -> io::Result<Vec<Transcript>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut lines = reader.lines();
+    let header = match provenance::csv_header(&mut lines)? {
+        Some(h) => h,
+        None => return Ok(Vec::new()),
+    };
+    let idx = |name: &str| header.iter().position(|h| h == name);
+    let missing = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no {} column", file_path, what));
+
+    let key_idx = idx("transcriptid").or_else(|| idx("ChrID")).ok_or_else(|| missing("transcriptid/ChrID"))?;
+    let pos_idx = ["transcript_position", "position", "pipe_truncation_ChrPos", "ChrPos"]
+        .iter()
+        .find_map(|c| idx(c))
+        .ok_or_else(|| missing("position"))?;
+    let transcript_coords = header[pos_idx] == "transcript_position";
+    let strand_idx = idx("pipe_truncation_Strand");
+    let base_idx = idx("rf_mutation_Base").or_else(|| idx("pipe_truncation_Base"));
+    let rate_idx = match column {
+        "mut" => Some((idx("rf_mutation_Count").ok_or_else(|| missing("rf_mutation_Count"))?, idx("rf_mutation_Depth").ok_or_else(|| missing("rf_mutation_Depth"))?)),
+        "stop" => Some((idx("pipe_truncation_count").ok_or_else(|| missing("pipe_truncation_count"))?, idx("pipe_truncation_BD").ok_or_else(|| missing("pipe_truncation_BD"))?)),
+        _ => None,
+    };
+    let value_idx = if rate_idx.is_none() { Some(idx(column).ok_or_else(|| missing(column))?) } else { None };
+
+    // transcript -> (strand, positions)
+    let mut map: HashMap<String, (char, Positions)> = HashMap::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts = provenance::fields(&line);
+        let position = match parts.get(pos_idx).and_then(|p| p.parse::<f64>().ok()) {
+            Some(p) => p as usize,
+            None => continue,
+        };
+        let num = |i: usize| parts.get(i).and_then(|v| v.parse::<f64>().ok()).filter(|v| *v != -999.0);
+        let value = match rate_idx {
+            Some((ci, di)) => match (num(ci), num(di)) {
+                (Some(c), Some(d)) if d > 0.0 => c / d,
+                _ => f64::NAN,
+            },
+            None => num(value_idx.unwrap()).unwrap_or(f64::NAN),
+        };
+        let base = base_idx.and_then(|i| parts.get(i)).and_then(|b| b.bytes().next()).unwrap_or(b'N');
+        let strand = strand_idx.and_then(|i| parts.get(i)).and_then(|s| s.chars().next()).unwrap_or('+');
+        let name = match parts.get(key_idx) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let entry = map.entry(name).or_insert_with(|| (strand, HashMap::new()));
+        entry.1.insert(position, (base.to_ascii_uppercase(), value));
+    }
+
+    let mut transcripts = Vec::new();
+    for (name, (strand, rows)) in map {
+        let from_fasta = fasta.as_ref().and_then(|f| f.get(&name));
+        // a fasta sequence spans the transcript only for transcript positions; genomic rows keep
+        // their own span, read 5'->3' from a transcript sequence of that length or looked up in a
+        // chromosome sequence
+        let (lo, hi) = match from_fasta {
+            Some(seq) if transcript_coords => (1, seq.len()),
+            _ => (*rows.keys().min().unwrap(), *rows.keys().max().unwrap()),
+        };
+        let mut positions: Vec<usize> = (lo..=hi).collect();
+        let minus = strand == '-' && !transcript_coords;
+        if minus {
+            positions.reverse();
+        }
+        let seq: Vec<u8> = match from_fasta {
+            Some(seq) if seq.len() == positions.len() => seq.clone(),
+            Some(seq) if !transcript_coords && hi <= seq.len() => positions.iter().map(|p| if minus { complement(seq[p - 1]) } else { seq[p - 1] }).collect(),
+            _ => positions
+                .iter()
+                .map(|p| {
+                    let b = rows.get(p).map(|r| r.0).unwrap_or(b'N');
+                    let b = if b == b'T' { b'U' } else { b };
+                    if minus { complement(b) } else { b }
+                })
+                .collect(),
+        };
+        let reactivity = positions.iter().map(|p| rows.get(p).map(|r| r.1).unwrap_or(f64::NAN)).collect();
+        transcripts.push(Transcript { name, strand, positions, seq, reactivity });
+    }
+    transcripts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(transcripts)
+}
+
+fn window_starts(n: usize, window: usize, step: usize) -> Vec<usize> {
+    if n <= window {
+        return vec![0];
+    }
+    let mut starts: Vec<usize> = (0..=n - window).step_by(step.max(1)).collect();
+    if *starts.last().unwrap() != n - window {
+        starts.push(n - window);
+    }
+    starts
+}
+
+// consensus from averaged probabilities: strongest pairs first, no conflicts or crossings
+fn consensus(n: usize, probs: &HashMap<(usize, usize), f64>, min_prob: f64) -> Vec<Option<usize>> {
+    let mut candidates: Vec<(&(usize, usize), &f64)> = probs.iter().filter(|(_, p)| **p > min_prob).collect();
+    candidates.sort_by(|a, b| b.1.partial_cmp(a.1).unwrap().then(a.0.cmp(b.0)));
+    let mut pairs: Vec<Option<usize>> = vec![None; n];
+    let mut chosen: Vec<(usize, usize)> = Vec::new();
+    for (&(i, j), _) in candidates {
+        if pairs[i].is_some() || pairs[j].is_some() {
+            continue;
+        }
+        if chosen.iter().any(|&(k, l)| (k < i && i < l && l < j) || (i < k && k < j && j < l)) {
+            continue;
+        }
+        pairs[i] = Some(j);
+        pairs[j] = Some(i);
+        chosen.push((i, j));
+    }
+    pairs
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let cli = Cli::parse();
+    let params = load_params(cli.params.as_deref())?;
+    let fasta = match &cli.fasta {
+        Some(f) => Some(read_fasta(f)?),
+        None => None,
+    };
+    println!("load file");
+    let transcripts = read_transcripts(&cli.input, &cli.column, &fasta)?;
+
+    // every window of every transcript is one job
+    let mut jobs: Vec<(usize, usize, usize)> = Vec::new();
+    for (t, tr) in transcripts.iter().enumerate() {
+        for s in window_starts(tr.seq.len(), cli.window, cli.step) {
+            jobs.push((t, s, (s + cli.window).min(tr.seq.len())));
+        }
+    }
+    println!("{} transcripts, {} windows", transcripts.len(), jobs.len());
+
+    // summed window probabilities per transcript
+    let sums: Vec<Mutex<HashMap<(usize, usize), f64>>> = transcripts.iter().map(|_| Mutex::new(HashMap::new())).collect();
+    let count = Arc::new(Mutex::new(0));
+    let clen = jobs.len();
+    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
+    pool.install(|| {
+        jobs.par_iter().for_each(|&(t, s, e)| {
+            let tr = &transcripts[t];
+            let pseudo = deigan(&tr.reactivity[s..e], cli.slope, cli.intercept);
+            let mut model = Model::new(tr.seq[s..e].to_vec(), &params, Some(pseudo));
+            model.max_span = cli.maxspan;
+            let m = mfe(&model);
+            let pf = partition(&model, m.energy);
+            let mut local = Vec::new();
+            for i in 0..pf.n {
+                for j in i + 1..pf.n {
+                    let p = pf.prob(i, j);
+                    if p >= 1e-4 {
+                        local.push(((s + i, s + j), p));
+                    }
+                }
+            }
+            let mut sum = sums[t].lock().unwrap();
+            for (k, p) in local {
+                *sum.entry(k).or_insert(0.0) += p;
+            }
+            drop(sum);
+
+            let mut num = count.lock().unwrap();
+            *num += 1;
+            print!(
+                "\r{}{}{}%",
+                "#".repeat(((*num * 100) / clen) / 2),
+                BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
+                (*num * 100) / clen
+            );
+        });
+    });
+    println!("\nOutput data...");
+
+    let mut block = Block::new("fold-windows", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&cli.input);
+    match &cli.params {
+        Some(p) => block.input(p),
+        None => block.set("params", nnfold::DEFAULT_PARAMS_NAME),
+    };
+    if let Some(f) = &cli.fasta {
+        block.input(f);
+    }
+    block.set("column", cli.column.clone());
+    block.set("deigan", format!("{}\t{}", cli.slope, cli.intercept));
+    block.set("windows", format!("window={},step={},maxspan={},min_prob={}", cli.window, cli.step, cli.maxspan, cli.min_prob));
+    let comments: Vec<String> = provenance::upstream(&[&cli.input], '#').into_iter().chain(block.lines('#')).collect();
+
+    let mut dot = BufWriter::new(File::create(format!("{}.dot", cli.output))?);
+    let mut table = BufWriter::new(File::create(format!("{}_pp.csv", cli.output))?);
+    for line in &comments {
+        writeln!(dot, "{}", line)?;
+        writeln!(table, "{}", line)?;
+    }
+    writeln!(table, "Transcript,Strand,Position,ChrPos,Base,Reactivity,Pair,PairProb")?;
+    for (t, tr) in transcripts.iter().enumerate() {
+        let n = tr.seq.len();
+        let starts = window_starts(n, cli.window, cli.step);
+        let sum = sums[t].lock().unwrap();
+        // average over the windows that contain both nucleotides
+        let probs: HashMap<(usize, usize), f64> = sum
+            .iter()
+            .map(|(&(i, j), &p)| {
+                let covering = starts.iter().filter(|&&s| s <= i && j < s + cli.window).count().max(1);
+                ((i, j), p / covering as f64)
+            })
+            .collect();
+        let pairs = consensus(n, &probs, cli.min_prob);
+        let mut pairing = vec![0.0; n];
+        for (&(i, j), &p) in &probs {
+            pairing[i] += p;
+            pairing[j] += p;
+        }
+
+        writeln!(dot, ">{}", tr.name)?;
+        writeln!(dot, "{}", String::from_utf8_lossy(&tr.seq))?;
+        writeln!(dot, "{}", dot_bracket(&pairs))?;
+        for i in 0..n {
+            let r = tr.reactivity[i];
+            writeln!(
+                table,
+                "{},{},{},{},{},{},{},{:.6}",
+                tr.name,
+                tr.strand,
+                i + 1,
+                tr.positions[i],
+                tr.seq[i] as char,
+                if r.is_nan() { "NA".to_string() } else { r.to_string() },
+                pairs[i].map(|j| j + 1).unwrap_or(0),
+                pairing[i].min(1.0)
+            )?;
+        }
+    }
+    dot.flush()?;
+    table.flush()?;
+
+    println!("Total runtime {:?}", now.elapsed());
+    Ok(())
+}
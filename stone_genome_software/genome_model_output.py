@@ -6,6 +6,8 @@ import numpy as np
 import pandas as pd
 import pickle
 import argparse
+import hashlib
+import time
 from sklearn.preprocessing import MinMaxScaler
 
 # Load the model (modified to take user input for the model file path)
@@ -60,7 +62,7 @@ def remap_values(values):
 # Define data processing function
 def process_testdata(file_path, depth_threshold=50, rf_mutation_Count=0, pipe_truncation_count=0, rate_mut=0.25, rate_stop=1, acc_threshold=0, filtered_bases=None, filter_acc=None, custom_names=None):
     # Read the data
-    df_original = pd.read_csv(file_path)
+    df_original = pd.read_csv(file_path, comment='#')
 
     # Create a copy of the original data to preserve all original position information
     df_original_positions = df_original[['ChrID', 'pipe_truncation_Strand', 'pipe_truncation_ChrPos', 'rf_mutation_Base']].copy()
@@ -157,10 +159,39 @@ def process_and_save(file_path, output_file_path, model):
         how='left', 
         on=['ChrID', 'pipe_truncation_Strand', 'pipe_truncation_ChrPos'])
     
-    # Save the result to a CSV file
-    df_output.to_csv(output_file_path, index=False)
+    # Save the result to a CSV file, history of the input and this run first as #stone lines
+    block = [('tool', 'genome_model_output.py 1.0'), ('command', ' '.join(sys.argv)),
+             ('date', utc_date()), ('input', f'{file_path}\tsha256:{sha256_file(file_path)}')] + provenance
+    with open(output_file_path, 'w') as out:
+        out.write(''.join(f'{line}\n' for line in stone_lines(file_path)))
+        out.write(''.join(f'#stone\t{key}\t{value}\n' for key, value in block))
+        df_output.to_csv(out, index=False)
     print(f"Results saved to {output_file_path}")
 
+# Provenance lines shared with the rust tools: "#stone<TAB>key<TAB>value"
+def stone_lines(path):
+    """The #stone lines at the top of a csv."""
+    lines = []
+    with open(path) as f:
+        for line in f:
+            if not line.startswith('#'):
+                break
+            if line.startswith('#stone\t'):
+                lines.append(line.rstrip('\n'))
+    return lines
+
+def sha256_file(path):
+    h = hashlib.sha256()
+    with open(path, 'rb') as f:
+        for chunk in iter(lambda: f.read(1 << 20), b''):
+            h.update(chunk)
+    return h.hexdigest()
+
+def utc_date():
+    """UTC now, or SOURCE_DATE_EPOCH when set."""
+    secs = int(os.environ.get('SOURCE_DATE_EPOCH', time.time()))
+    return time.strftime('%Y-%m-%dT%H:%M:%SZ', time.gmtime(secs))
+
 # Define default parameters
 depth_threshold = 10
 rf_mutation_Count = 0
@@ -170,15 +201,45 @@ rate_stop = 1
 acc_threshold = 0
 filtered_base = False
 filter_acc = True
+provenance = []
+
+# [model] keys of stone.toml and their types
+MODEL_KEYS = {'depth_threshold': int, 'rf_mutation_Count': int, 'pipe_truncation_count': int,
+              'rate_mut': float, 'rate_stop': float, 'acc_threshold': float, 'filter_acc': bool}
+
+def load_model_config(path):
+    """Read the [model] section of a stone.toml, unknown keys are an error."""
+    try:
+        import tomllib
+    except ModuleNotFoundError:
+        import tomli as tomllib
+    with open(path, 'rb') as f:
+        section = tomllib.load(f).get('model', {})
+    unknown = set(section) - set(MODEL_KEYS)
+    if unknown:
+        sys.exit(f"{path}: unknown key(s) {', '.join(sorted('model.' + k for k in unknown))} (known: {', '.join(MODEL_KEYS)})")
+    return section
 
 if __name__ == "__main__":
     parser = argparse.ArgumentParser(description='Process and predict data using SHAPE model.')
     parser.add_argument('-i', '--input_folder', required=True, help='Path to the folder containing input CSV files.')
     parser.add_argument('-o', '--output_folder', required=True, help='Path to the folder where results will be saved.')
     parser.add_argument('-m', '--model_path', required=True, help='Path to the SHAPE model file (e.g., .sav file).')
+    parser.add_argument('-c', '--config', help='stone.toml, its [model] section replaces the defaults.')
+    for key, kind in MODEL_KEYS.items():
+        parser.add_argument(f'--{key}', type=(lambda v: v.lower() in ('1', 'true', 'yes')) if kind is bool else kind,
+                            help=f'overrides [model] {key} (default {globals()[key]}).')
 
     args = parser.parse_args()
 
+    # defaults < --config < command line
+    settings = load_model_config(args.config) if args.config else {}
+    settings.update({k: getattr(args, k) for k in MODEL_KEYS if getattr(args, k) is not None})
+    for key, value in settings.items():
+        globals()[key] = MODEL_KEYS[key](value)
+    provenance = [('input', f'{args.model_path}\tsha256:{sha256_file(args.model_path)}'), ('config', args.config or '-')]
+    provenance += [('setting', f'model.{k}={globals()[k]}') for k in MODEL_KEYS]
+
     input_folder = args.input_folder
     output_folder = args.output_folder
     model_path = args.model_path
@@ -0,0 +1,108 @@
+#![allow(dead_code)]
+// <output>.manifest written by stone run next to every stage output: tool, parameters and the
+// size, mtime and sha256 of the inputs and the output, one tab separated record per line.
+use crate::provenance::sha256_file;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+pub const MAGIC: &str = "#stone manifest 1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileRecord {
+    pub path: String,
+    pub size: u64,
+    pub mtime: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub stage: String,
+    pub tool: String,
+    pub version: String,
+    pub params: Vec<String>,
+    pub inputs: Vec<FileRecord>,
+    pub output: Option<FileRecord>,
+    pub created: u64,
+}
+
+pub fn path_for(output: &Path) -> PathBuf {
+    let mut name = output.as_os_str().to_owned();
+    name.push(".manifest");
+    PathBuf::from(name)
+}
+
+/// Size, mtime and hash of a file; the hash of `cached` is reused when size and mtime are unchanged.
+pub fn file_record(path: &Path, cached: Option<&FileRecord>) -> io::Result<FileRecord> {
+    let meta = fs::metadata(path)?;
+    let size = meta.len();
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let path_str = path.display().to_string();
+    if let Some(c) = cached {
+        if c.path == path_str && c.size == size && c.mtime == mtime {
+            return Ok(c.clone());
+        }
+    }
+    Ok(FileRecord { path: path_str, size, mtime, sha256: sha256_file(path)? })
+}
+
+fn parse_record(fields: &[&str]) -> Option<FileRecord> {
+    if fields.len() < 5 {
+        return None;
+    }
+    Some(FileRecord {
+        path: fields[1].to_string(),
+        size: fields[2].parse().ok()?,
+        mtime: fields[3].parse().ok()?,
+        sha256: fields[4].to_string(),
+    })
+}
+
+impl Manifest {
+    pub fn read(path: &Path) -> io::Result<Manifest> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(MAGIC) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: not a stone manifest", path.display())));
+        }
+        let mut m = Manifest::default();
+        for line in lines {
+            let line = line?;
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[0] {
+                "stage" => m.stage = fields.get(1).unwrap_or(&"").to_string(),
+                "tool" => {
+                    m.tool = fields.get(1).unwrap_or(&"").to_string();
+                    m.version = fields.get(2).unwrap_or(&"").to_string();
+                }
+                "param" => m.params.push(fields.get(1).unwrap_or(&"").to_string()),
+                "input" => m.inputs.extend(parse_record(&fields)),
+                "output" => m.output = parse_record(&fields),
+                "created" => m.created = fields.get(1).and_then(|c| c.parse().ok()).unwrap_or(0),
+                _ => {}
+            }
+        }
+        Ok(m)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "stage\t{}", self.stage)?;
+        writeln!(out, "tool\t{}\t{}", self.tool, self.version)?;
+        for p in &self.params {
+            writeln!(out, "param\t{}", p)?;
+        }
+        for (kind, r) in self.inputs.iter().map(|r| ("input", r)).chain(self.output.iter().map(|r| ("output", r))) {
+            writeln!(out, "{}\t{}\t{}\t{}\t{}", kind, r.path, r.size, r.mtime, r.sha256)?;
+        }
+        writeln!(out, "created\t{}", self.created)?;
+        out.flush()
+    }
+
+    pub fn input(&self, path: &str) -> Option<&FileRecord> {
+        self.inputs.iter().find(|r| r.path == path)
+    }
+}
@@ -1,44 +1,78 @@
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufReader, BufRead, Write, BufWriter};
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+
+mod config;
+mod provenance;
+use config::Config;
+use provenance::Block;
+
+// (chrid, geneid, position) -> (rf count, rf depth, pipe count, pipe bd)
+type PositionCounts = HashMap<(String, String, String), (u32, u32, u32, u32)>;
+// geneid -> (mean rf count, mean rf depth, rf ratio, mean pipe count, mean pipe bd, pipe ratio)
+type GeneStats = HashMap<String, (f64, f64, f64, f64, f64, f64)>;
 
 #[derive(Parser)]
 #[command(name="mbreport", author="hyf", version="1.0", about="statistic", long_about = None)]
 struct Cli {
     #[arg(short,long)]
     input: String,
+    /// falls back to [mbreport] depth in --config, then 10
     #[arg(short,long)]
-    depth: u32,
+    depth: Option<u32>,
     #[arg(short,long)]
     output: String,
+    /// highest count/depth kept for both RF and pipe, falls back to [mbreport] max_ratio, then 0.25
+    #[arg(long)]
+    max_ratio: Option<f64>,
+    /// stone.toml
+    #[arg(long)]
+    config: Option<String>,
 }
 
 fn main() -> io::Result<()> {
     let cli = Cli::parse();
-    let file = File::open(cli.input)?; 
+    let config = Config::load(cli.config.as_deref())?;
+    let depth = cli.depth.or(config.usize("mbreport", "depth").map(|d| d as u32)).unwrap_or(10);
+    let max_ratio = cli.max_ratio.or(config.float("mbreport", "max_ratio")).unwrap_or(0.25);
+    let file = File::open(&cli.input)?; 
     let reader = BufReader::new(file); 
 
-    let mut data_map: HashMap<(String, String, String), (u32, u32, u32, u32)> = HashMap::new();
+    let mut data_map: PositionCounts = HashMap::new();
     let lines = reader.lines(); 
+    // 列号按 bgsg 的表头查找, 没有表头时用旧版 bgsg 的列号
+    let mut cols = [0, 1, 3, 6, 7, 23, 24];
+    let names = ["ChrID", "geneid", "position", "rf_mutation_Count", "rf_mutation_Depth", "pipe_truncation_count", "pipe_truncation_BD"];
 
     for line in lines {
         let line = line?; 
-        if line.trim().is_empty() {
+        if line.trim().is_empty() || line.starts_with('#') {
             continue; 
         }
         let fields: Vec<&str> = line.split(',').collect(); 
-        let chrid = fields[0].to_string();
-        let geneid = fields[1].to_string();
-        let position = fields[3].to_string();
+        if fields[0] == "ChrID" {
+            for (c, name) in cols.iter_mut().zip(names) {
+                if let Some(i) = fields.iter().position(|f| f.trim() == name) {
+                    *c = i;
+                }
+            }
+            continue;
+        }
+        let field = |i: usize| fields.get(cols[i]).copied().unwrap_or("");
+        let chrid = field(0).to_string();
+        let geneid = field(1).to_string();
+        let position = field(2).to_string();
 
-        let rf_mutation_count: u32 = fields[6].parse().unwrap_or(0);
-        let rf_mutation_depth: u32 = fields[7].parse().unwrap_or(0);
-        let pipe_truncation_count: u32 = fields[23].parse().unwrap_or(0);
-        let pipe_truncation_bd: u32 = fields[24].parse().unwrap_or(0);
+        let rf_mutation_count: u32 = field(3).parse().unwrap_or(0);
+        let rf_mutation_depth: u32 = field(4).parse().unwrap_or(0);
+        let pipe_truncation_count: u32 = field(5).parse().unwrap_or(0);
+        let pipe_truncation_bd: u32 = field(6).parse().unwrap_or(0);
 
 
-        if rf_mutation_depth > cli.depth && pipe_truncation_bd > cli.depth && rf_mutation_count*4 < rf_mutation_depth && pipe_truncation_count*4 < pipe_truncation_bd{
+        if rf_mutation_depth > depth && pipe_truncation_bd > depth
+            && (rf_mutation_count as f64) < rf_mutation_depth as f64 * max_ratio
+            && (pipe_truncation_count as f64) < pipe_truncation_bd as f64 * max_ratio {
             data_map.insert((chrid, geneid, position), (rf_mutation_count, rf_mutation_depth, pipe_truncation_count, pipe_truncation_bd));
         }
 }
@@ -46,7 +80,14 @@ fn main() -> io::Result<()> {
 
     let file = File::create(cli.output)?;
     let mut writer = BufWriter::new(file);
-
+    let effective = [("mbreport.depth", depth.to_string()), ("mbreport.max_ratio", max_ratio.to_string())];
+    let mut block = Block::new("mbreport", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&cli.input);
+    block.set("filter", format!("rf_mutation_Depth>{0},pipe_truncation_BD>{0},count/depth<{1}", depth, max_ratio));
+    block.extend(config::settings(&config, &effective));
+    for line in provenance::upstream(&[&cli.input], '#').into_iter().chain(block.lines('#')) {
+        writeln!(writer, "{}", line)?;
+    }
 
     writeln!(writer, "GeneID,Avg_RF_Count,Avg_RF_Depth,RF_Ratio,Avg_Pipe_Count,Avg_Pipe_BD,Pipe_Ratio")?;
 
@@ -66,9 +107,9 @@ fn main() -> io::Result<()> {
 }
 
 fn calculate_gene_statistics(
-    data_map: HashMap<(String, String, String), (u32, u32, u32, u32)>
-) -> HashMap<String, (f64, f64, f64, f64, f64, f64)> {
-    let mut gene_stats_map: HashMap<String, (f64, f64, f64, f64, f64, f64)> = HashMap::new();
+    data_map: PositionCounts
+) -> GeneStats {
+    let mut gene_stats_map: GeneStats = HashMap::new();
     let mut gene_aggregates: HashMap<String, (u32, u32, u32, u32, usize)> = HashMap::new();
 
 
@@ -1,276 +1,123 @@
-use rayon::prelude::*;
-use clap::Parser;
-use std::collections::HashMap;
+mod config;
+mod faidx;
+mod merged;
+mod provenance;
+mod rc;
+mod zipped;
+
+use clap::{CommandFactory, Parser};
+use config::Config;
+use faidx::Fasta;
+use merged::{merge_data, parse_file1, parse_file2, parse_file3, GeneEntry};
+use provenance::Block;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Write};
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
-use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
-#[derive(Debug, Clone)]
-struct GeneEntry {
-    chr_id: String,
-    strand: char,
-    position: u32,
-    base1: Option<char>,
-    base3: Option<char>,
-    rt_1: Option<i32>,
-    bd_1: Option<i32>,
-    mutations: Vec<i32>,
-    rt_3: Option<i32>,
-    bd_3: Option<i32>,         
+// 与参考基因组比对碱基, 返回RefBase和BaseFlag
+fn check_bases(entry: &GeneEntry, fasta: &Fasta, counts: &mut [usize; 5]) -> (char, String) {
+    let norm = |b: Option<char>| b.map(|c| if c == 'U' { 'T' } else { c.to_ascii_uppercase() }).filter(|c| *c != 'N');
+    let base1 = norm(entry.base1);
+    let base3 = norm(entry.base3);
+    counts[0] += 1;
+    let ref_base = match fasta.stranded_base(&entry.chr_id, entry.strand, entry.position as usize) {
+        Some(b) if b != 'N' => b,
+        _ => {
+            counts[4] += 1;
+            return ('N', "noref".to_string());
+        }
+    };
+    let mut flags = Vec::new();
+    // RF的碱基总是正链
+    if base1.is_some() && fasta.base(&entry.chr_id, entry.position as usize) != base1 {
+        flags.push("rf");
+        counts[1] += 1;
+    }
+    if base3.is_some() && base3 != Some(ref_base) {
+        flags.push("pipe");
+        counts[2] += 1;
+    }
+    // pipe的碱基在转录本方向, 负链上RF碱基取互补再比较
+    let stranded1 = base1.map(|b| if entry.strand == '-' { faidx::complement(b) } else { b });
+    if stranded1.is_some() && base3.is_some() && stranded1 != base3 {
+        flags.push("discordant");
+        counts[3] += 1;
+    }
+    let flag = if flags.is_empty() { ".".to_string() } else { flags.join("|") };
+    (ref_base, flag)
 }
 
-const BAR_LAB: &str = "-\\|/";
-
-fn parse_file1(file_path: &str) -> io::Result<HashMap<(String, char, u32), GeneEntry>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-
-    let map: HashMap<(String, char, u32), GeneEntry> = reader
-        .lines()
-        .par_bridge() 
-        .filter_map(|line| {
-            let line = line.ok()?;
-            if line.starts_with('@') {
-                return None; 
-            }
-            let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
-            if parts.len() < 5 {
-                return None;
+// RNA Framework RC file in place of the zip_rfcsv output, positions without count and coverage are
+// left out as zip_rfcsv does
+type RfRows = HashMap<(String, char, u32), GeneEntry>;
+
+fn parse_rc(file_path: &str, strand: char) -> io::Result<RfRows> {
+    let mut rc = rc::RcFile::open(file_path)?;
+    let mut map = HashMap::new();
+    for i in 0..rc.len() {
+        let t = rc.read(i).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file_path, e)))?;
+        for (p, ((&base, &count), &coverage)) in t.sequence.iter().zip(&t.counts).zip(&t.coverage).enumerate() {
+            if count == 0 && coverage == 0 {
+                continue;
             }
-
-            let chr_id = parts[0].to_string();
-            let position: u32 = parts[1].parse().ok()?;
-            let base1 = parts[2].chars().next();
-            let rt_1: i32 = parts[3].parse().ok()?;
-            let bd_1: i32 = parts[4].parse().ok()?;
-
+            let position = p as u32 + 1;
             let entry = GeneEntry {
-                chr_id: chr_id.clone(),
-                strand: '+',
+                chr_id: t.name.clone(),
+                strand,
                 position,
-                base1,
+                base1: Some(base as char),
                 base3: None,
-                rt_1: Some(rt_1),
-                bd_1: Some(bd_1),
+                rt_1: Some(count as i32),
+                bd_1: Some(coverage as i32),
                 mutations: vec![0; 14],
                 rt_3: None,
                 bd_3: None,
             };
-
-            Some(((chr_id, '+', position), entry))
-        })
-        .collect();
-
-    Ok(map)
-}
-
-
-fn parse_file2(file_path: &str) -> io::Result<HashMap<(String, char, u32), Vec<i32>>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-
-    let map: HashMap<(String, char, u32), Vec<i32>> = reader
-        .lines()
-        .par_bridge()
-        .filter_map(|line| {
-            let line = line.ok()?;
-            if line.starts_with('@') {
-                return None;
-            }
-            let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
-            if parts.len() < 16 {
-                return None;
-            }
-
-            let chr_id = parts[0].to_string();
-            let position: u32 = parts[1].parse().ok()?;
-            let mutations: Vec<i32> = parts[2..16]
-                .iter()
-                .map(|&s| s.parse().unwrap_or(0))
-                .collect();
-
-            Some(((chr_id, '+', position), mutations))
-        })
-        .collect();
-
-    Ok(map)
-}
-
-// 解析文件3
-fn parse_file3(file_path: &str) -> io::Result<HashMap<(String, char, u32), (Option<char>, i32, i32)>> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-
-    let mut chr_id_index = vec![];
-    let mut strand_index = vec![];
-
-    let lines: Vec<_> = reader.lines().collect::<Result<_, _>>()?;
-
-    for line in &lines {
-        if line.starts_with("@ChrID_Index") {
-            chr_id_index = line.split_whitespace().skip(1).map(String::from).collect();
-            continue;
-        }
-        if line.starts_with("@ChrID_Strand") {
-            strand_index = line.split_whitespace().skip(1).map(|s| s.chars().next().unwrap()).collect();
-            continue;
+            map.insert((t.name.clone(), strand, position), entry);
         }
     }
-
-    let map: HashMap<(String, char, u32), (Option<char>, i32, i32)> = lines
-        .into_par_iter() 
-        .filter_map(|line| {
-            if line.starts_with('@') {
-                return None; 
-            }
-
-            let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
-            if parts.len() < 5 {
-                return None; // 跳过不完整行
-            }
-
-            let index: usize = parts[0].parse::<usize>().ok()? - 1; // 减1以匹配索引
-            let chr_id = chr_id_index[index].clone(); // 从索引中获取染色体ID
-            let strand = strand_index[index]; // 从索引中获取链信息
-            let position: u32 = parts[1].parse().ok()?;
-            let base3 = parts[2].chars().next(); // 文件3的碱基信息
-            let rt_3: i32 = parts[3].parse().ok()?;
-            let bd_3: i32 = parts[4].parse().ok()?;
-
-            Some(((chr_id, strand, position), (base3, rt_3, bd_3)))
-        })
-        .collect();
-
     Ok(map)
 }
 
-// 合并数据
-fn merge_data(
-    file1_data: HashMap<(String, char, u32), GeneEntry>,
-    file2_data: HashMap<(String, char, u32), Vec<i32>>,
-    file3_data: HashMap<(String, char, u32), (Option<char>, i32, i32)>
-    ) -> Vec<GeneEntry> {
-    let merged_entries = Arc::new(Mutex::new(HashMap::new())); // 使用Arc和Mutex包装HashMap
-    let clen = &file1_data.len();
-    let count = Arc::new(Mutex::new(0));
-    // 使用par_iter并行处理
-    file1_data.into_par_iter().for_each(|((chr_id, strand, position), entry)| {
-        let counter = Arc::clone(&count);
-        let mut merged_entries = merged_entries.lock().unwrap();
-        merged_entries.insert((chr_id.clone(), strand, position), entry);
-        let mut num = counter.lock().unwrap();
-        *num += 1;
-        print!(
-            "\rmergefile1{}{}{}%",
-            "#".repeat(((*num * 100) / clen)/ 2),
-            BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
-            ((*num * 100) / clen)
-        );
-    });
-    let count = Arc::new(Mutex::new(0));
-    let clen = &file2_data.len();
-    file2_data.into_par_iter().for_each(|((chr_id, strand, position), mutations)| {
-        let counter = Arc::clone(&count);
-        let mut merged_entries = merged_entries.lock().unwrap();
-        let entry = merged_entries.entry((chr_id.clone(), strand, position)).or_insert(GeneEntry {
-            chr_id: chr_id.clone(),
-            strand,
-            position,
-            base1: None,
-            base3: None,
-            rt_1: None,
-            bd_1: None,
-            mutations: vec![0; 14],
-            rt_3: None,
-            bd_3: None,
-        });
-
-        entry.mutations = mutations;
-        let mut num = counter.lock().unwrap();
-        *num += 1;
-        print!(
-            "\rmergefile2{}{}{}%",
-            "#".repeat(((*num * 100) / clen)/ 2),
-            BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
-            ((*num * 100) / clen)
-        );
-    });
-
-    let count = Arc::new(Mutex::new(0));
-    let clen = &file3_data.len();
-    file3_data.into_par_iter().for_each(|((chr_id, strand, position), (base3, rt_3, bd_3))| {
-        let counter = Arc::clone(&count);
-        let mut merged_entries = merged_entries.lock().unwrap();
-        let entry = merged_entries.entry((chr_id.clone(), strand, position)).or_insert(GeneEntry {
-            chr_id: chr_id.clone(),
-            strand,
-            position,
-            base1: None,
-            base3,
-            rt_1: None,
-            bd_1: None,
-            mutations: vec![0; 14],
-            rt_3: None,
-            bd_3: None,
-        });
-        
-        entry.base3 = base3.or(entry.base3);
-        entry.rt_3 = Some(rt_3);
-        entry.bd_3 = Some(bd_3);
-        let mut num = counter.lock().unwrap();
-        *num += 1;
-        print!(
-            "\rmergefile3{}{}{}%",
-            "#".repeat(((*num * 100) / clen)/ 2),
-            BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
-            ((*num * 100) / clen)
-        );
-    });
-
-    let mut result: Vec<GeneEntry> = merged_entries.lock().unwrap().clone().into_iter().map(|(_, v)| v).collect();
-    
-    result.par_sort_by(|a, b| {
-        a.chr_id.cmp(&b.chr_id)
-            .then_with(|| a.strand.cmp(&b.strand))
-            .then_with(|| a.position.cmp(&b.position))
-    });
-    result
-}
-
 // 写入到新的CSV文件
-fn write_to_csv(file_path: &str, data: Vec<GeneEntry>) -> io::Result<()> {
+fn write_to_csv(file_path: &str, data: Vec<GeneEntry>, fasta: Option<&Fasta>, comments: &[String]) -> io::Result<()> {
     let path = Path::new(file_path);
-    let mut file = File::create(&path)?;
+    let mut file = BufWriter::new(File::create(path)?);
+    // ChrID -> Positions,RF_Mismatch,Pipe_Mismatch,Discordant,NoRef
+    let mut report: BTreeMap<String, [usize; 5]> = BTreeMap::new();
 
+    for line in comments {
+        writeln!(file, "{}", line)?;
+    }
     // 写入表头
-    writeln!(file, "ChrID,Strand,Position,Base1,RT1,BD1,AC,AG,AT,CA,CG,CT,GA,GC,GT,TA,TC,TG,Ins,Del,Base3,RT3,BD3")?;
+    write!(file, "{}", merged::HEADER)?;
+    if fasta.is_some() {
+        write!(file, ",RefBase,BaseFlag")?;
+    }
+    writeln!(file)?;
 
     for entry in data {
-        // 如果base1或base3为空，用'N'代替
-        let base1_str = entry.base1.map_or("N".to_string(), |b| b.to_string());
-        let base3_str = entry.base3.map_or("N".to_string(), |b| b.to_string());
-
-
-        // 将每个条目写入CSV文件
-        writeln!(
-            file,
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            entry.chr_id,
-            entry.strand,
-            entry.position,
-            base1_str,
-            entry.rt_1.unwrap_or(0),
-            entry.bd_1.unwrap_or(0),
-            entry.mutations[0], entry.mutations[1], entry.mutations[2], entry.mutations[3],
-            entry.mutations[4], entry.mutations[5], entry.mutations[6], entry.mutations[7],
-            entry.mutations[8], entry.mutations[9], entry.mutations[10], entry.mutations[11],
-            entry.mutations[12], entry.mutations[13],
-            base3_str, // 文件3的碱基信息
-            entry.rt_3.unwrap_or(0),
-            entry.bd_3.unwrap_or(0)
-        )?;
+        write!(file, "{}", merged::row(&entry))?;
+        if let Some(fasta) = fasta {
+            let counts = report.entry(entry.chr_id.clone()).or_insert([0; 5]);
+            let (ref_base, flag) = check_bases(&entry, fasta, counts);
+            write!(file, ",{},{}", ref_base, flag)?;
+        }
+        writeln!(file)?;
+    }
+    file.flush()?;
+
+    if fasta.is_some() {
+        let mut out = BufWriter::new(File::create(format!("{}.basecheck.csv", file_path))?);
+        writeln!(out, "ChrID,Positions,RF_Mismatch,Pipe_Mismatch,Discordant,NoRef")?;
+        println!("ChrID\tPositions\tRF_Mismatch\tPipe_Mismatch\tDiscordant\tNoRef");
+        for (chr_id, c) in &report {
+            writeln!(out, "{},{},{},{},{},{}", chr_id, c[0], c[1], c[2], c[3], c[4])?;
+            println!("{}\t{}\t{}\t{}\t{}\t{}", chr_id, c[0], c[1], c[2], c[3], c[4]);
+        }
+        out.flush()?;
     }
 
     Ok(())
@@ -279,6 +126,7 @@ fn write_to_csv(file_path: &str, data: Vec<GeneEntry>) -> io::Result<()> {
 #[derive(Parser)]
 #[command(name="merge", author="hyf", version="1.0", about="merge three files", long_about = None)]
 struct Cli {
+    /// zip_rfcsv output or the RNA Framework .rc file (with its .rci when there is one)
     #[arg(short,long)]
     csv: String,
     #[arg(short,long)]
@@ -287,6 +135,16 @@ struct Cli {
     txt: String,
     #[arg(short,long)]
     output: String,
+    /// samtools-indexed reference, adds RefBase and BaseFlag columns and writes <OUTPUT>.basecheck.csv
+    /// falls back to [reference] fasta in --config
+    #[arg(short,long)]
+    fasta: Option<String>,
+    /// strand of the rows of an .rc --csv, falls back to [zip] strand in --config, then +
+    #[arg(short,long)]
+    strand: Option<char>,
+    /// stone.toml
+    #[arg(long)]
+    config: Option<String>,
 }
 
 
@@ -296,6 +154,9 @@ fn main() -> io::Result<()> {
     let file_path1 = &cli.csv;
     let file_path2 = &cli.pipe;
     let file_path3 = &cli.txt;
+    let config = Config::load(cli.config.as_deref())?;
+    let rc_input = rc::is_rc(file_path1);
+    let strand = cli.strand.or(config.str("zip", "strand").and_then(|s| s.chars().next())).unwrap_or('+');
 
     // 使用 rayon::join 来并行运行三个任务
     let (result1, result2, result3) = {
@@ -305,7 +166,7 @@ fn main() -> io::Result<()> {
 
         rayon::scope(|s| {
             s.spawn(|_| {
-                result1 = Some(parse_file1(file_path1));
+                result1 = Some(if rc_input { parse_rc(file_path1, strand) } else { parse_file1(file_path1) });
             });
 
             s.spawn(|_| {
@@ -330,9 +191,31 @@ fn main() -> io::Result<()> {
     println!("three file read over");
     let output_file = &cli.output;
     println!("merge data");
-    let merged_data = merge_data(file1_data, file2_data, file3_data);
+    let fasta_path = cli.fasta.clone().or(config.str("reference", "fasta"));
+    let fasta = match &fasta_path {
+        Some(f) => Some(Fasta::open(f)?),
+        None => None,
+    };
+    let mut effective = vec![("reference.fasta", fasta_path.clone().unwrap_or_else(|| "-".to_string()))];
+    if rc_input {
+        effective.push(("zip.strand", strand.to_string()));
+    }
+    // history of the three zip files (an RC file has none), then this run
+    let mut zipped = vec![file_path2.as_str(), file_path3.as_str()];
+    if !rc_input {
+        zipped.insert(0, file_path1);
+    }
+    let mut comments = provenance::upstream(&zipped, '#');
+    let mut block = Block::new("merge", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(file_path1).input(file_path2).input(file_path3);
+    if let Some(f) = &fasta_path {
+        block.reference(f);
+    }
+    block.extend(config::settings(&config, &effective));
+    comments.extend(block.lines('#'));
+    let merged_data = merge_data(file1_data, file2_data, file3_data, true);
     println!("write to csv");
-    write_to_csv(output_file, merged_data)?;
+    write_to_csv(output_file, merged_data, fasta.as_ref(), &comments)?;
 
     let end = now.elapsed().as_secs();
     
@@ -0,0 +1,222 @@
+#![allow(dead_code)]
+// The three zip files merged on (ChrID, strand, position): zip_rfcsv (RT, BD), zip_rftxt2 (the
+// mutation counts) and zip_pipe (truncations), read with zipped.rs; RF rows take the strand the zip
+// tools were given. Shared by merge and the bindings (stone_r) so the
+// transcript-level tables come from the same code as the genome-level ones.
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use crate::zipped::{self, Kind, Row};
+
+#[derive(Debug, Clone)]
+pub struct GeneEntry {
+    pub chr_id: String,
+    pub strand: char,
+    pub position: u32,
+    pub base1: Option<char>,
+    pub base3: Option<char>,
+    pub rt_1: Option<i32>,
+    pub bd_1: Option<i32>,
+    pub mutations: Vec<i32>,
+    pub rt_3: Option<i32>,
+    pub bd_3: Option<i32>,         
+}
+
+/// (ChrID, strand, position)
+pub type Key = (String, char, u32);
+/// zip_pipe base, RT stops and depth by position
+pub type PipeRows = HashMap<Key, (Option<char>, i32, i32)>;
+
+const BAR_LAB: &str = "-\\|/";
+
+// The rows of a zip file of `kind` by (ChrID, strand, position). A row that cannot be read fails
+// the whole file, with the line number of the first one, so no position goes missing unnoticed.
+fn read_rows<V, F>(file_path: &str, kind: Kind, value: F) -> io::Result<HashMap<Key, V>>
+where
+    V: Send,
+    F: Fn(&Row) -> Result<V, String> + Sync,
+{
+    let (header, lines) = zipped::open_numbered(file_path)?;
+    header.expect(kind, file_path)?;
+
+    let first_error: Mutex<Option<(usize, io::Error)>> = Mutex::new(None);
+    let map: HashMap<Key, V> = lines
+        .par_bridge()
+        .filter_map(|(number, line)| {
+            let parsed = line.and_then(|line| {
+                let row = header.row(&line)?;
+                let value = value(&row).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", e, line)))?;
+                Ok(((row.chr_id.to_string(), row.strand, row.position), value))
+            });
+            match parsed {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    let mut first = first_error.lock().unwrap();
+                    if !matches!(&*first, Some((n, _)) if *n < number) {
+                        *first = Some((number, e));
+                    }
+                    None
+                }
+            }
+        })
+        .collect();
+
+    match first_error.into_inner().unwrap() {
+        Some((number, e)) => Err(io::Error::new(e.kind(), format!("{}: line {}: {}", file_path, number, e))),
+        None => Ok(map),
+    }
+}
+
+fn count(field: &str, name: &str) -> Result<i32, String> {
+    field.parse().map_err(|_| format!("{} {} is not a count", name, field))
+}
+
+// zip_rfcsv output: base, mutation count and depth
+pub fn parse_file1(file_path: &str) -> io::Result<HashMap<Key, GeneEntry>> {
+    read_rows(file_path, Kind::RfCsv, |row| {
+        Ok(GeneEntry {
+            chr_id: row.chr_id.to_string(),
+            strand: row.strand,
+            position: row.position,
+            base1: row.fields[0].chars().next(),
+            base3: None,
+            rt_1: Some(count(row.fields[1], "RT")?),
+            bd_1: Some(count(row.fields[2], "BD")?),
+            mutations: vec![0; 14],
+            rt_3: None,
+            bd_3: None,
+        })
+    })
+}
+
+
+// zip_rftxt2 output: the 14 mutation counts
+pub fn parse_file2(file_path: &str) -> io::Result<HashMap<Key, Vec<i32>>> {
+    read_rows(file_path, Kind::RfTxt, |row| row.fields.iter().map(|s| count(s, "mutation count")).collect())
+}
+
+// zip_pipe output: base, RT stops and depth
+pub fn parse_file3(file_path: &str) -> io::Result<PipeRows> {
+    read_rows(file_path, Kind::Pipe, |row| {
+        let base3 = row.fields[0].chars().next(); // 文件3的碱基信息
+        Ok((base3, count(row.fields[1], "RT")?, count(row.fields[2], "BD")?))
+    })
+}
+
+// 合并数据
+pub fn merge_data(
+    file1_data: HashMap<Key, GeneEntry>,
+    file2_data: HashMap<Key, Vec<i32>>,
+    file3_data: PipeRows,
+    progress: bool,
+    ) -> Vec<GeneEntry> {
+    let merged_entries = Arc::new(Mutex::new(HashMap::new())); // 使用Arc和Mutex包装HashMap
+    let clen = &file1_data.len();
+    let count = Arc::new(Mutex::new(0));
+    // 使用par_iter并行处理
+    file1_data.into_par_iter().for_each(|((chr_id, strand, position), entry)| {
+        let counter = Arc::clone(&count);
+        let mut merged_entries = merged_entries.lock().unwrap();
+        merged_entries.insert((chr_id.clone(), strand, position), entry);
+        let mut num = counter.lock().unwrap();
+        *num += 1;
+        if progress {
+            print!(
+                "\rmergefile1{}{}{}%",
+                "#".repeat(((*num * 100) / clen)/ 2),
+                BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
+                ((*num * 100) / clen)
+        );
+        }
+    });
+    let count = Arc::new(Mutex::new(0));
+    let clen = &file2_data.len();
+    file2_data.into_par_iter().for_each(|((chr_id, strand, position), mutations)| {
+        let counter = Arc::clone(&count);
+        let mut merged_entries = merged_entries.lock().unwrap();
+        let entry = merged_entries.entry((chr_id.clone(), strand, position)).or_insert(GeneEntry {
+            chr_id: chr_id.clone(),
+            strand,
+            position,
+            base1: None,
+            base3: None,
+            rt_1: None,
+            bd_1: None,
+            mutations: vec![0; 14],
+            rt_3: None,
+            bd_3: None,
+        });
+
+        entry.mutations = mutations;
+        let mut num = counter.lock().unwrap();
+        *num += 1;
+        if progress {
+            print!(
+                "\rmergefile2{}{}{}%",
+                "#".repeat(((*num * 100) / clen)/ 2),
+                BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
+                ((*num * 100) / clen)
+        );
+        }
+    });
+
+    let count = Arc::new(Mutex::new(0));
+    let clen = &file3_data.len();
+    file3_data.into_par_iter().for_each(|((chr_id, strand, position), (base3, rt_3, bd_3))| {
+        let counter = Arc::clone(&count);
+        let mut merged_entries = merged_entries.lock().unwrap();
+        let entry = merged_entries.entry((chr_id.clone(), strand, position)).or_insert(GeneEntry {
+            chr_id: chr_id.clone(),
+            strand,
+            position,
+            base1: None,
+            base3,
+            rt_1: None,
+            bd_1: None,
+            mutations: vec![0; 14],
+            rt_3: None,
+            bd_3: None,
+        });
+        
+        entry.base3 = base3.or(entry.base3);
+        entry.rt_3 = Some(rt_3);
+        entry.bd_3 = Some(bd_3);
+        let mut num = counter.lock().unwrap();
+        *num += 1;
+        if progress {
+            print!(
+                "\rmergefile3{}{}{}%",
+                "#".repeat(((*num * 100) / clen)/ 2),
+                BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
+                ((*num * 100) / clen)
+        );
+        }
+    });
+
+    let mut result: Vec<GeneEntry> = merged_entries.lock().unwrap().clone().into_values().collect();
+    
+    result.par_sort_by(|a, b| {
+        a.chr_id.cmp(&b.chr_id)
+            .then_with(|| a.strand.cmp(&b.strand))
+            .then_with(|| a.position.cmp(&b.position))
+    });
+    result
+}
+
+/// ChrID,Strand,Position,Base1,RT1,BD1,AC..TG,Ins,Del,Base3,RT3,BD3, missing bases as N and counts as 0.
+pub const HEADER: &str = "ChrID,Strand,Position,Base1,RT1,BD1,AC,AG,AT,CA,CG,CT,GA,GC,GT,TA,TC,TG,Ins,Del,Base3,RT3,BD3";
+
+pub fn row(entry: &GeneEntry) -> String {
+    // 如果base1或base3为空，用'N'代替
+    let base1_str = entry.base1.map_or("N".to_string(), |b| b.to_string());
+    let base3_str = entry.base3.map_or("N".to_string(), |b| b.to_string());
+    let mut fields = vec![entry.chr_id.clone(), entry.strand.to_string(), entry.position.to_string(), base1_str];
+    fields.push(entry.rt_1.unwrap_or(0).to_string());
+    fields.push(entry.bd_1.unwrap_or(0).to_string());
+    fields.extend(entry.mutations.iter().map(|m| m.to_string()));
+    fields.push(base3_str); // 文件3的碱基信息
+    fields.push(entry.rt_3.unwrap_or(0).to_string());
+    fields.push(entry.bd_3.unwrap_or(0).to_string());
+    fields.join(",")
+}
@@ -0,0 +1,216 @@
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use rand::Rng;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+mod config;
+mod provenance;
+mod signal;
+mod zipped;
+use config::Config;
+use provenance::Block;
+use signal::{model_row, MODEL_INPUT_HEADER};
+
+#[derive(Parser)]
+#[command(name="model-input", author="hyf", version="1.0", about="split merged files into per-transcript model input csv", long_about = None)]
+struct Cli {
+    /// transcript lengths, one "ChrID length" per line, falls back to [model_input] len in --config
+    #[arg(short,long)]
+    len: Option<String>,
+    #[arg(short,long, default_value = "+")]
+    strand: char,
+    /// merged files, directories are searched for files ending with --suffix
+    #[arg(short,long, num_args = 1.., required = true)]
+    merged: Vec<String>,
+    #[arg(long, default_value = "merged.txt")]
+    suffix: String,
+    /// writes <OUTPUT>/<merged file stem>/<ChrID>_<plus|minus>.csv
+    #[arg(short,long)]
+    output: String,
+    /// merged files processed at the same time
+    #[arg(short,long, default_value_t = 8)]
+    threads: usize,
+    /// stone.toml, [model_input] fills in options not given on the command line
+    #[arg(long)]
+    config: Option<String>,
+}
+
+fn read_len(file_path: &str) -> io::Result<HashMap<String, usize>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut lengths = HashMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() >= 2 {
+            if let Ok(len) = fields[1].parse::<usize>() {
+                lengths.insert(fields[0].to_string(), len);
+            }
+        }
+    }
+    Ok(lengths)
+}
+
+fn find_merged(paths: &[String], suffix: &str) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut stack: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    while let Some(p) = stack.pop() {
+        if p.is_dir() {
+            for entry in fs::read_dir(&p)? {
+                let path = entry?.path();
+                if path.is_dir() || path.to_string_lossy().ends_with(suffix) {
+                    stack.push(path);
+                }
+            }
+        } else {
+            files.push(p);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn write_transcript(dir: &Path, chr_id: &str, strand: char, len: usize, rows: &HashMap<usize, String>, comments: &[String]) -> io::Result<()> {
+    let name = format!("{}_{}.csv", chr_id, if strand == '-' { "minus" } else { "plus" });
+    let mut out = BufWriter::new(File::create(dir.join(name))?);
+    for line in comments {
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out, "{}", MODEL_INPUT_HEADER)?;
+    let empty = ",".repeat(17);
+    for p in 1..=len {
+        match rows.get(&p) {
+            Some(row) => writeln!(out, "{}", row)?,
+            None => writeln!(out, "{},{}{},{},,,,,,,,", chr_id, empty, strand, p)?,
+        }
+    }
+    out.flush()
+}
+
+// the merged file is sorted by ChrID, strand and position, so one transcript is held at a time
+fn convert(file: &Path, cli: &Cli, lengths: &HashMap<String, usize>, settings: &[(String, String)]) -> io::Result<(usize, usize)> {
+    let stem = file.file_name().unwrap().to_string_lossy().trim_end_matches(".txt").trim_end_matches(".csv").to_string();
+    let dir = Path::new(&cli.output).join(stem);
+    fs::create_dir_all(&dir)?;
+    let reader = BufReader::new(File::open(file)?);
+    // history of the merged file, then this run
+    let path = file.display().to_string();
+    let mut block = Block::new("model-input", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&path).input(cli.len.as_deref().unwrap_or("-"));
+    block.set("filter", format!("pipe_truncation_Strand={}", cli.strand)).extend(settings.to_vec());
+    let mut comments = provenance::upstream(&[&path], '#');
+    comments.extend(block.lines('#'));
+    let mut rng = rand::thread_rng();
+
+    let mut done: HashSet<String> = HashSet::new();
+    let mut current: Option<String> = None;
+    let mut rows: HashMap<usize, String> = HashMap::new();
+    let mut unknown: HashSet<String> = HashSet::new();
+    let mut written = 0;
+
+    // #stone provenance lines come before the header
+    let lines = reader.lines().filter(|l| !matches!(l, Ok(l) if l.starts_with('#')));
+    for line in lines.skip(1) {
+        let line = line?;
+        let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+        if parts.len() < 23 || !parts[1].starts_with(cli.strand) {
+            continue;
+        }
+        let len = match lengths.get(parts[0]) {
+            Some(l) => *l,
+            None => {
+                unknown.insert(parts[0].to_string());
+                continue;
+            }
+        };
+        if current.as_deref() != Some(parts[0]) {
+            if let Some(chr_id) = current.take() {
+                write_transcript(&dir, &chr_id, cli.strand, lengths[&chr_id], &rows, &comments)?;
+                written += 1;
+                rows.clear();
+                done.insert(chr_id);
+            }
+            if done.contains(parts[0]) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: not sorted by ChrID ({})", file.display(), parts[0])));
+            }
+            current = Some(parts[0].to_string());
+        }
+        let position: usize = match parts[2].parse() {
+            Ok(p) if p >= 1 && p <= len => p,
+            _ => continue,
+        };
+        rows.insert(position, model_row(&parts, &rng.gen_range(0..=1u8).to_string()));
+    }
+    if let Some(chr_id) = current {
+        write_transcript(&dir, &chr_id, cli.strand, lengths[&chr_id], &rows, &comments)?;
+        written += 1;
+    }
+    Ok((written, unknown.len()))
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let config = Config::load(cli.config.as_deref())?;
+    let defaulted = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    cli.len = cli.len.or(config.str("model_input", "len"));
+    if defaulted("strand") {
+        cli.strand = config.str("model_input", "strand").and_then(|s| s.chars().next()).unwrap_or(cli.strand);
+    }
+    if defaulted("suffix") {
+        cli.suffix = config.str("model_input", "suffix").unwrap_or(cli.suffix);
+    }
+    if defaulted("threads") {
+        cli.threads = config.usize("model_input", "threads").unwrap_or(cli.threads);
+    }
+    let len_path = cli.len.clone().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "--len is required (or [model_input] len in --config)"))?;
+    if cli.strand != '+' && cli.strand != '-' {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "--strand must be + or -"));
+    }
+    let lengths = read_len(&len_path)?;
+    let files = find_merged(&cli.merged, &cli.suffix)?;
+    println!("{} merged files, {} transcripts in {}", files.len(), lengths.len(), len_path);
+    let effective = [
+        ("model_input.len", len_path.clone()),
+        ("model_input.strand", cli.strand.to_string()),
+        ("model_input.threads", cli.threads.to_string()),
+        ("model_input.suffix", cli.suffix.clone()),
+    ];
+    let settings = config::settings(&config, &effective);
+
+    let finished = AtomicUsize::new(0);
+    let pool = ThreadPoolBuilder::new().num_threads(cli.threads).build().unwrap();
+    let failed: Vec<String> = pool.install(|| {
+        files
+            .par_iter()
+            .filter_map(|file| {
+                let result = convert(file, &cli, &lengths, &settings);
+                let n = finished.fetch_add(1, Ordering::SeqCst) + 1;
+                match result {
+                    Ok((written, unknown)) => {
+                        println!("[{}/{}] {}: {} transcripts, {} ChrIDs not in the length file", n, files.len(), file.display(), written, unknown);
+                        None
+                    }
+                    Err(e) => {
+                        eprintln!("[{}/{}] {}: {}", n, files.len(), file.display(), e);
+                        Some(file.display().to_string())
+                    }
+                }
+            })
+            .collect()
+    });
+
+    println!("Total runtime {:?}", now.elapsed());
+    if !failed.is_empty() {
+        eprintln!("{} of {} merged files failed: {}", failed.len(), files.len(), failed.join(", "));
+        std::process::exit(1);
+    }
+    Ok(())
+}
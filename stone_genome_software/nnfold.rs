@@ -0,0 +1,873 @@
+// Nearest neighbour energy model and Zuker MFE folding shared by the fold tools.
+// Energies are in kcal/mol. Parameters are read from RNAfold 2.0 parameter files (the
+// Turner 2004 set in params/turner2004.par is built into the tools, ViennaRNA's
+// rna_turner2004.par can be given instead) and used as RNAfold -d2 does: stacking,
+// hairpin/bulge/interior loops with terminal mismatches, the 1x1/1x2/2x2 interior loop
+// tables, special hairpins, Ninio asymmetry, a linear multiloop, and dangles or mismatches
+// on both sides of every exterior and multiloop branch.
+// Each tool uses only part of this file.
+#![allow(dead_code)]
+
+use std::io;
+
+pub const MAXLOOP: usize = 30;
+pub const MIN_HAIRPIN: usize = 3;
+const INF: f64 = f64::INFINITY;
+
+// CG GC GU UG AU UA and NS (any other pair) rows of the tables, bases N A C G U
+pub const NBPAIRS: usize = 7;
+pub type Mismatch = [[[f64; 5]; 5]; NBPAIRS];
+pub type Dangle = [[f64; 5]; NBPAIRS];
+
+#[derive(Debug, Clone)]
+pub struct Params {
+    // [outer pair][inner pair read 3'->5']
+    pub stack: [[f64; NBPAIRS]; NBPAIRS],
+    // [pair][base 3' of i][base 5' of j], for exterior and multiloop branches the bases
+    // outside the pair: [pair][base 5' of i][base 3' of j]
+    pub mismatch_hairpin: Mismatch,
+    pub mismatch_interior: Mismatch,
+    pub mismatch_interior_1n: Mismatch,
+    pub mismatch_interior_23: Mismatch,
+    pub mismatch_multi: Mismatch,
+    pub mismatch_exterior: Mismatch,
+    pub dangle5: Dangle,
+    pub dangle3: Dangle,
+    // flat [outer][inner][bases] tables, None when the file has none and the loops are generic
+    pub int11: Option<Vec<f64>>,
+    pub int21: Option<Vec<f64>>,
+    pub int22: Option<Vec<f64>>,
+    pub hairpin: Vec<f64>,
+    pub bulge: Vec<f64>,
+    pub interior: Vec<f64>,
+    pub ml_base: f64,
+    pub ml_closing: f64,
+    pub ml_intern: f64,
+    pub ninio: f64,
+    pub ninio_max: f64,
+    pub terminal_au: f64,
+    pub lxc: f64,
+    // special hairpins, the loop with its closing pair and the energy of the whole loop
+    pub triloops: Vec<(Vec<u8>, f64)>,
+    pub tetraloops: Vec<(Vec<u8>, f64)>,
+    pub hexaloops: Vec<(Vec<u8>, f64)>,
+}
+
+// /* */ comments may span lines, newlines are kept so sections stay on their lines
+fn strip_comments(text: &str) -> String {
+    let mut clean = String::new();
+    let mut rest = text;
+    while let Some(s) = rest.find("/*") {
+        clean.push_str(&rest[..s]);
+        rest = match rest[s..].find("*/") {
+            Some(e) => {
+                clean.extend(rest[s..s + e].chars().filter(|&c| c == '\n'));
+                &rest[s + e + 2..]
+            }
+            None => "",
+        };
+    }
+    clean.push_str(rest);
+    clean
+}
+
+// INF means forbidden, DEF and NST are the RNAfold placeholders for -50 and 0
+fn parse_value(t: &str) -> Option<f64> {
+    match t {
+        "INF" => Some(INF),
+        "DEF" => Some(-50.0),
+        "NST" => Some(0.0),
+        _ => t.parse::<f64>().ok(),
+    }
+}
+
+pub const DEFAULT_PARAMS: &str = include_str!("params/turner2004.par");
+pub const DEFAULT_PARAMS_NAME: &str = "built-in params/turner2004.par";
+const HEADER: &str = "## RNAfold parameter file v2.0";
+
+/// The parameter file at `file_path`, or the built-in set when there is none.
+pub fn load_params(file_path: Option<&str>) -> io::Result<Params> {
+    match file_path {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+            parse_params(&text, path)
+        }
+        None => parse_params(DEFAULT_PARAMS, DEFAULT_PARAMS_NAME),
+    }
+}
+
+// sections present must have exactly their RNAfold 2.0 number of values, *_enthalpies and
+// unknown sections are skipped. Stack, loop, ML_params, NINIO and Misc are required; without
+// dangles there are none, without mismatch tables loops closed by AU/GU get the terminal
+// penalty and branches the sum of the dangles, without int11/int21/int22 those loops are generic
+pub fn parse_params(text: &str, name: &str) -> io::Result<Params> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", name, msg));
+    if text.lines().map(|l| l.trim()).find(|l| !l.is_empty()) != Some(HEADER) {
+        return Err(invalid(format!("not an RNAfold 2.0 parameter file (first line \"{}\")", HEADER)));
+    }
+    let mut sections: Vec<(String, Vec<String>)> = Vec::new();
+    for line in strip_comments(text).lines() {
+        let t = line.trim();
+        if let Some(section) = t.strip_prefix("# ") {
+            sections.push((section.trim().to_string(), Vec::new()));
+        } else if t.starts_with('#') || t.is_empty() {
+            continue;
+        } else if let Some((_, lines)) = sections.last_mut() {
+            lines.push(t.to_string());
+        }
+    }
+    let section = |name: &str| sections.iter().find(|(n, _)| n == name).map(|(_, l)| l);
+    // dcal/mol -> kcal/mol
+    let numbers = |name: &str| -> io::Result<Option<Vec<f64>>> {
+        let lines = match section(name) {
+            Some(lines) => lines,
+            None => return Ok(None),
+        };
+        let mut v = Vec::new();
+        for t in lines.iter().flat_map(|l| l.split_whitespace()) {
+            v.push(parse_value(t).ok_or_else(|| invalid(format!("section {}: bad value {}", name, t)))? / 100.0);
+        }
+        Ok(Some(v))
+    };
+    let values = |name: &str, len: usize| -> io::Result<Option<Vec<f64>>> {
+        match numbers(name)? {
+            Some(v) if v.len() != len => {
+                Err(invalid(format!("section {} has {} values, RNAfold 2.0 files have {}", name, v.len(), len)))
+            }
+            v => Ok(v),
+        }
+    };
+    let required = |name: &str, len: usize| -> io::Result<Vec<f64>> {
+        values(name, len)?.ok_or_else(|| invalid(format!("section {} missing", name)))
+    };
+    let special = |name: &str| -> io::Result<Vec<(Vec<u8>, f64)>> {
+        let mut loops = Vec::new();
+        for l in section(name).into_iter().flatten() {
+            let mut f = l.split_whitespace();
+            match (f.next(), f.next().and_then(parse_value)) {
+                (Some(s), Some(e)) => loops.push((clean_sequence(s), e / 100.0)),
+                _ => return Err(invalid(format!("section {}: bad line {}", name, l))),
+            }
+        }
+        Ok(loops)
+    };
+
+    let s = required("stack", NBPAIRS * NBPAIRS)?;
+    let mut stack = [[0.0; NBPAIRS]; NBPAIRS];
+    for (a, row) in stack.iter_mut().enumerate() {
+        row.copy_from_slice(&s[a * NBPAIRS..(a + 1) * NBPAIRS]);
+    }
+    let dangle = |name: &str| -> io::Result<Dangle> {
+        let mut d = [[0.0; 5]; NBPAIRS];
+        if let Some(v) = values(name, NBPAIRS * 5)? {
+            for (t, row) in d.iter_mut().enumerate() {
+                row.copy_from_slice(&v[t * 5..(t + 1) * 5]);
+            }
+        }
+        Ok(d)
+    };
+    let dangle5 = dangle("dangle5")?;
+    let dangle3 = dangle("dangle3")?;
+    let ml = required("ML_params", 6)?;
+    let ninio = required("NINIO", 3)?;
+    // DuplexInit, TerminalAU and LXC with their enthalpies
+    let misc = match numbers("Misc")? {
+        Some(v) if v.len() >= 5 => v,
+        _ => return Err(invalid("section Misc needs DuplexInit, TerminalAU and LXC".to_string())),
+    };
+    let terminal_au = misc[2];
+    let au = |t: usize| if t >= 2 { terminal_au } else { 0.0 };
+    let mismatch = |name: &str, default: &dyn Fn(usize, usize, usize) -> f64| -> io::Result<Mismatch> {
+        let v = values(name, NBPAIRS * 25)?;
+        let mut m = [[[0.0; 5]; 5]; NBPAIRS];
+        for (t, block) in m.iter_mut().enumerate() {
+            for (a, row) in block.iter_mut().enumerate() {
+                for (b, x) in row.iter_mut().enumerate() {
+                    *x = match &v {
+                        Some(v) => v[(t * 5 + a) * 5 + b],
+                        None => default(t, a, b),
+                    };
+                }
+            }
+        }
+        Ok(m)
+    };
+    let loop_au = |t: usize, _: usize, _: usize| au(t);
+    let dangles = |t: usize, a: usize, b: usize| dangle5[t][a] + dangle3[t][b];
+
+    Ok(Params {
+        stack,
+        mismatch_hairpin: mismatch("mismatch_hairpin", &loop_au)?,
+        mismatch_interior: mismatch("mismatch_interior", &loop_au)?,
+        mismatch_interior_1n: mismatch("mismatch_interior_1n", &loop_au)?,
+        mismatch_interior_23: mismatch("mismatch_interior_23", &loop_au)?,
+        mismatch_multi: mismatch("mismatch_multi", &dangles)?,
+        mismatch_exterior: mismatch("mismatch_exterior", &dangles)?,
+        dangle5,
+        dangle3,
+        int11: values("int11", NBPAIRS * NBPAIRS * 25)?,
+        int21: values("int21", NBPAIRS * NBPAIRS * 125)?,
+        int22: values("int22", 6 * 6 * 256)?,
+        hairpin: required("hairpin", MAXLOOP + 1)?,
+        bulge: required("bulge", MAXLOOP + 1)?,
+        interior: required("interior", MAXLOOP + 1)?,
+        ml_base: ml[0],
+        ml_closing: ml[2],
+        ml_intern: ml[4],
+        ninio: ninio[0],
+        ninio_max: ninio[2],
+        terminal_au,
+        lxc: misc[4],
+        triloops: special("Triloops")?,
+        tetraloops: special("Tetraloops")?,
+        hexaloops: special("Hexaloops")?,
+    })
+}
+
+// CG GC GU UG AU UA, the order of the tables
+pub fn pair_type(a: u8, b: u8) -> Option<usize> {
+    match (a, b) {
+        (b'C', b'G') => Some(0),
+        (b'G', b'C') => Some(1),
+        (b'G', b'U') => Some(2),
+        (b'U', b'G') => Some(3),
+        (b'A', b'U') => Some(4),
+        (b'U', b'A') => Some(5),
+        _ => None,
+    }
+}
+
+// the same pair read from the other strand
+fn reverse_type(t: usize) -> usize {
+    t ^ 1
+}
+
+// N A C G U, the order of the mismatch and dangle columns
+fn base_index(b: u8) -> usize {
+    match b {
+        b'A' => 1,
+        b'C' => 2,
+        b'G' => 3,
+        b'U' => 4,
+        _ => 0,
+    }
+}
+
+pub fn clean_sequence(seq: &str) -> Vec<u8> {
+    seq.trim().bytes().map(|b| match b.to_ascii_uppercase() {
+        b'T' => b'U',
+        c => c,
+    }).collect()
+}
+
+// Deigan et al. 2009: dG(i) = m*ln(reactivity+1) + b, missing data gets no term
+pub fn deigan(reactivity: &[f64], slope: f64, intercept: f64) -> Vec<f64> {
+    reactivity
+        .iter()
+        .map(|&r| if r.is_nan() || r < -500.0 { 0.0 } else { slope * (r.max(0.0) + 1.0).ln() + intercept })
+        .collect()
+}
+
+pub struct Model<'a> {
+    pub seq: Vec<u8>,
+    pub params: &'a Params,
+    // per nucleotide pseudo energy, added for every nucleotide of a stacked pair
+    pub pseudo: Vec<f64>,
+    pub max_span: usize,
+}
+
+fn loop_extrapolate(table: &[f64], n: usize, lxc: f64) -> f64 {
+    if n <= MAXLOOP {
+        table[n]
+    } else {
+        table[MAXLOOP] + lxc * (n as f64 / MAXLOOP as f64).ln()
+    }
+}
+
+impl<'a> Model<'a> {
+    pub fn new(seq: Vec<u8>, params: &'a Params, pseudo: Option<Vec<f64>>) -> Model<'a> {
+        let n = seq.len();
+        Model {
+            seq,
+            params,
+            pseudo: pseudo.unwrap_or_else(|| vec![0.0; n]),
+            max_span: n,
+        }
+    }
+
+    pub fn can_pair(&self, i: usize, j: usize) -> Option<usize> {
+        if j < i + MIN_HAIRPIN + 1 || j - i >= self.max_span {
+            return None;
+        }
+        pair_type(self.seq[i], self.seq[j])
+    }
+
+    pub fn au_penalty(&self, t: usize) -> f64 {
+        if t >= 2 { self.params.terminal_au } else { 0.0 }
+    }
+
+    fn base(&self, i: usize) -> usize {
+        base_index(self.seq[i])
+    }
+
+    // mismatch, or the one dangle, of the unpaired bases 5' and 3' of a branch of type t
+    fn branch_end(&self, mismatch: &Mismatch, t: usize, five: Option<usize>, three: Option<usize>) -> f64 {
+        let p = self.params;
+        let e = match (five, three) {
+            (Some(a), Some(b)) => mismatch[t][self.base(a)][self.base(b)],
+            (Some(a), None) => p.dangle5[t][self.base(a)],
+            (None, Some(b)) => p.dangle3[t][self.base(b)],
+            (None, None) => 0.0,
+        };
+        e + self.au_penalty(t)
+    }
+
+    // branch (i,j) of the exterior loop
+    pub fn exterior(&self, i: usize, j: usize, t: usize) -> f64 {
+        let five = i.checked_sub(1);
+        let three = Some(j + 1).filter(|&b| b < self.seq.len());
+        self.branch_end(&self.params.mismatch_exterior, t, five, three)
+    }
+
+    // branch (i,j) inside a multiloop
+    pub fn ml_stem(&self, i: usize, j: usize, t: usize) -> f64 {
+        let five = i.checked_sub(1);
+        let three = Some(j + 1).filter(|&b| b < self.seq.len());
+        self.params.ml_intern + self.branch_end(&self.params.mismatch_multi, t, five, three)
+    }
+
+    // multiloop closed by (i,j), seen from inside as a branch of the reversed pair
+    pub fn ml_closing(&self, i: usize, j: usize, t: usize) -> f64 {
+        let p = self.params;
+        p.ml_closing + p.ml_intern + self.branch_end(&p.mismatch_multi, reverse_type(t), Some(j - 1), Some(i + 1))
+    }
+
+    pub fn hairpin(&self, i: usize, j: usize, t: usize) -> f64 {
+        let p = self.params;
+        let u = j - i - 1;
+        if u < MIN_HAIRPIN {
+            return INF;
+        }
+        let special: &[(Vec<u8>, f64)] = match u {
+            3 => &p.triloops,
+            4 => &p.tetraloops,
+            6 => &p.hexaloops,
+            _ => &[],
+        };
+        if let Some((_, e)) = special.iter().find(|(l, _)| l[..] == self.seq[i..=j]) {
+            return *e;
+        }
+        let e = loop_extrapolate(&p.hairpin, u, p.lxc);
+        if u == 3 {
+            return e + self.au_penalty(t);
+        }
+        e + p.mismatch_hairpin[t][self.base(i + 1)][self.base(j - 1)]
+    }
+
+    // loop closed by (i,j) with inner pair (k,l), stacks bulges and interior loops
+    pub fn interior(&self, i: usize, j: usize, k: usize, l: usize, t1: usize, t2: usize) -> f64 {
+        let p = self.params;
+        let u1 = k - i - 1;
+        let u2 = j - l - 1;
+        // the inner pair is read 3'->5' in the tables
+        let rt2 = pair_type(self.seq[l], self.seq[k]).unwrap_or(t2);
+        if u1 == 0 && u2 == 0 {
+            return p.stack[t1][rt2] + self.pseudo[i] + self.pseudo[j] + self.pseudo[k] + self.pseudo[l];
+        }
+        if u1 == 0 || u2 == 0 {
+            let u = u1 + u2;
+            let e = loop_extrapolate(&p.bulge, u, p.lxc);
+            if u == 1 {
+                return e + p.stack[t1][rt2];
+            }
+            return e + self.au_penalty(t1) + self.au_penalty(t2);
+        }
+        // bases inside the loop next to i, j, k and l
+        let (si, sj, sk, sl) = (self.base(i + 1), self.base(j - 1), self.base(k - 1), self.base(l + 1));
+        match (u1, u2) {
+            (1, 1) => {
+                if let Some(int11) = &p.int11 {
+                    return int11[(t1 * NBPAIRS + rt2) * 25 + si * 5 + sj];
+                }
+            }
+            (1, 2) => {
+                if let Some(int21) = &p.int21 {
+                    return int21[(t1 * NBPAIRS + rt2) * 125 + (si * 5 + sl) * 5 + sj];
+                }
+            }
+            (2, 1) => {
+                if let Some(int21) = &p.int21 {
+                    return int21[(rt2 * NBPAIRS + t1) * 125 + (sl * 5 + si) * 5 + sk];
+                }
+            }
+            (2, 2) => {
+                // A C G U only, the pairs without NS
+                if let Some(int22) = &p.int22 {
+                    if si * sk * sl * sj > 0 {
+                        return int22[(t1 * 6 + rt2) * 256 + ((si - 1) * 64 + (sk - 1) * 16 + (sl - 1) * 4 + sj - 1)];
+                    }
+                }
+            }
+            _ => {}
+        }
+        let mismatch = match (u1.min(u2), u1.max(u2)) {
+            (1, n) if n > 2 => &p.mismatch_interior_1n,
+            (2, 3) => &p.mismatch_interior_23,
+            _ => &p.mismatch_interior,
+        };
+        let asym = (p.ninio * (u1 as f64 - u2 as f64).abs()).min(p.ninio_max);
+        loop_extrapolate(&p.interior, u1 + u2, p.lxc) + asym + mismatch[t1][si][sj] + mismatch[rt2][sl][sk]
+    }
+}
+
+pub struct Mfe {
+    pub energy: f64,
+    pub pairs: Vec<Option<usize>>,
+}
+
+pub fn mfe(model: &Model) -> Mfe {
+    let n = model.seq.len();
+    let p = model.params;
+    if n == 0 {
+        return Mfe { energy: 0.0, pairs: Vec::new() };
+    }
+    let idx = |i: usize, j: usize| i * n + j;
+    let mut v = vec![INF; n * n];
+    let mut wm = vec![INF; n * n];
+    let mut wm1 = vec![INF; n * n];
+
+    for d in (MIN_HAIRPIN + 1)..n {
+        for i in 0..n - d {
+            let j = i + d;
+            if let Some(t) = model.can_pair(i, j) {
+                let mut best = model.hairpin(i, j, t);
+                for k in i + 1..(i + MAXLOOP + 2).min(j) {
+                    let u1 = k - i - 1;
+                    let lmin = (k + MIN_HAIRPIN + 1).max(j.saturating_sub(MAXLOOP + 1 - u1));
+                    for l in (lmin..j).rev() {
+                        let vkl = v[idx(k, l)];
+                        if vkl == INF {
+                            continue;
+                        }
+                        if let Some(t2) = pair_type(model.seq[k], model.seq[l]) {
+                            let e = model.interior(i, j, k, l, t, t2) + vkl;
+                            if e < best {
+                                best = e;
+                            }
+                        }
+                    }
+                }
+                // multiloop: at least two branches inside (i,j)
+                for u in i + 2..j.saturating_sub(1) {
+                    let e = wm[idx(i + 1, u - 1)] + wm1[idx(u, j - 1)];
+                    if e + model.ml_closing(i, j, t) < best {
+                        best = e + model.ml_closing(i, j, t);
+                    }
+                }
+                v[idx(i, j)] = best;
+            }
+
+            // one branch starting at i, trailing unpaired bases
+            let mut b1 = INF;
+            if let Some(t) = model.can_pair(i, j) {
+                b1 = v[idx(i, j)] + model.ml_stem(i, j, t);
+            }
+            b1 = b1.min(wm1[idx(i, j - 1)] + p.ml_base);
+            wm1[idx(i, j)] = b1;
+
+            let mut m = INF;
+            for u in i..j {
+                let left_unpaired = (u - i) as f64 * p.ml_base + wm1[idx(u, j)];
+                m = m.min(left_unpaired);
+                if u > i {
+                    m = m.min(wm[idx(i, u - 1)] + wm1[idx(u, j)]);
+                }
+            }
+            wm[idx(i, j)] = m;
+        }
+    }
+
+    // exterior loop
+    let mut w = vec![0.0; n + 1];
+    for j in 1..=n {
+        let mut best = w[j - 1];
+        for i in 0..j {
+            if let Some(t) = model.can_pair(i, j - 1) {
+                let e = w[i] + v[idx(i, j - 1)] + model.exterior(i, j - 1, t);
+                if e < best {
+                    best = e;
+                }
+            }
+        }
+        w[j] = best;
+    }
+
+    let mut pairs = vec![None; n];
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    // 0: exterior prefix, 1: V, 2: WM, 3: WM1
+    let mut stack: Vec<(u8, usize, usize)> = vec![(0, 0, n)];
+    while let Some((kind, i, j)) = stack.pop() {
+        match kind {
+            0 => {
+                if j == 0 {
+                    continue;
+                }
+                if close(w[j], w[j - 1]) {
+                    stack.push((0, 0, j - 1));
+                    continue;
+                }
+                for k in 0..j {
+                    if let Some(t) = model.can_pair(k, j - 1) {
+                        if close(w[j], w[k] + v[idx(k, j - 1)] + model.exterior(k, j - 1, t)) {
+                            stack.push((0, 0, k));
+                            stack.push((1, k, j - 1));
+                            break;
+                        }
+                    }
+                }
+            }
+            1 => {
+                pairs[i] = Some(j);
+                pairs[j] = Some(i);
+                let t = model.can_pair(i, j).unwrap();
+                let target = v[idx(i, j)];
+                if close(target, model.hairpin(i, j, t)) {
+                    continue;
+                }
+                let mut found = false;
+                'outer: for k in i + 1..(i + MAXLOOP + 2).min(j) {
+                    let u1 = k - i - 1;
+                    let lmin = (k + MIN_HAIRPIN + 1).max(j.saturating_sub(MAXLOOP + 1 - u1));
+                    for l in (lmin..j).rev() {
+                        let vkl = v[idx(k, l)];
+                        if vkl == INF {
+                            continue;
+                        }
+                        if let Some(t2) = pair_type(model.seq[k], model.seq[l]) {
+                            if close(target, model.interior(i, j, k, l, t, t2) + vkl) {
+                                stack.push((1, k, l));
+                                found = true;
+                                break 'outer;
+                            }
+                        }
+                    }
+                }
+                if !found {
+                    for u in i + 2..j.saturating_sub(1) {
+                        let e = wm[idx(i + 1, u - 1)] + wm1[idx(u, j - 1)] + model.ml_closing(i, j, t);
+                        if close(target, e) {
+                            stack.push((2, i + 1, u - 1));
+                            stack.push((3, u, j - 1));
+                            break;
+                        }
+                    }
+                }
+            }
+            2 => {
+                let target = wm[idx(i, j)];
+                for u in i..j {
+                    if close(target, (u - i) as f64 * p.ml_base + wm1[idx(u, j)]) {
+                        stack.push((3, u, j));
+                        break;
+                    }
+                    if u > i && close(target, wm[idx(i, u - 1)] + wm1[idx(u, j)]) {
+                        stack.push((2, i, u - 1));
+                        stack.push((3, u, j));
+                        break;
+                    }
+                }
+            }
+            _ => {
+                let target = wm1[idx(i, j)];
+                if let Some(t) = model.can_pair(i, j) {
+                    if close(target, v[idx(i, j)] + model.ml_stem(i, j, t)) {
+                        stack.push((1, i, j));
+                        continue;
+                    }
+                }
+                stack.push((3, i, j - 1));
+            }
+        }
+    }
+
+    Mfe { energy: w[n], pairs }
+}
+
+pub fn dot_bracket(pairs: &[Option<usize>]) -> String {
+    pairs
+        .iter()
+        .enumerate()
+        .map(|(i, p)| match p {
+            Some(j) if *j > i => '(',
+            Some(_) => ')',
+            None => '.',
+        })
+        .collect()
+}
+
+pub fn ct_lines(name: &str, seq: &[u8], pairs: &[Option<usize>], energy: f64) -> Vec<String> {
+    let n = seq.len();
+    let mut lines = vec![format!("{:>5}  ENERGY = {:.2}  {}", n, energy, name)];
+    for i in 0..n {
+        lines.push(format!(
+            "{:>5} {} {:>5} {:>5} {:>5} {:>5}",
+            i + 1,
+            seq[i] as char,
+            i,
+            if i + 1 < n { i + 2 } else { 0 },
+            pairs[i].map(|j| j + 1).unwrap_or(0),
+            i + 1
+        ));
+    }
+    lines
+}
+
+// free energy of a given structure under the same model, loops decomposed from the pair table
+pub fn energy_of(model: &Model, pairs: &[Option<usize>]) -> f64 {
+    let n = pairs.len();
+    let p = model.params;
+    let mut e = 0.0;
+    // exterior loop
+    let mut i = 0;
+    while i < n {
+        match pairs[i] {
+            Some(j) if j > i => {
+                e += model.exterior(i, j, pair_type(model.seq[i], model.seq[j]).unwrap());
+                i = j + 1;
+            }
+            _ => i += 1,
+        }
+    }
+    for i in 0..n {
+        let j = match pairs[i] {
+            Some(j) if j > i => j,
+            _ => continue,
+        };
+        let t = pair_type(model.seq[i], model.seq[j]).unwrap();
+        let mut branches = Vec::new();
+        let mut k = i + 1;
+        while k < j {
+            match pairs[k] {
+                Some(l) if l > k => {
+                    branches.push((k, l));
+                    k = l + 1;
+                }
+                _ => k += 1,
+            }
+        }
+        e += match branches.len() {
+            0 => model.hairpin(i, j, t),
+            1 => {
+                let (k, l) = branches[0];
+                model.interior(i, j, k, l, t, pair_type(model.seq[k], model.seq[l]).unwrap())
+            }
+            _ => {
+                let paired: usize = branches.iter().map(|(k, l)| l - k + 1).sum();
+                let unpaired = (j - i - 1 - paired) as f64;
+                model.ml_closing(i, j, t) + p.ml_base * unpaired
+                    + branches.iter().map(|&(k, l)| model.ml_stem(k, l, pair_type(model.seq[k], model.seq[l]).unwrap())).sum::<f64>()
+            }
+        };
+    }
+    e
+}
+
+// RT at 37C in kcal/mol
+pub const RT: f64 = 0.0019872 * 310.15;
+
+fn boltz(e: f64) -> f64 {
+    if e == INF { 0.0 } else { (-e / RT).exp() }
+}
+
+pub struct Partition {
+    pub n: usize,
+    // ln of the ensemble partition function
+    pub ln_q: f64,
+    // base pair probabilities, row major n*n with i < j filled
+    pub bpp: Vec<f64>,
+}
+
+impl Partition {
+    pub fn prob(&self, i: usize, j: usize) -> f64 {
+        if i < j { self.bpp[i * self.n + j] } else { self.bpp[j * self.n + i] }
+    }
+
+    pub fn pairing(&self) -> Vec<f64> {
+        (0..self.n).map(|i| (0..self.n).filter(|&j| j != i).map(|j| self.prob(i, j)).sum::<f64>().min(1.0)).collect()
+    }
+
+    // Shannon entropy per nucleotide, log10 as in Superfold
+    pub fn entropy(&self) -> Vec<f64> {
+        (0..self.n)
+            .map(|i| {
+                let mut h = 0.0;
+                let mut paired = 0.0;
+                for j in 0..self.n {
+                    let p = if j == i { 0.0 } else { self.prob(i, j) };
+                    if p > 0.0 {
+                        h -= p * p.log10();
+                        paired += p;
+                    }
+                }
+                let u = 1.0 - paired;
+                if u > 0.0 {
+                    h -= u * u.log10();
+                }
+                h.max(0.0)
+            })
+            .collect()
+    }
+}
+
+// McCaskill inside/outside over the same decomposition as mfe(); quantities are
+// scaled by pf_scale per nucleotide to stay inside f64 range
+pub fn partition(model: &Model, mfe_energy: f64) -> Partition {
+    let n = model.seq.len();
+    let p = model.params;
+    if n == 0 {
+        return Partition { n, ln_q: 0.0, bpp: Vec::new() };
+    }
+    let idx = |i: usize, j: usize| i * n + j;
+    let pf_scale = (-1.07 * mfe_energy / RT / n as f64).exp().max(1.0);
+    let scale: Vec<f64> = (0..=n + 1).map(|k| pf_scale.powi(-(k as i32))).collect();
+
+    let ml_base = boltz(p.ml_base) * scale[1];
+    let ml_unpaired: Vec<f64> = (0..=n).map(|k| boltz(p.ml_base * k as f64) * scale[k]).collect();
+
+    let mut qb = vec![0.0; n * n];
+    let mut qm = vec![0.0; n * n];
+    let mut qm1 = vec![0.0; n * n];
+
+    for d in (MIN_HAIRPIN + 1)..n {
+        for i in 0..n - d {
+            let j = i + d;
+            if let Some(t) = model.can_pair(i, j) {
+                let mut q = boltz(model.hairpin(i, j, t)) * scale[d + 1];
+                for k in i + 1..(i + MAXLOOP + 2).min(j) {
+                    let u1 = k - i - 1;
+                    let lmin = (k + MIN_HAIRPIN + 1).max(j.saturating_sub(MAXLOOP + 1 - u1));
+                    for l in lmin..j {
+                        let inner = qb[idx(k, l)];
+                        if inner == 0.0 {
+                            continue;
+                        }
+                        let t2 = pair_type(model.seq[k], model.seq[l]).unwrap();
+                        q += boltz(model.interior(i, j, k, l, t, t2)) * inner * scale[u1 + j - l + 1];
+                    }
+                }
+                let close = boltz(model.ml_closing(i, j, t)) * scale[2];
+                for u in i + 2..j.saturating_sub(1) {
+                    q += close * qm[idx(i + 1, u - 1)] * qm1[idx(u, j - 1)];
+                }
+                qb[idx(i, j)] = q;
+            }
+
+            let mut b1 = qm1[idx(i, j - 1)] * ml_base;
+            if let Some(t) = model.can_pair(i, j) {
+                b1 += qb[idx(i, j)] * boltz(model.ml_stem(i, j, t));
+            }
+            qm1[idx(i, j)] = b1;
+
+            let mut m = 0.0;
+            for u in i..j {
+                m += ml_unpaired[u - i] * qm1[idx(u, j)];
+                if u > i {
+                    m += qm[idx(i, u - 1)] * qm1[idx(u, j)];
+                }
+            }
+            qm[idx(i, j)] = m;
+        }
+    }
+
+    // exterior loop, z[j] covers the prefix 0..j
+    let ext = |i: usize, j: usize| -> f64 {
+        match model.can_pair(i, j) {
+            Some(t) => qb[idx(i, j)] * boltz(model.exterior(i, j, t)),
+            None => 0.0,
+        }
+    };
+    let mut z = vec![0.0; n + 1];
+    z[0] = 1.0;
+    for j in 1..=n {
+        let mut q = z[j - 1] * scale[1];
+        for (i, zi) in z[..j].iter().enumerate() {
+            q += zi * ext(i, j - 1);
+        }
+        z[j] = q;
+    }
+
+    // outside pass, the adjoint of every inside rule in reverse order
+    let mut oz = vec![0.0; n + 1];
+    let mut ob = vec![0.0; n * n];
+    let mut om = vec![0.0; n * n];
+    let mut om1 = vec![0.0; n * n];
+    oz[n] = 1.0;
+    for j in (1..=n).rev() {
+        let o = oz[j];
+        if o == 0.0 {
+            continue;
+        }
+        oz[j - 1] += o * scale[1];
+        for i in 0..j {
+            if let Some(t) = model.can_pair(i, j - 1) {
+                let w = boltz(model.exterior(i, j - 1, t));
+                oz[i] += o * qb[idx(i, j - 1)] * w;
+                ob[idx(i, j - 1)] += o * z[i] * w;
+            }
+        }
+    }
+    for d in ((MIN_HAIRPIN + 1)..n).rev() {
+        for i in 0..n - d {
+            let j = i + d;
+            let o = om[idx(i, j)];
+            if o != 0.0 {
+                for u in i..j {
+                    om1[idx(u, j)] += o * ml_unpaired[u - i];
+                    if u > i {
+                        om[idx(i, u - 1)] += o * qm1[idx(u, j)];
+                        om1[idx(u, j)] += o * qm[idx(i, u - 1)];
+                    }
+                }
+            }
+            let o = om1[idx(i, j)];
+            if o != 0.0 {
+                om1[idx(i, j - 1)] += o * ml_base;
+                if let Some(t) = model.can_pair(i, j) {
+                    ob[idx(i, j)] += o * boltz(model.ml_stem(i, j, t));
+                }
+            }
+            let o = ob[idx(i, j)];
+            if o == 0.0 || qb[idx(i, j)] == 0.0 {
+                continue;
+            }
+            let t = model.can_pair(i, j).unwrap();
+            for k in i + 1..(i + MAXLOOP + 2).min(j) {
+                let u1 = k - i - 1;
+                let lmin = (k + MIN_HAIRPIN + 1).max(j.saturating_sub(MAXLOOP + 1 - u1));
+                for l in lmin..j {
+                    if qb[idx(k, l)] == 0.0 {
+                        continue;
+                    }
+                    let t2 = pair_type(model.seq[k], model.seq[l]).unwrap();
+                    ob[idx(k, l)] += o * boltz(model.interior(i, j, k, l, t, t2)) * scale[u1 + j - l + 1];
+                }
+            }
+            let close = o * boltz(model.ml_closing(i, j, t)) * scale[2];
+            for u in i + 2..j.saturating_sub(1) {
+                om[idx(i + 1, u - 1)] += close * qm1[idx(u, j - 1)];
+                om1[idx(u, j - 1)] += close * qm[idx(i + 1, u - 1)];
+            }
+        }
+    }
+
+    let total = z[n];
+    let mut bpp = vec![0.0; n * n];
+    for i in 0..n {
+        for j in i + 1..n {
+            if qb[idx(i, j)] > 0.0 {
+                bpp[idx(i, j)] = (ob[idx(i, j)] * qb[idx(i, j)] / total).min(1.0);
+            }
+        }
+    }
+    Partition { n, ln_q: total.ln() + n as f64 * pf_scale.ln(), bpp }
+}
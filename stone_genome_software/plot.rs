@@ -0,0 +1,481 @@
+mod provenance;
+
+use clap::{CommandFactory, Parser};
+use provenance::Block;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::time::Instant;
+
+#[derive(Parser)]
+#[command(name="plot", author="hyf", version="1.0", about="reactivity profile and arc diagram per transcript as SVG and PDF", long_about = None)]
+struct Cli {
+    /// scored csv, fold _pp.csv or a .shape/.map file
+    #[arg(short,long)]
+    reactivity: Option<String>,
+    #[arg(short,long, default_value = "norm_model")]
+    column: String,
+    /// structures to draw as arcs, .dot or .ct
+    #[arg(short,long)]
+    structure: Option<String>,
+    /// prefix of fold --partition output, arcs are coloured by <prefix>_<name>.dp
+    #[arg(short,long)]
+    dp: Option<String>,
+    /// score bin edges for the bar colours
+    #[arg(long, value_delimiter = ',', default_value = "0.4,0.7")]
+    bins: Vec<f64>,
+    /// only plot these transcripts
+    #[arg(long, value_delimiter = ',')]
+    transcripts: Vec<String>,
+    /// output prefix, writes <prefix>_<name>.svg and <prefix>_<name>.pdf
+    #[arg(short,long)]
+    output: String,
+}
+
+type Rgb = (u8, u8, u8);
+
+const BAR_COLORS: [Rgb; 3] = [(40, 40, 40), (242, 140, 40), (204, 32, 32)];
+const NO_DATA: Rgb = (200, 200, 200);
+// Superfold style arc colours from high to low probability
+const ARC_COLORS: [(f64, Rgb); 4] = [(0.8, (0, 150, 60)), (0.3, (40, 90, 200)), (0.1, (240, 190, 30)), (0.0, (170, 170, 170))];
+
+enum Shape {
+    Rect { x: f64, y: f64, w: f64, h: f64, color: Rgb },
+    Line { x1: f64, y1: f64, x2: f64, y2: f64, color: Rgb, width: f64 },
+    // half ellipse from x1 to x2 on baseline y, bulging up by h
+    Arc { x1: f64, x2: f64, y: f64, h: f64, color: Rgb, width: f64 },
+    Text { x: f64, y: f64, size: f64, text: String, middle: bool },
+}
+
+// shapes in SVG coordinates (origin top left), rendered to SVG or to a one page PDF
+struct Canvas {
+    width: f64,
+    height: f64,
+    shapes: Vec<Shape>,
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn pdf_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('(', "\\(").replace(')', "\\)")
+}
+
+impl Canvas {
+    // the provenance lines go into <metadata>, a comment could not hold the "--" of the options
+    fn to_svg(&self, provenance: &[String]) -> String {
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+            w = self.width,
+            h = self.height
+        );
+        if !provenance.is_empty() {
+            out.push_str("<metadata>\n");
+            for line in provenance {
+                out.push_str(&xml_escape(line));
+                out.push('\n');
+            }
+            out.push_str("</metadata>\n");
+        }
+        out.push_str(&format!("<rect width=\"{w}\" height=\"{h}\" fill=\"white\"/>\n", w = self.width, h = self.height));
+        for s in &self.shapes {
+            let line = match s {
+                Shape::Rect { x, y, w, h, color } => format!(
+                    "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" fill=\"rgb({},{},{})\"/>",
+                    x, y, w, h, color.0, color.1, color.2
+                ),
+                Shape::Line { x1, y1, x2, y2, color, width } => format!(
+                    "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"rgb({},{},{})\" stroke-width=\"{}\"/>",
+                    x1, y1, x2, y2, color.0, color.1, color.2, width
+                ),
+                Shape::Arc { x1, x2, y, h, color, width } => format!(
+                    "<path d=\"M {:.2} {:.2} A {:.2} {:.2} 0 0 1 {:.2} {:.2}\" fill=\"none\" stroke=\"rgb({},{},{})\" stroke-width=\"{}\"/>",
+                    x1, y, (x2 - x1) / 2.0, h, x2, y, color.0, color.1, color.2, width
+                ),
+                Shape::Text { x, y, size, text, middle } => format!(
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-family=\"Helvetica,Arial,sans-serif\" font-size=\"{}\"{}>{}</text>",
+                    x, y, size, if *middle { " text-anchor=\"middle\"" } else { "" }, xml_escape(text)
+                ),
+            };
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+
+    // provenance as '%' comment lines after the version line, before the offsets are counted
+    fn to_pdf(&self, provenance: &[String]) -> Vec<u8> {
+        let h = self.height;
+        let rgb = |c: &Rgb| format!("{:.3} {:.3} {:.3}", c.0 as f64 / 255.0, c.1 as f64 / 255.0, c.2 as f64 / 255.0);
+        let mut stream = String::new();
+        for s in &self.shapes {
+            match s {
+                Shape::Rect { x, y, w, h: rh, color } => {
+                    stream.push_str(&format!("{} rg {:.2} {:.2} {:.2} {:.2} re f\n", rgb(color), x, h - y - rh, w, rh));
+                }
+                Shape::Line { x1, y1, x2, y2, color, width } => {
+                    stream.push_str(&format!("{} RG {} w {:.2} {:.2} m {:.2} {:.2} l S\n", rgb(color), width, x1, h - y1, x2, h - y2));
+                }
+                Shape::Arc { x1, x2, y, h: ah, color, width } => {
+                    // two cubic Beziers approximating the half ellipse
+                    let k = 0.552_284_75;
+                    let base = h - y;
+                    let cx = (x1 + x2) / 2.0;
+                    let rx = (x2 - x1) / 2.0;
+                    stream.push_str(&format!(
+                        "{} RG {} w {:.2} {:.2} m {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c {:.2} {:.2} {:.2} {:.2} {:.2} {:.2} c S\n",
+                        rgb(color), width,
+                        x1, base,
+                        x1, base + k * ah, cx - k * rx, base + ah, cx, base + ah,
+                        cx + k * rx, base + ah, x2, base + k * ah, x2, base
+                    ));
+                }
+                Shape::Text { x, y, size, text, middle } => {
+                    // Helvetica averages about half an em per character
+                    let shift = if *middle { text.len() as f64 * size * 0.25 } else { 0.0 };
+                    stream.push_str(&format!("0 0 0 rg BT /F1 {} Tf {:.2} {:.2} Td ({}) Tj ET\n", size, x - shift, h - y, pdf_escape(text)));
+                }
+            }
+        }
+
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents 4 0 R /Resources << /Font << /F1 5 0 R >> >> >>",
+                self.width, self.height
+            ),
+            format!("<< /Length {} >>\nstream\n{}endstream", stream.len(), stream),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+        let mut out = b"%PDF-1.4\n".to_vec();
+        for line in provenance {
+            out.extend(format!("%{}\n", &line[1..]).as_bytes());
+        }
+        let mut offsets = Vec::new();
+        for (i, obj) in objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, obj).as_bytes());
+        }
+        let xref = out.len();
+        out.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes());
+        for o in offsets {
+            out.extend(format!("{:010} 00000 n \n", o).as_bytes());
+        }
+        out.extend(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", objects.len() + 1, xref).as_bytes());
+        out
+    }
+}
+
+#[derive(Debug, Default)]
+struct Structure {
+    seq: String,
+    pairs: Vec<Option<usize>>,
+}
+
+fn pairs_from_dot_bracket(db: &str) -> Vec<Option<usize>> {
+    let mut pairs = vec![None; db.len()];
+    let mut stacks: HashMap<char, Vec<usize>> = HashMap::new();
+    for (i, c) in db.chars().enumerate() {
+        let open = match c {
+            '(' | '[' | '{' | '<' => Some(c),
+            ')' => Some('('),
+            ']' => Some('['),
+            '}' => Some('{'),
+            '>' => Some('<'),
+            _ => None,
+        };
+        match open {
+            Some(o) if o == c => stacks.entry(o).or_default().push(i),
+            Some(o) => {
+                if let Some(j) = stacks.entry(o).or_default().pop() {
+                    pairs[i] = Some(j);
+                    pairs[j] = Some(i);
+                }
+            }
+            None => {}
+        }
+    }
+    pairs
+}
+
+fn read_structures(file_path: &str) -> io::Result<HashMap<String, Structure>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut map: HashMap<String, Structure> = HashMap::new();
+    if file_path.to_lowercase().ends_with(".ct") {
+        let mut name = String::new();
+        let mut remain = 0usize;
+        let mut current = Structure::default();
+        for line in reader.lines() {
+            let line = line?;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if remain == 0 {
+                remain = fields[0].parse().unwrap_or(0);
+                name = fields.last().unwrap_or(&"").to_string();
+                current = Structure::default();
+                continue;
+            }
+            if fields.len() < 6 {
+                continue;
+            }
+            current.seq.push_str(fields[1]);
+            current.pairs.push(fields[4].parse::<usize>().ok().filter(|p| *p > 0).map(|p| p - 1));
+            remain -= 1;
+            if remain == 0 {
+                map.insert(name.clone(), std::mem::take(&mut current));
+            }
+        }
+    } else {
+        let mut name = String::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(h) = line.strip_prefix('>') {
+                name = h.split_whitespace().next().unwrap_or("").to_string();
+                map.insert(name.clone(), Structure::default());
+            } else if let Some(s) = map.get_mut(&name) {
+                if line.chars().any(|c| c.is_ascii_alphabetic()) {
+                    s.seq.push_str(line);
+                } else if let Some(db) = line.split_whitespace().next() {
+                    s.pairs.extend(pairs_from_dot_bracket(db));
+                }
+            }
+        }
+    }
+    Ok(map)
+}
+
+// transcript -> 1-based position -> reactivity, "" holds files without transcript ids
+fn read_reactivity(file_path: &str, column: &str) -> io::Result<HashMap<String, HashMap<usize, f64>>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut map: HashMap<String, HashMap<usize, f64>> = HashMap::new();
+    let lower = file_path.to_lowercase();
+    if lower.ends_with(".shape") || lower.ends_with(".map") {
+        let entry = map.entry(String::new()).or_default();
+        for line in reader.lines() {
+            let line = line?;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                if let (Ok(p), Ok(v)) = (parts[0].parse::<usize>(), parts[1].parse::<f64>()) {
+                    entry.insert(p, v);
+                }
+            }
+        }
+        return Ok(map);
+    }
+    let mut lines = reader.lines();
+    let header = match provenance::csv_header(&mut lines)? {
+        Some(h) => h,
+        None => return Ok(map),
+    };
+    let idx = |name: &str| header.iter().position(|h| h == name);
+    let id_idx = idx("Transcript").or_else(|| idx("transcriptid")).or_else(|| idx("ChrID"));
+    let pos_idx = ["Position", "transcript_position", "pipe_truncation_ChrPos", "ChrPos", "position"]
+        .iter()
+        .find_map(|c| idx(c))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no position column", file_path)))?;
+    let val_idx = idx(column)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no {} column", file_path, column)))?;
+    for line in lines {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let parts = provenance::fields(&line);
+        let position = match parts.get(pos_idx).and_then(|p| p.parse::<f64>().ok()) {
+            Some(p) => p as usize,
+            None => continue,
+        };
+        if let Some(v) = parts.get(val_idx).and_then(|v| v.parse::<f64>().ok()) {
+            let id = id_idx.and_then(|i| parts.get(i)).map(|s| s.to_string()).unwrap_or_default();
+            map.entry(id).or_default().insert(position, v);
+        }
+    }
+    Ok(map)
+}
+
+// fold --partition dot plot text: n, header, then i j -log10(p); '#' lines are skipped
+fn read_dp(file_path: &str) -> io::Result<HashMap<(usize, usize), f64>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut map = HashMap::new();
+    let lines = reader.lines().filter(|l| !matches!(l, Ok(l) if l.starts_with('#')));
+    for line in lines.skip(2) {
+        let line = line?;
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 3 {
+            continue;
+        }
+        if let (Ok(i), Ok(j), Ok(l)) = (parts[0].parse::<usize>(), parts[1].parse::<usize>(), parts[2].parse::<f64>()) {
+            map.insert((i.min(j) - 1, i.max(j) - 1), 10f64.powf(-l));
+        }
+    }
+    Ok(map)
+}
+
+fn bar_color(v: f64, bins: &[f64]) -> Rgb {
+    if v.is_nan() {
+        return NO_DATA;
+    }
+    let bin = bins.iter().filter(|b| v >= **b).count();
+    BAR_COLORS[bin.min(BAR_COLORS.len() - 1)]
+}
+
+fn arc_color(p: f64) -> Rgb {
+    ARC_COLORS.iter().find(|(min, _)| p >= *min).map(|c| c.1).unwrap_or(NO_DATA)
+}
+
+fn draw(name: &str, n: usize, reactivity: Option<&Vec<f64>>, pairs: Option<&Vec<Option<usize>>>, dp: Option<&HashMap<(usize, usize), f64>>, bins: &[f64]) -> Canvas {
+    let left = 60.0;
+    let plot_w = 1200.0;
+    let arc_h = 260.0;
+    let bar_h = 140.0;
+    let top = 40.0;
+    let baseline = top + arc_h;
+    let xs = plot_w / n.max(1) as f64;
+    let mut shapes = Vec::new();
+
+    shapes.push(Shape::Text { x: left, y: 20.0, size: 14.0, text: name.to_string(), middle: false });
+
+    if let Some(pairs) = pairs {
+        let max_span = pairs.iter().enumerate().filter_map(|(i, p)| p.filter(|j| *j > i).map(|j| j - i)).max().unwrap_or(1) as f64;
+        for (i, p) in pairs.iter().enumerate() {
+            if let Some(j) = *p {
+                if j <= i || j >= n {
+                    continue;
+                }
+                let color = match dp {
+                    Some(dp) => arc_color(*dp.get(&(i, j)).unwrap_or(&0.0)),
+                    None => (60, 60, 60),
+                };
+                shapes.push(Shape::Arc {
+                    x1: left + (i as f64 + 0.5) * xs,
+                    x2: left + (j as f64 + 0.5) * xs,
+                    y: baseline,
+                    h: (arc_h - 10.0) * (j - i) as f64 / max_span,
+                    color,
+                    width: 0.8,
+                });
+            }
+        }
+    }
+    shapes.push(Shape::Line { x1: left, y1: baseline, x2: left + plot_w, y2: baseline, color: (0, 0, 0), width: 1.0 });
+
+    let bar_top = baseline + 10.0;
+    let zero = bar_top + bar_h;
+    if let Some(r) = reactivity {
+        let max = r.iter().cloned().filter(|v| !v.is_nan()).fold(1.0, f64::max);
+        for (i, v) in r.iter().enumerate() {
+            let h = if v.is_nan() { bar_h * 0.05 } else { bar_h * v.max(0.0) / max };
+            shapes.push(Shape::Rect { x: left + i as f64 * xs, y: zero - h, w: xs.max(0.2), h, color: bar_color(*v, bins) });
+        }
+        shapes.push(Shape::Line { x1: left, y1: bar_top, x2: left, y2: zero, color: (0, 0, 0), width: 1.0 });
+        shapes.push(Shape::Text { x: left - 8.0, y: bar_top + 4.0, size: 9.0, text: format!("{:.2}", max), middle: true });
+        shapes.push(Shape::Text { x: left - 8.0, y: zero, size: 9.0, text: "0".to_string(), middle: true });
+    }
+    shapes.push(Shape::Line { x1: left, y1: zero, x2: left + plot_w, y2: zero, color: (0, 0, 0), width: 1.0 });
+
+    // nucleotide axis
+    let tick = [10, 50, 100, 500, 1000, 5000].iter().cloned().find(|t| n / t <= 12).unwrap_or(10000);
+    let mut p = tick;
+    while p <= n {
+        let x = left + (p as f64 - 0.5) * xs;
+        shapes.push(Shape::Line { x1: x, y1: zero, x2: x, y2: zero + 5.0, color: (0, 0, 0), width: 1.0 });
+        shapes.push(Shape::Text { x, y: zero + 17.0, size: 9.0, text: p.to_string(), middle: true });
+        p += tick;
+    }
+
+    let legend_y = zero + 40.0;
+    let mut x = left;
+    let labels = [format!("< {}", bins.first().unwrap_or(&0.0)), format!("{}-{}", bins.first().unwrap_or(&0.0), bins.get(1).unwrap_or(&1.0)), format!(">= {}", bins.last().unwrap_or(&1.0))];
+    for (k, label) in labels.iter().enumerate() {
+        shapes.push(Shape::Rect { x, y: legend_y - 9.0, w: 10.0, h: 10.0, color: BAR_COLORS[k] });
+        shapes.push(Shape::Text { x: x + 14.0, y: legend_y, size: 10.0, text: label.clone(), middle: false });
+        x += 90.0;
+    }
+    if dp.is_some() {
+        for (min, color) in ARC_COLORS.iter() {
+            shapes.push(Shape::Line { x1: x, y1: legend_y - 4.0, x2: x + 14.0, y2: legend_y - 4.0, color: *color, width: 2.0 });
+            shapes.push(Shape::Text { x: x + 18.0, y: legend_y, size: 10.0, text: format!("p >= {}", min), middle: false });
+            x += 80.0;
+        }
+    }
+
+    Canvas { width: left + plot_w + 30.0, height: legend_y + 20.0, shapes }
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let cli = Cli::parse();
+    let structures = match &cli.structure {
+        Some(s) => read_structures(s)?,
+        None => HashMap::new(),
+    };
+    let reactivity = match &cli.reactivity {
+        Some(r) => read_reactivity(r, &cli.column)?,
+        None => HashMap::new(),
+    };
+
+    let mut block = Block::new("plot", Cli::command().get_version().unwrap_or("unknown"));
+    if let Some(r) = &cli.reactivity {
+        block.input(r).set("column", cli.column.clone());
+    }
+    if let Some(s) = &cli.structure {
+        block.input(s);
+    }
+    if let Some(dp) = &cli.dp {
+        block.set("dp", dp.clone());
+    }
+    block.set("bins", cli.bins.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(","));
+    let inputs: Vec<&str> = [cli.reactivity.as_ref(), cli.structure.as_ref()].into_iter().flatten().map(|s| s.as_str()).collect();
+    let comments: Vec<String> = provenance::upstream(&inputs, '#').into_iter().chain(block.lines('#')).collect();
+
+    let mut names: Vec<String> = structures.keys().chain(reactivity.keys()).filter(|k| !k.is_empty()).cloned().collect();
+    if names.is_empty() && reactivity.contains_key("") {
+        names.push("transcript".to_string());
+    }
+    names.sort();
+    names.dedup();
+    if !cli.transcripts.is_empty() {
+        names.retain(|n| cli.transcripts.contains(n));
+    }
+
+    for name in names {
+        let structure = structures.get(&name);
+        let values = reactivity.get(&name).or_else(|| reactivity.get(""));
+        let n = structure
+            .map(|s| s.pairs.len().max(s.seq.len()))
+            .filter(|l| *l > 0)
+            .or_else(|| values.and_then(|v| v.keys().max().cloned()))
+            .unwrap_or(0);
+        if n == 0 {
+            continue;
+        }
+        let profile: Option<Vec<f64>> = values.map(|v| (1..=n).map(|p| *v.get(&p).unwrap_or(&f64::NAN)).collect());
+        let dp = match &cli.dp {
+            Some(prefix) => match read_dp(&format!("{}_{}.dp", prefix, name)) {
+                Ok(dp) => Some(dp),
+                Err(e) => {
+                    eprintln!("{}: no dot plot ({}), arcs left uncoloured", name, e);
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let canvas = draw(&name, n, profile.as_ref(), structure.map(|s| &s.pairs), dp.as_ref(), &cli.bins);
+        File::create(format!("{}_{}.svg", cli.output, name))?.write_all(canvas.to_svg(&comments).as_bytes())?;
+        File::create(format!("{}_{}.pdf", cli.output, name))?.write_all(&canvas.to_pdf(&comments))?;
+        println!("{} plotted", name);
+    }
+
+    println!("Total runtime {:?}", now.elapsed());
+    Ok(())
+}
@@ -0,0 +1,168 @@
+#![allow(dead_code)]
+// Comment lines at the top of every output saying which tool made it, from which inputs, when and
+// with which settings: "#stone<TAB>key<TAB>value" ('@' instead of '#' in the zip files). A block
+// starts with its "tool" line; the blocks of the inputs are copied in front of a tool's own block,
+// so the whole history travels with the file. `stone info <file>` prints it.
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const TAG: &str = "stone";
+
+pub fn sha256_file(path: &std::path::Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// SOURCE_DATE_EPOCH pins the date for reproducible outputs
+fn now_utc() -> String {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    let (days, rem) = ((secs / 86400) as i64, secs % 86400);
+    // days since 1970-01-01 to y-m-d, Howard Hinnant's civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, m, d, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+fn command_line() -> String {
+    std::env::args()
+        .map(|a| if a.is_empty() || a.contains(char::is_whitespace) { format!("'{}'", a) } else { a })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Metadata of one tool run.
+pub struct Block {
+    pub entries: Vec<(String, String)>,
+}
+
+impl Block {
+    pub fn new(tool: &str, version: &str) -> Block {
+        let entries = vec![
+            ("tool".to_string(), format!("{} {}", tool, version)),
+            ("command".to_string(), command_line()),
+            ("date".to_string(), now_utc()),
+        ];
+        Block { entries }
+    }
+
+    pub fn set(&mut self, key: &str, value: impl Into<String>) -> &mut Block {
+        self.entries.push((key.to_string(), value.into()));
+        self
+    }
+
+    /// "input <path> sha256:<hex>", unreadable files are recorded as such.
+    pub fn input(&mut self, path: &str) -> &mut Block {
+        let hash = match sha256_file(std::path::Path::new(path)) {
+            Ok(h) => format!("sha256:{}", h),
+            Err(e) => format!("unreadable: {}", e),
+        };
+        self.set("input", format!("{}\t{}", path, hash))
+    }
+
+    /// A samtools-indexed reference is identified by the hash of its .fai.
+    pub fn reference(&mut self, path: &str) -> &mut Block {
+        let hash = match sha256_file(std::path::Path::new(&format!("{}.fai", path))) {
+            Ok(h) => format!("fai-sha256:{}", h),
+            Err(e) => format!("unreadable: {}", e),
+        };
+        self.set("reference", format!("{}\t{}", path, hash))
+    }
+
+    pub fn extend(&mut self, entries: Vec<(String, String)>) -> &mut Block {
+        self.entries.extend(entries);
+        self
+    }
+
+    pub fn lines(&self, prefix: char) -> Vec<String> {
+        self.entries.iter().map(|(k, v)| format!("{}{}\t{}\t{}", prefix, TAG, k, v)).collect()
+    }
+}
+
+/// The "stone\t…" lines in the leading comment ('#' or '@') lines of a file, without the prefix.
+pub fn read(path: &str) -> io::Result<Vec<String>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut out = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if !line.starts_with('#') && !line.starts_with('@') {
+            break;
+        }
+        if line[1..].starts_with(TAG) && line[1 + TAG.len()..].starts_with('\t') {
+            out.push(line[1..].to_string());
+        }
+    }
+    Ok(out)
+}
+
+/// Provenance of the inputs in front of a tool's own block, re-prefixed for the output. A block
+/// already copied from an earlier input (two outputs of one run) is not repeated.
+pub fn upstream(paths: &[&str], prefix: char) -> Vec<String> {
+    let mut blocks: Vec<Vec<String>> = Vec::new();
+    for path in paths {
+        let mut current: Vec<Vec<String>> = Vec::new();
+        for line in read(path).unwrap_or_default() {
+            if line.split('\t').nth(1) == Some("tool") || current.is_empty() {
+                current.push(Vec::new());
+            }
+            current.last_mut().unwrap().push(line);
+        }
+        for block in current {
+            if !blocks.contains(&block) {
+                blocks.push(block);
+            }
+        }
+    }
+    blocks.into_iter().flatten().map(|l| format!("{}{}", prefix, l)).collect()
+}
+
+/// Fields of a comma separated line, trimmed and unquoted (R and pandas may quote every field).
+pub fn fields(line: &str) -> Vec<&str> {
+    line.split(',').map(|s| s.trim().trim_matches('"')).collect()
+}
+
+/// Column names of a csv, from the first line after the leading comment and blank lines; the data
+/// lines are left in `lines`. None for a file without a header.
+pub fn csv_header<B: BufRead>(lines: &mut io::Lines<B>) -> io::Result<Option<Vec<String>>> {
+    for line in lines.by_ref() {
+        let line = line?;
+        if !line.trim().is_empty() && !line.starts_with('#') {
+            return Ok(Some(fields(&line).into_iter().map(String::from).collect()));
+        }
+    }
+    Ok(None)
+}
+
+/// Blocks of key/value pairs, split at every "tool" line.
+pub fn parse(lines: &[String]) -> Vec<Vec<(String, String)>> {
+    let mut blocks: Vec<Vec<(String, String)>> = Vec::new();
+    for line in lines {
+        let mut parts = line.splitn(3, '\t');
+        let (_, key, value) = (parts.next(), parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+        if key == "tool" || blocks.is_empty() {
+            blocks.push(Vec::new());
+        }
+        blocks.last_mut().unwrap().push((key.to_string(), value.to_string()));
+    }
+    blocks
+}
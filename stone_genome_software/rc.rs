@@ -0,0 +1,190 @@
+#![allow(dead_code)]
+// RNA Framework RC count files (rf-count) and their RCI index, read without the rf-rctools text
+// conversion. All numbers are little-endian:
+//
+//   per transcript   uint32 len_id (with the NUL), char[len_id] id, uint32 len_seq,
+//                    uint8[(len_seq + 1) / 2] sequence (4 bit, ACGTN = 0..4, high nibble first),
+//                    uint32[len_seq] counts (RT stops or mutations), uint32[len_seq] coverage
+//   end of file      uint64 mapped reads, "[eofrc]"
+//
+// The RCI file has uint32 len_id, char[len_id] id, uint64 offset of the transcript for every
+// transcript. Without one the RC file is scanned once for the offsets.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+pub const EOF_MARKER: &[u8; 7] = b"[eofrc]";
+const TRAILER: u64 = 8 + EOF_MARKER.len() as u64;
+const BASES: [u8; 5] = *b"ACGTN";
+// longer ids are taken for a wrong offset
+const MAX_ID: usize = 1 << 16;
+
+pub struct Transcript {
+    pub name: String,
+    /// ACGTN, one byte per position
+    pub sequence: Vec<u8>,
+    pub counts: Vec<u32>,
+    pub coverage: Vec<u32>,
+}
+
+pub struct RcFile {
+    reader: BufReader<File>,
+    /// (transcript, offset of its entry), in file order
+    pub index: Vec<(String, u64)>,
+    by_name: HashMap<String, usize>,
+    pub mapped_reads: u64,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+// len_id and the NUL terminated id, with the bytes they take
+fn read_id(r: &mut impl Read) -> io::Result<(String, u64)> {
+    let len = read_u32(r)? as usize;
+    if len == 0 || len > MAX_ID {
+        return Err(invalid(format!("transcript id of {} bytes", len)));
+    }
+    let mut id = vec![0u8; len];
+    r.read_exact(&mut id)?;
+    if id.last() == Some(&0) {
+        id.pop();
+    }
+    let id = String::from_utf8(id).map_err(|_| invalid("transcript id is not utf-8".to_string()))?;
+    Ok((id, 4 + len as u64))
+}
+
+/// True when the file ends with the RC end-of-file marker.
+pub fn is_rc(path: &str) -> bool {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return false,
+    };
+    let mut tail = [0u8; 7];
+    file.seek(SeekFrom::End(-(EOF_MARKER.len() as i64))).is_ok() && file.read_exact(&mut tail).is_ok() && &tail == EOF_MARKER
+}
+
+/// <file>.rci, as rf-count writes it, or the .rc extension swapped for .rci.
+pub fn index_path(path: &str) -> Option<PathBuf> {
+    let appended = PathBuf::from(format!("{}.rci", path));
+    let swapped = Path::new(path).with_extension("rci");
+    [appended, swapped].into_iter().find(|p| p.is_file())
+}
+
+pub fn read_index(path: &Path) -> io::Result<Vec<(String, u64)>> {
+    let len = std::fs::metadata(path)?.len();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut index = Vec::new();
+    let mut at = 0u64;
+    while at < len {
+        let (id, size) = read_id(&mut reader)?;
+        let offset = read_u64(&mut reader)?;
+        at += size + 8;
+        index.push((id, offset));
+    }
+    Ok(index)
+}
+
+impl RcFile {
+    pub fn open(path: &str) -> io::Result<RcFile> {
+        let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path, e));
+        let file = File::open(path).map_err(with_path)?;
+        let len = file.metadata().map_err(with_path)?.len();
+        if len < TRAILER || !is_rc(path) {
+            return Err(invalid(format!("{}: not an RC file, no {} at the end", path, String::from_utf8_lossy(EOF_MARKER))));
+        }
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(len - TRAILER)).map_err(with_path)?;
+        let mapped_reads = read_u64(&mut reader).map_err(with_path)?;
+        let index = match index_path(path) {
+            Some(rci) => read_index(&rci).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", rci.display(), e)))?,
+            None => scan(&mut reader, len - TRAILER).map_err(with_path)?,
+        };
+        if let Some((name, offset)) = index.iter().find(|(_, offset)| *offset >= len - TRAILER) {
+            return Err(invalid(format!("{}: index puts {} at {}, past the counts; rebuild the .rci", path, name, offset)));
+        }
+        let by_name = index.iter().enumerate().map(|(i, (name, _))| (name.clone(), i)).collect();
+        Ok(RcFile { reader, index, by_name, mapped_reads })
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.index.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// The i-th transcript of the index; errors when the index does not match the RC file.
+    pub fn read(&mut self, i: usize) -> io::Result<Transcript> {
+        let (name, offset) = self.index[i].clone();
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let id = read_id(&mut self.reader).map(|(id, _)| id).unwrap_or_default();
+        if id != name {
+            return Err(invalid(format!("index puts {} at {}, the RC file has no transcript there; rebuild the .rci", name, offset)));
+        }
+        let len = read_u32(&mut self.reader)? as usize;
+        let mut packed = vec![0u8; len.div_ceil(2)];
+        self.reader.read_exact(&mut packed)?;
+        let mut sequence = Vec::with_capacity(len);
+        for (p, byte) in packed.iter().enumerate() {
+            for code in [byte >> 4, byte & 0x0f] {
+                if sequence.len() == len {
+                    break;
+                }
+                let base = BASES.get(code as usize).ok_or_else(|| invalid(format!("{}: base code {} at {}", name, code, 2 * p + 1)))?;
+                sequence.push(*base);
+            }
+        }
+        let mut numbers = vec![0u8; 8 * len];
+        self.reader.read_exact(&mut numbers)?;
+        let value = |chunk: &[u8]| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let counts = numbers[..4 * len].chunks_exact(4).map(value).collect();
+        let coverage = numbers[4 * len..].chunks_exact(4).map(value).collect();
+        Ok(Transcript { name, sequence, counts, coverage })
+    }
+
+    /// One transcript by name, without reading the ones before it.
+    pub fn get(&mut self, name: &str) -> io::Result<Option<Transcript>> {
+        match self.by_name.get(name) {
+            Some(&i) => self.read(i).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// offsets of the transcripts from their lengths, skipping sequence and numbers
+fn scan(reader: &mut BufReader<File>, end: u64) -> io::Result<Vec<(String, u64)>> {
+    let mut index = Vec::new();
+    let mut at = 0u64;
+    reader.seek(SeekFrom::Start(0))?;
+    while at < end {
+        let (id, size) = read_id(reader)?;
+        let len = read_u32(reader)? as u64;
+        let skip = len.div_ceil(2) + 8 * len;
+        reader.seek_relative(skip as i64)?;
+        index.push((id, at));
+        at += size + 4 + skip;
+    }
+    if at != end {
+        return Err(invalid(format!("last transcript runs {} bytes into the end of file", at - end)));
+    }
+    Ok(index)
+}
@@ -0,0 +1,500 @@
+#![allow(dead_code)]
+// Byte-offset index of merged, bgsg and genome_model_output.py csv files and the queries of
+// `stone serve`. A file is cut into blocks of consecutive rows with the same transcript, ChrID and
+// strand; each block keeps its byte range, position range and depth sum, and every CHECKPOINT rows
+// the offset of a row, so a query reads only the rows it returns. The index is written next to the
+// file as <file>.stidx and rebuilt when the file's size or modification time changes.
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::time::UNIX_EPOCH;
+
+const INDEX_VERSION: u32 = 1;
+// a power of two, so the row count is tested with a mask
+const CHECKPOINT: u64 = 4096;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Key columns of a file, looked up by header name.
+pub struct Columns {
+    pub names: Vec<String>,
+    pub id: usize,
+    pub chr: usize,
+    pub strand: usize,
+    pub position: usize,
+    pub depth: Option<usize>,
+}
+
+impl Columns {
+    fn new(header: &str) -> Result<Columns, String> {
+        let names: Vec<String> = header.split(',').map(|s| s.trim().to_string()).collect();
+        let find = |candidates: &[&str]| candidates.iter().find_map(|c| names.iter().position(|n| n == c));
+        let need = |candidates: &[&str]| find(candidates).ok_or_else(|| format!("no {} column", candidates.join(" or ")));
+        Ok(Columns {
+            // bgsg rows name their transcript, merged and model rows are keyed by ChrID
+            id: need(&["transcriptid", "ChrID"])?,
+            chr: need(&["ChrID"])?,
+            strand: need(&["Strand", "pipe_truncation_Strand"])?,
+            position: need(&["Position", "position", "pipe_truncation_ChrPos"])?,
+            depth: find(&["BD1", "rf_mutation_Depth"]),
+            names,
+        })
+    }
+}
+
+pub struct Block {
+    pub id: String,
+    pub chr: String,
+    pub strand: String,
+    /// byte range of the rows
+    pub start: u64,
+    pub end: u64,
+    pub min_pos: u64,
+    pub max_pos: u64,
+    pub rows: u64,
+    pub depth_sum: f64,
+    pub depth_rows: u64,
+    /// positions ascending, so checkpoints can be used and reading can stop early
+    pub sorted: bool,
+    /// (position, offset) of every CHECKPOINT-th row
+    pub checkpoints: Vec<(u64, u64)>,
+}
+
+pub struct Index {
+    pub path: String,
+    pub header: String,
+    pub columns: Columns,
+    pub blocks: Vec<Block>,
+    by_id: HashMap<String, Vec<usize>>,
+}
+
+fn index_path(path: &str) -> String {
+    format!("{}.stidx", path)
+}
+
+// size and modification time identify the indexed version of a file
+fn stamp(path: &str) -> io::Result<(u64, u64)> {
+    let meta = fs::metadata(path)?;
+    let mtime = meta.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    Ok((meta.len(), mtime))
+}
+
+fn field(line: &str, i: usize) -> &str {
+    line.split(',').nth(i).unwrap_or("").trim()
+}
+
+impl Index {
+    fn finish(path: &str, header: String, blocks: Vec<Block>) -> io::Result<Index> {
+        let columns = Columns::new(&header).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+        let mut by_id: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, b) in blocks.iter().enumerate() {
+            by_id.entry(b.id.clone()).or_default().push(i);
+        }
+        Ok(Index { path: path.to_string(), header, columns, blocks, by_id })
+    }
+
+    /// Reads the file once; '#' lines before the header are skipped.
+    pub fn build(path: &str) -> io::Result<Index> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut line = String::new();
+        let mut offset = 0u64;
+        let header = loop {
+            line.clear();
+            let n = reader.read_line(&mut line)? as u64;
+            if n == 0 {
+                return Err(invalid(format!("{}: no header line", path)));
+            }
+            offset += n;
+            if !line.starts_with('#') {
+                break line.trim_end().to_string();
+            }
+        };
+        let columns = Columns::new(&header).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+        let mut blocks: Vec<Block> = Vec::new();
+        loop {
+            line.clear();
+            let n = reader.read_line(&mut line)? as u64;
+            if n == 0 {
+                break;
+            }
+            let row = line.trim_end();
+            if row.is_empty() {
+                offset += n;
+                continue;
+            }
+            let fields: Vec<&str> = row.split(',').map(|s| s.trim()).collect();
+            let get = |i: usize| fields.get(i).copied().unwrap_or("");
+            let position: u64 = get(columns.position).parse().map_err(|_| invalid(format!("{}: bad position in {}", path, row)))?;
+            let same = matches!(blocks.last(), Some(b) if b.id == get(columns.id) && b.chr == get(columns.chr) && b.strand == get(columns.strand));
+            if !same {
+                blocks.push(Block {
+                    id: get(columns.id).to_string(),
+                    chr: get(columns.chr).to_string(),
+                    strand: get(columns.strand).to_string(),
+                    start: offset,
+                    end: offset,
+                    min_pos: position,
+                    max_pos: position,
+                    rows: 0,
+                    depth_sum: 0.0,
+                    depth_rows: 0,
+                    sorted: true,
+                    checkpoints: Vec::new(),
+                });
+            }
+            let b = blocks.last_mut().unwrap();
+            if b.rows & (CHECKPOINT - 1) == 0 {
+                b.checkpoints.push((position, offset));
+            }
+            if position < b.max_pos {
+                b.sorted = false;
+            }
+            b.min_pos = b.min_pos.min(position);
+            b.max_pos = b.max_pos.max(position);
+            b.rows += 1;
+            if let Some(d) = columns.depth.and_then(|i| get(i).parse::<f64>().ok()) {
+                b.depth_sum += d;
+                b.depth_rows += 1;
+            }
+            offset += n;
+            b.end = offset;
+        }
+        Index::finish(path, header, blocks)
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        let (size, mtime) = stamp(&self.path)?;
+        let mut out = BufWriter::new(File::create(index_path(&self.path))?);
+        writeln!(out, "#stone-index\t{}\t{}\t{}", INDEX_VERSION, size, mtime)?;
+        writeln!(out, "header\t{}", self.header)?;
+        for b in &self.blocks {
+            writeln!(
+                out,
+                "block\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                b.id, b.chr, b.strand, b.start, b.end, b.min_pos, b.max_pos, b.rows, b.depth_sum, b.depth_rows, b.sorted as u8
+            )?;
+            for (pos, off) in &b.checkpoints {
+                writeln!(out, "cp\t{}\t{}", pos, off)?;
+            }
+        }
+        out.flush()
+    }
+
+    /// The saved index of `path`, None when missing, of another version or made for another file state.
+    pub fn read(path: &str) -> io::Result<Option<Index>> {
+        let file = match File::open(index_path(path)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (size, mtime) = stamp(path)?;
+        let mut lines = BufReader::new(file).lines();
+        let first = lines.next().transpose()?.unwrap_or_default();
+        if first != format!("#stone-index\t{}\t{}\t{}", INDEX_VERSION, size, mtime) {
+            return Ok(None);
+        }
+        let bad = |l: &str| invalid(format!("{}: bad line {}", index_path(path), l));
+        let mut header = String::new();
+        let mut blocks: Vec<Block> = Vec::new();
+        for line in lines {
+            let line = line?;
+            let f: Vec<&str> = line.split('\t').collect();
+            let num = |i: usize| f.get(i).and_then(|v| v.parse::<u64>().ok()).ok_or_else(|| bad(&line));
+            match f[0] {
+                "header" => header = f.get(1).unwrap_or(&"").to_string(),
+                "block" if f.len() == 12 => blocks.push(Block {
+                    id: f[1].to_string(),
+                    chr: f[2].to_string(),
+                    strand: f[3].to_string(),
+                    start: num(4)?,
+                    end: num(5)?,
+                    min_pos: num(6)?,
+                    max_pos: num(7)?,
+                    rows: num(8)?,
+                    depth_sum: f[9].parse().map_err(|_| bad(&line))?,
+                    depth_rows: num(10)?,
+                    sorted: f[11] == "1",
+                    checkpoints: Vec::new(),
+                }),
+                "cp" => {
+                    let (pos, off) = (num(1)?, num(2)?);
+                    blocks.last_mut().ok_or_else(|| bad(&line))?.checkpoints.push((pos, off));
+                }
+                _ => return Err(bad(&line)),
+            }
+        }
+        Index::finish(path, header, blocks).map(Some)
+    }
+
+    /// The saved index when it is current, else a new one, saved when the directory is writable.
+    pub fn open(path: &str) -> io::Result<(Index, bool)> {
+        if let Some(index) = Index::read(path)? {
+            return Ok((index, false));
+        }
+        let index = Index::build(path)?;
+        if let Err(e) = index.write() {
+            eprintln!("{}: index not saved: {}", index_path(path), e);
+        }
+        Ok((index, true))
+    }
+
+    // rows of block `b` with a position in `range`, at most `limit`
+    fn block_rows(&self, file: &mut BufReader<File>, b: &Block, range: Option<(u64, u64)>, limit: usize, out: &mut Vec<String>) -> Result<(), String> {
+        let mut offset = b.start;
+        if let (true, Some((from, _))) = (b.sorted, range) {
+            // last checkpoint before the range
+            let i = b.checkpoints.partition_point(|(pos, _)| *pos < from);
+            if i > 0 {
+                offset = b.checkpoints[i - 1].1;
+            }
+        }
+        file.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        while offset < b.end {
+            line.clear();
+            let n = file.read_line(&mut line).map_err(|e| e.to_string())? as u64;
+            if n == 0 {
+                break;
+            }
+            offset += n;
+            let row = line.trim_end();
+            if row.is_empty() {
+                continue;
+            }
+            if let Some((from, to)) = range {
+                let pos: u64 = field(row, self.columns.position).parse().unwrap_or(0);
+                if pos > to && b.sorted {
+                    break;
+                }
+                if pos < from || pos > to {
+                    continue;
+                }
+            }
+            if out.len() >= limit {
+                return Err(format!("more than {} rows, narrow the query", limit));
+            }
+            out.push(row.to_string());
+        }
+        Ok(())
+    }
+
+    fn rows(&self, blocks: &[usize], range: Option<(u64, u64)>, limit: usize) -> Result<Vec<String>, String> {
+        let mut out = Vec::new();
+        if blocks.is_empty() {
+            return Ok(out);
+        }
+        let mut file = BufReader::new(File::open(&self.path).map_err(|e| format!("{}: {}", self.path, e))?);
+        for &i in blocks {
+            self.block_rows(&mut file, &self.blocks[i], range, limit, &mut out)?;
+        }
+        Ok(out)
+    }
+
+    /// Every row of transcript `id` (transcriptid of bgsg files, ChrID of the others).
+    pub fn transcript(&self, id: &str, limit: usize) -> Result<Vec<String>, String> {
+        let blocks = self.by_id.get(id).cloned().unwrap_or_default();
+        self.rows(&blocks, None, limit)
+    }
+
+    /// Rows on `chr` and `strand` with start <= position <= end.
+    pub fn region(&self, chr: &str, start: u64, end: u64, strand: &str, limit: usize) -> Result<Vec<String>, String> {
+        let blocks: Vec<usize> = (0..self.blocks.len())
+            .filter(|&i| {
+                let b = &self.blocks[i];
+                b.chr == chr && b.strand == strand && b.max_pos >= start && b.min_pos <= end
+            })
+            .collect();
+        self.rows(&blocks, Some((start, end)), limit)
+    }
+
+    /// Transcripts with a mean depth of at least `min_depth`, None for files without a depth column.
+    pub fn genes(&self, min_depth: f64) -> Option<Vec<Gene>> {
+        self.columns.depth?;
+        let mut genes: Vec<Gene> = Vec::new();
+        let mut seen: HashMap<(&str, &str, &str), usize> = HashMap::new();
+        for b in &self.blocks {
+            let key = (b.id.as_str(), b.chr.as_str(), b.strand.as_str());
+            let i = *seen.entry(key).or_insert_with(|| {
+                genes.push(Gene { id: b.id.clone(), chr: b.chr.clone(), strand: b.strand.clone(), start: b.min_pos, end: b.max_pos, positions: 0, depth_sum: 0.0, depth_rows: 0 });
+                genes.len() - 1
+            });
+            let g = &mut genes[i];
+            g.start = g.start.min(b.min_pos);
+            g.end = g.end.max(b.max_pos);
+            g.positions += b.rows;
+            g.depth_sum += b.depth_sum;
+            g.depth_rows += b.depth_rows;
+        }
+        genes.retain(|g| g.mean_depth() >= min_depth);
+        Some(genes)
+    }
+}
+
+pub struct Gene {
+    pub id: String,
+    pub chr: String,
+    pub strand: String,
+    pub start: u64,
+    pub end: u64,
+    pub positions: u64,
+    depth_sum: f64,
+    depth_rows: u64,
+}
+
+impl Gene {
+    pub fn mean_depth(&self) -> f64 {
+        if self.depth_rows == 0 {
+            0.0
+        } else {
+            self.depth_sum / self.depth_rows as f64
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// numbers stay numbers, NA and empty cells are null
+fn json_value(s: &str) -> String {
+    if s.is_empty() || s == "NA" || s == "nan" || s == "NaN" {
+        return "null".to_string();
+    }
+    match s.parse::<f64>() {
+        Ok(v) if v.is_finite() && !s.starts_with('+') => s.to_string(),
+        _ => json_string(s),
+    }
+}
+
+/// The rows one file gave for a query.
+pub struct Hits<'a> {
+    pub index: &'a Index,
+    pub rows: Vec<String>,
+}
+
+/// {"files":[{"file":...,"columns":[...],"rows":[{column: value}]}]}
+pub fn rows_json(hits: &[Hits]) -> String {
+    let files: Vec<String> = hits
+        .iter()
+        .map(|h| {
+            let names = &h.index.columns.names;
+            let rows: Vec<String> = h
+                .rows
+                .iter()
+                .map(|r| {
+                    let cells: Vec<String> = r.split(',').zip(names).map(|(v, n)| format!("{}:{}", json_string(n), json_value(v.trim()))).collect();
+                    format!("{{{}}}", cells.join(","))
+                })
+                .collect();
+            let columns: Vec<String> = names.iter().map(|n| json_string(n)).collect();
+            format!("{{\"file\":{},\"columns\":[{}],\"rows\":[{}]}}", json_string(&h.index.path), columns.join(","), rows.join(","))
+        })
+        .collect();
+    format!("{{\"files\":[{}]}}\n", files.join(","))
+}
+
+/// Per file a "#file<TAB>path" line, the header and the rows.
+pub fn rows_csv(hits: &[Hits]) -> String {
+    let mut out = String::new();
+    for h in hits {
+        out.push_str(&format!("#file\t{}\n{}\n", h.index.path, h.index.header));
+        for r in &h.rows {
+            out.push_str(r);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+const GENE_COLUMNS: &str = "file,id,chr,strand,start,end,positions,mean_depth";
+
+pub fn genes_json(genes: &[(&Index, Vec<Gene>)]) -> String {
+    let mut items = Vec::new();
+    for (index, list) in genes {
+        for g in list {
+            items.push(format!(
+                "{{\"file\":{},\"id\":{},\"chr\":{},\"strand\":{},\"start\":{},\"end\":{},\"positions\":{},\"mean_depth\":{:.3}}}",
+                json_string(&index.path),
+                json_string(&g.id),
+                json_string(&g.chr),
+                json_string(&g.strand),
+                g.start,
+                g.end,
+                g.positions,
+                g.mean_depth()
+            ));
+        }
+    }
+    format!("{{\"genes\":[{}]}}\n", items.join(","))
+}
+
+pub fn genes_csv(genes: &[(&Index, Vec<Gene>)]) -> String {
+    let mut out = format!("{}\n", GENE_COLUMNS);
+    for (index, list) in genes {
+        for g in list {
+            out.push_str(&format!("{},{},{},{},{},{},{},{:.3}\n", index.path, g.id, g.chr, g.strand, g.start, g.end, g.positions, g.mean_depth()));
+        }
+    }
+    out
+}
+
+pub fn error_json(msg: &str) -> String {
+    format!("{{\"error\":{}}}\n", json_string(msg))
+}
+
+/// "%2B" and friends in a url path or query value; '+' in a query value is a space.
+pub fn percent_decode(s: &str, plus_is_space: bool) -> String {
+    let bytes = s.as_bytes();
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let decoded = match (bytes[i], bytes.get(i + 1).and_then(|b| hex(*b)), bytes.get(i + 2).and_then(|b| hex(*b))) {
+            (b'%', Some(hi), Some(lo)) => Some(hi * 16 + lo),
+            _ => None,
+        };
+        match decoded {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(if bytes[i] == b'+' && plus_is_space { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// "chr:start-end:strand", the chromosome may itself contain ':'.
+pub fn parse_region(s: &str) -> Result<(String, u64, u64, String), String> {
+    let bad = || format!("region {} should be chr:start-end:strand", s);
+    let (rest, strand) = s.rsplit_once(':').ok_or_else(bad)?;
+    let (chr, span) = rest.rsplit_once(':').ok_or_else(bad)?;
+    let (start, end) = span.split_once('-').ok_or_else(bad)?;
+    let start: u64 = start.replace(',', "").parse().map_err(|_| bad())?;
+    let end: u64 = end.replace(',', "").parse().map_err(|_| bad())?;
+    if strand != "+" && strand != "-" {
+        return Err(format!("strand of {} should be + or -", s));
+    }
+    if chr.is_empty() || start > end {
+        return Err(bad());
+    }
+    Ok((chr.to_string(), start, end, strand.to_string()))
+}
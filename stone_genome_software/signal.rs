@@ -0,0 +1,362 @@
+#![allow(dead_code)]
+// Readers for the zipped, merged, bgsg and model-input files and the model features of
+// genome_model_output.py, shared by the CLI tools and the Python bindings so both give the same
+// numbers. Tables are column-wise: numbers as f64 with NaN where a value is missing.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use crate::zipped;
+
+pub enum Column {
+    Num(Vec<f64>),
+    Text(Vec<String>),
+}
+
+impl Column {
+    pub fn len(&self) -> usize {
+        match self {
+            Column::Num(v) => v.len(),
+            Column::Text(v) => v.len(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Table {
+    pub columns: Vec<(String, Column)>,
+}
+
+impl Table {
+    pub fn rows(&self) -> usize {
+        self.columns.first().map(|(_, c)| c.len()).unwrap_or(0)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|(n, _)| n == name).map(|(_, c)| c)
+    }
+
+    pub fn num(&self, name: &str) -> io::Result<&[f64]> {
+        match self.get(name) {
+            Some(Column::Num(v)) => Ok(v),
+            Some(Column::Text(_)) => Err(invalid(format!("column {} is not numeric", name))),
+            None => Err(invalid(format!("no column {}", name))),
+        }
+    }
+
+    // every column numeric where all present values parse, text otherwise
+    fn from_rows(names: Vec<String>, rows: Vec<Vec<String>>) -> Table {
+        let missing = |v: &str| v.is_empty() || v == "NA";
+        let columns = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let cell = |r: &Vec<String>| r.get(i).cloned().unwrap_or_default();
+                let numeric = rows.iter().all(|r| {
+                    let v = cell(r);
+                    missing(&v) || v.parse::<f64>().is_ok()
+                });
+                let column = if numeric {
+                    Column::Num(rows.iter().map(|r| cell(r).parse().unwrap_or(f64::NAN)).collect())
+                } else {
+                    Column::Text(rows.iter().map(cell).collect())
+                };
+                (name, column)
+            })
+            .collect();
+        Table { columns }
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// merge, bgsg, mbreport and model-input csv: '#' lines skipped, first line is the header, fields
+/// unquoted (the model input csvs of example_data quote every field).
+pub fn read_csv(path: &str) -> io::Result<Table> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut names: Option<Vec<String>> = None;
+    let mut rows = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<String> = line.split(',').map(|s| s.trim().trim_matches('"').to_string()).collect();
+        if names.is_none() {
+            names = Some(fields);
+        } else {
+            rows.push(fields);
+        }
+    }
+    let names = names.ok_or_else(|| invalid(format!("{}: no header line", path)))?;
+    Ok(Table::from_rows(names, rows))
+}
+
+/// zip_rfcsv, zip_rftxt2 or zip_pipe output (either zip format version, see zipped.rs) as ChrID,
+/// Strand, ChrPos and the data columns.
+pub fn read_zipped(path: &str) -> io::Result<Table> {
+    let (header, lines) = zipped::open(path)?;
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for line in lines {
+        let line = line?;
+        let row = header.row(&line).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+        let mut fields = vec![row.chr_id.to_string(), row.strand.to_string(), row.position.to_string()];
+        fields.extend(row.fields.iter().map(|s| s.to_string()));
+        rows.push(fields);
+    }
+    let mut names = vec!["ChrID".to_string(), "Strand".to_string()];
+    names.extend(header.columns[1..].iter().cloned());
+    Ok(Table::from_rows(names, rows))
+}
+
+/// base_A, base_T, base_G, base_C from the RF base, count, depth and the 12 substitution counts.
+pub fn base_counts(base: char, count: usize, depth: usize, mutations: &[&str]) -> Option<[usize; 4]> {
+    let m = |i: usize| mutations.get(i).and_then(|v| v.parse::<usize>().ok()).unwrap_or(0);
+    let r = depth.saturating_sub(count);
+    // A C G T order, then reordered to A T G C
+    let acgt = match base.to_ascii_uppercase() {
+        'A' => [r, m(0), m(1), m(2)],
+        'C' => [m(3), r, m(4), m(5)],
+        'G' => [m(6), m(7), r, m(8)],
+        'T' | 'U' => [m(9), m(10), m(11), r],
+        _ => return None,
+    };
+    Some([acgt[0], acgt[3], acgt[2], acgt[1]])
+}
+
+/// Columns of the per-transcript model input csv written by model-input.
+pub const MODEL_INPUT_HEADER: &str = "ChrID,rf_mutation_Base,rf_mutation_Count,rf_mutation_Depth,rf_mutation_AC,rf_mutation_AG,rf_mutation_AT,rf_mutation_CA,rf_mutation_CG,rf_mutation_CT,rf_mutation_GA,rf_mutation_GC,rf_mutation_GT,rf_mutation_TA,rf_mutation_TC,rf_mutation_TG,rf_mutation_ins,rf_mutation_del,pipe_truncation_Strand,pipe_truncation_ChrPos,pipe_truncation_Base,pipe_truncation_count,pipe_truncation_BD,base_A,base_T,base_G,base_C,modified_string";
+
+// merged row (ChrID,Strand,Position,Base1,RT1,BD1,AC..TG,Ins,Del,Base3,RT3,BD3) to the model input row,
+// `label` goes in modified_string
+pub fn model_row(parts: &[&str], label: &str) -> String {
+    let rf_missing = parts[3] == "N" && parts[5] == "0";
+    let pipe_missing = parts[20] == "N" && parts[22] == "0";
+    let mut row = vec![parts[0].to_string()];
+    if rf_missing {
+        row.push(if pipe_missing { String::new() } else { parts[20].to_string() });
+        row.resize(row.len() + 16, String::new());
+    } else {
+        row.extend(parts[3..20].iter().map(|s| s.to_string()));
+    }
+    row.push(parts[1].to_string());
+    row.push(parts[2].to_string());
+    if pipe_missing {
+        row.resize(row.len() + 3, String::new());
+    } else {
+        row.extend(parts[20..23].iter().map(|s| s.to_string()));
+    }
+    let counts = if rf_missing {
+        None
+    } else {
+        let count = parts[4].parse().unwrap_or(0);
+        let depth = parts[5].parse().unwrap_or(0);
+        base_counts(parts[3].chars().next().unwrap_or('N'), count, depth, &parts[6..18])
+    };
+    match counts {
+        Some(c) => row.extend(c.iter().map(|v| v.to_string())),
+        None => row.resize(row.len() + 4, String::new()),
+    }
+    row.push(label.to_string());
+    row.join(",")
+}
+
+/// One (ChrID, strand) of a merged file, the rows as model-input makes them (modified_string empty).
+pub struct Transcript {
+    pub chr_id: String,
+    pub strand: char,
+    pub table: Table,
+}
+
+/// merge output split per transcript in file order; every position of the file is kept.
+pub fn read_merged_transcripts(path: &str) -> io::Result<Vec<Transcript>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut index: HashMap<(String, char), usize> = HashMap::new();
+    let mut groups: Vec<(String, char, Vec<Vec<String>>)> = Vec::new();
+    // #stone provenance lines come before the header
+    let lines = reader.lines().filter(|l| !matches!(l, Ok(l) if l.starts_with('#')));
+    for line in lines.skip(1) {
+        let line = line?;
+        let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+        if parts.len() < 23 {
+            return Err(invalid(format!("{}: {} fields, a merged row has 23", path, parts.len())));
+        }
+        let strand = parts[1].chars().next().unwrap_or('+');
+        let key = (parts[0].to_string(), strand);
+        let i = *index.entry(key).or_insert_with(|| {
+            groups.push((parts[0].to_string(), strand, Vec::new()));
+            groups.len() - 1
+        });
+        groups[i].2.push(model_row(&parts, "").split(',').map(String::from).collect());
+    }
+    let names: Vec<String> = MODEL_INPUT_HEADER.split(',').map(String::from).collect();
+    Ok(groups
+        .into_iter()
+        .map(|(chr_id, strand, rows)| Transcript { chr_id, strand, table: Table::from_rows(names.clone(), rows) })
+        .collect())
+}
+
+/// numpy.percentile with linear interpolation over the non-NaN values.
+pub fn percentile(values: &[f64], q: f64) -> f64 {
+    let mut v: Vec<f64> = values.iter().copied().filter(|x| !x.is_nan()).collect();
+    if v.is_empty() {
+        return f64::NAN;
+    }
+    v.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let rank = q / 100.0 * (v.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    v[lo] + (v[hi] - v[lo]) * (rank - lo as f64)
+}
+
+// numpy.clip, NaN bounds give NaN
+fn clip(x: f64, lo: f64, hi: f64) -> f64 {
+    if lo.is_nan() || hi.is_nan() {
+        return f64::NAN;
+    }
+    x.max(lo).min(hi)
+}
+
+/// normalize_by_percentile: clip at the percentiles and scale to 0..1, NaN stays NaN.
+pub fn normalize_by_percentile(values: &[f64], lower: f64, upper: f64) -> Vec<f64> {
+    let (lo, hi) = (percentile(values, lower), percentile(values, upper));
+    values.iter().map(|&x| if x.is_nan() { x } else { (clip(x, lo, hi) - lo) / (hi - lo) }).collect()
+}
+
+/// percentile_normalize: as normalize_by_percentile, the percentiles leave out `outlier`
+/// and equal percentiles give all zeros.
+pub fn percentile_normalize(values: &[f64], lower: f64, upper: f64, outlier: f64) -> Vec<f64> {
+    let kept: Vec<f64> = values.iter().copied().filter(|x| *x != outlier).collect();
+    let (lo, hi) = (percentile(&kept, lower), percentile(&kept, upper));
+    if lo == hi {
+        return vec![0.0; values.len()];
+    }
+    values.iter().map(|&x| if x.is_nan() { x } else { (clip(x, lo, hi) - lo) / (hi - lo) }).collect()
+}
+
+/// remap_values of Zarringhalam et al. (2012); NaN becomes 0 as with numpy.zeros_like.
+pub fn remap(values: &[f64]) -> Vec<f64> {
+    values
+        .iter()
+        .map(|&x| match x {
+            x if x < 0.25 => x * 0.35 / 0.25,
+            x if x < 0.3 => 0.35 + (x - 0.25) * 0.2 / 0.05,
+            x if x < 0.7 => 0.55 + (x - 0.3) * 0.3 / 0.4,
+            x if x >= 0.7 => 0.85 + (x - 0.7) * 0.15 / 0.3,
+            _ => 0.0,
+        })
+        .collect()
+}
+
+pub const RATE_COLUMNS: [&str; 6] = ["rate_A", "rate_T", "rate_C", "rate_G", "rate_stop", "rate_mut"];
+
+/// rate_A..rate_G, rate_stop and rate_mut: base and stop counts over rf_mutation_Depth.
+pub fn features(table: &Table) -> io::Result<Vec<(String, Vec<f64>)>> {
+    let depth = table.num("rf_mutation_Depth")?;
+    let over = |name: &str| -> io::Result<Vec<f64>> { Ok(table.num(name)?.iter().zip(depth).map(|(a, d)| a / d).collect()) };
+    Ok(vec![
+        ("rate_A".to_string(), over("base_A")?),
+        ("rate_T".to_string(), over("base_T")?),
+        ("rate_C".to_string(), over("base_C")?),
+        ("rate_G".to_string(), over("base_G")?),
+        ("rate_stop".to_string(), over("pipe_truncation_count")?),
+        ("rate_mut".to_string(), over("rf_mutation_Count")?),
+    ])
+}
+
+/// [model] settings of genome_model_output.py.
+pub struct ModelParams {
+    pub depth_threshold: f64,
+    pub rf_mutation_count: f64,
+    pub pipe_truncation_count: f64,
+    pub rate_mut: f64,
+    pub rate_stop: f64,
+    pub acc_threshold: f64,
+    pub filter_acc: bool,
+}
+
+impl Default for ModelParams {
+    fn default() -> ModelParams {
+        ModelParams { depth_threshold: 10.0, rf_mutation_count: 0.0, pipe_truncation_count: 0.0, rate_mut: 0.25, rate_stop: 1.0, acc_threshold: 0.0, filter_acc: true }
+    }
+}
+
+/// Columns process_testdata drops before the features; modified_string is the label and acc a filter.
+pub const DROPPED_COLUMNS: [&str; 20] = [
+    "ChrID",
+    "rf_mutation_Base",
+    "pipe_truncation_Strand",
+    "rf_mutation_AC",
+    "rf_mutation_AG",
+    "rf_mutation_AT",
+    "rf_mutation_CA",
+    "rf_mutation_CT",
+    "rf_mutation_CG",
+    "rf_mutation_GA",
+    "rf_mutation_GT",
+    "rf_mutation_GC",
+    "rf_mutation_TA",
+    "rf_mutation_TC",
+    "rf_mutation_TG",
+    "pipe_truncation_BD",
+    "pipe_truncation_Base",
+    "pipe_truncation_ChrPos",
+    "modified_string",
+    "acc",
+];
+
+/// Feature matrix X of process_testdata: the rows passing the filters, the columns not dropped in
+/// file order, then the rates normalised and remapped.
+pub struct Prepared {
+    pub rows: Vec<usize>,
+    pub columns: Vec<String>,
+    /// row-major, rows.len() x columns.len()
+    pub matrix: Vec<f64>,
+}
+
+pub fn prepare(table: &Table, params: &ModelParams) -> io::Result<Prepared> {
+    let rates = features(table)?;
+    let rate = |name: &str| &rates.iter().find(|(n, _)| n == name).unwrap().1;
+    let depth = table.num("rf_mutation_Depth")?;
+    let count = table.num("rf_mutation_Count")?;
+    let stop = table.num("pipe_truncation_count")?;
+    // NaN fails every comparison and is dropped, as in pandas
+    let rows: Vec<usize> = (0..table.rows())
+        .filter(|&i| depth[i] >= params.depth_threshold)
+        .filter(|&i| rate("rate_stop")[i] <= params.rate_stop && rate("rate_mut")[i] < params.rate_mut)
+        .filter(|&i| count[i] >= params.rf_mutation_count && stop[i] >= params.pipe_truncation_count)
+        .collect();
+    // rates are normalised over the rows before the acc filter
+    let mut rates: Vec<(String, Vec<f64>)> = rates
+        .into_iter()
+        .map(|(name, v)| {
+            let kept: Vec<f64> = rows.iter().map(|&i| v[i]).collect();
+            (name, remap(&normalize_by_percentile(&kept, 5.0, 95.0)))
+        })
+        .collect();
+    let mut keep: Vec<bool> = vec![true; rows.len()];
+    if let (true, Some(Column::Num(acc))) = (params.filter_acc, table.get("acc")) {
+        keep = rows.iter().map(|&i| acc[i].is_nan() || acc[i] >= params.acc_threshold).collect();
+    }
+    let select = |v: &[f64]| -> Vec<f64> { v.iter().zip(&keep).filter(|(_, k)| **k).map(|(x, _)| *x).collect() };
+    let mut columns: Vec<(String, Vec<f64>)> = Vec::new();
+    for (name, column) in &table.columns {
+        if DROPPED_COLUMNS.contains(&name.as_str()) {
+            continue;
+        }
+        match column {
+            Column::Num(v) => columns.push((name.clone(), select(&rows.iter().map(|&i| v[i]).collect::<Vec<_>>()))),
+            Column::Text(_) => return Err(invalid(format!("feature column {} is not numeric", name))),
+        }
+    }
+    for (_, v) in rates.iter_mut() {
+        *v = select(v);
+    }
+    let rows: Vec<usize> = rows.iter().zip(&keep).filter(|(_, k)| **k).map(|(i, _)| *i).collect();
+    columns.extend(rates);
+    let mut matrix = Vec::with_capacity(rows.len() * columns.len());
+    for r in 0..rows.len() {
+        matrix.extend(columns.iter().map(|(_, v)| v[r]));
+    }
+    Ok(Prepared { rows, columns: columns.into_iter().map(|(n, _)| n).collect(), matrix })
+}
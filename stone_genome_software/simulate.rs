@@ -0,0 +1,386 @@
+use clap::Parser;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::Instant;
+
+mod provenance;
+use clap::CommandFactory;
+use provenance::Block;
+
+#[derive(Parser)]
+#[command(name="simulate", author="hyf", version="1.0", about="synthetic countRT and RNA Framework outputs with known structure labels", long_about = None)]
+struct Cli {
+    /// transcripts to simulate, default the sequences of --structure
+    #[arg(short,long)]
+    fasta: Option<String>,
+    /// dot-bracket structures (>name / sequence / structure); transcripts without one get random states
+    #[arg(short,long)]
+    structure: Option<String>,
+    /// output prefix, writes <prefix>.rf.csv, <prefix>.rf.txt, <prefix>_countRT.csv and <prefix>.labels.csv
+    #[arg(short,long)]
+    output: String,
+    /// mean number of reads covering a base
+    #[arg(short,long, default_value_t = 200.0)]
+    depth: f64,
+    /// log-normal sigma of the depth between transcripts
+    #[arg(long, default_value_t = 0.5)]
+    depth_spread: f64,
+    /// modification rate of unpaired bases
+    #[arg(long, default_value_t = 0.05)]
+    unpaired_rate: f64,
+    /// modification rate of paired bases
+    #[arg(long, default_value_t = 0.005)]
+    paired_rate: f64,
+    /// stops and mutations of unmodified bases (RT errors, sequencing errors)
+    #[arg(long, default_value_t = 0.001)]
+    background: f64,
+    /// log-normal sigma of the per-base depth and modification rate
+    #[arg(short,long, default_value_t = 0.3)]
+    noise: f64,
+    /// fraction of unpaired bases in transcripts without a structure
+    #[arg(long, default_value_t = 0.5)]
+    unpaired_fraction: f64,
+    /// every transcript is written this many times as <name>_<copy>, for genome-sized inputs
+    #[arg(short,long, default_value_t = 1)]
+    copies: usize,
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    #[arg(short,long, default_value_t = 4)]
+    thread: usize,
+}
+
+// rf-count mutation table rows, also the order of the zip_rftxt2 columns
+const MUTATIONS: [&str; 14] = ["AC", "AG", "AT", "CA", "CG", "CT", "GA", "GC", "GT", "TA", "TC", "TG", "ins", "del"];
+// share of the mutations of a modified base that are insertions and deletions
+const INS_SHARE: f64 = 0.05;
+const DEL_SHARE: f64 = 0.05;
+// transcripts simulated in parallel before their lines are written
+const BATCH: usize = 256;
+
+struct Params {
+    depth: f64,
+    depth_spread: f64,
+    unpaired_rate: f64,
+    paired_rate: f64,
+    background: f64,
+    noise: f64,
+    unpaired_fraction: f64,
+}
+
+struct Transcript {
+    name: String,
+    seq: Vec<u8>,
+    // 1 unpaired, 0 paired, same convention as modified_string; None without a structure
+    unpaired: Option<Vec<u8>>,
+}
+
+// the lines of one transcript in each output
+struct Simulated {
+    rf_csv: String,
+    rf_txt: String,
+    pipe: String,
+    labels: String,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// >name records of a FASTA or .dot file, sequence letters and structure characters kept apart
+fn read_records(file_path: &str) -> io::Result<Vec<(String, String, String)>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut records: Vec<(String, String, String)> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('>') {
+            records.push((header.split_whitespace().next().unwrap_or("").to_string(), String::new(), String::new()));
+        } else if let Some((_, seq, db)) = records.last_mut() {
+            if line.chars().any(|c| c.is_ascii_alphabetic()) {
+                seq.push_str(line);
+            } else {
+                // RNAfold style energy after the structure
+                db.push_str(line.split_whitespace().next().unwrap_or(""));
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn to_dna(seq: &str) -> Vec<u8> {
+    seq.bytes()
+        .map(|b| match b.to_ascii_uppercase() {
+            b'U' => b'T',
+            b @ (b'A' | b'C' | b'G' | b'T') => b,
+            _ => b'N',
+        })
+        .collect()
+}
+
+fn unpaired_of(db: &str) -> Vec<u8> {
+    db.bytes().map(|c| if c == b'.' || c == b':' || c == b',' || c == b'_' || c == b'-' { 1 } else { 0 }).collect()
+}
+
+fn load_transcripts(fasta: Option<&str>, structure: Option<&str>) -> io::Result<Vec<Transcript>> {
+    let dot = match structure {
+        Some(path) => read_records(path)?,
+        None => Vec::new(),
+    };
+    let mut structures: HashMap<String, (usize, String)> = HashMap::new();
+    for (name, seq, db) in &dot {
+        if seq.len() != db.len() {
+            return Err(invalid(format!("{}: sequence length {} structure length {}", name, seq.len(), db.len())));
+        }
+        structures.insert(name.clone(), (seq.len(), db.clone()));
+    }
+    let records = match fasta {
+        Some(path) => read_records(path)?,
+        None => dot,
+    };
+    let mut transcripts = Vec::with_capacity(records.len());
+    for (name, seq, _) in records {
+        if seq.is_empty() {
+            continue;
+        }
+        let unpaired = match structures.get(&name) {
+            Some((len, db)) if *len == seq.len() => Some(unpaired_of(db)),
+            Some((len, _)) => return Err(invalid(format!("{}: sequence length {} structure length {}", name, seq.len(), len))),
+            None => None,
+        };
+        transcripts.push(Transcript { name, seq: to_dna(&seq), unpaired });
+    }
+    Ok(transcripts)
+}
+
+// standard normal, Box-Muller
+fn normal(rng: &mut StdRng) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+}
+
+// log-normal factor with mean 1
+fn spread(rng: &mut StdRng, sigma: f64) -> f64 {
+    if sigma <= 0.0 {
+        return 1.0;
+    }
+    (sigma * normal(rng) - sigma * sigma / 2.0).exp()
+}
+
+// Knuth for small means, normal approximation above
+fn poisson(rng: &mut StdRng, lambda: f64) -> u32 {
+    if lambda <= 0.0 {
+        return 0;
+    }
+    if lambda > 30.0 {
+        return (lambda + lambda.sqrt() * normal(rng)).round().max(0.0) as u32;
+    }
+    let limit = (-lambda).exp();
+    let mut k = 0;
+    let mut p = rng.gen::<f64>();
+    while p > limit {
+        k += 1;
+        p *= rng.gen::<f64>();
+    }
+    k
+}
+
+// geometric waiting times for small means, normal approximation above
+fn binomial(rng: &mut StdRng, n: u32, p: f64) -> u32 {
+    if n == 0 || p <= 0.0 {
+        return 0;
+    }
+    if p >= 1.0 {
+        return n;
+    }
+    if p > 0.5 {
+        return n - binomial(rng, n, 1.0 - p);
+    }
+    let mean = n as f64 * p;
+    if mean > 30.0 {
+        let sd = (mean * (1.0 - p)).sqrt();
+        return (mean + sd * normal(rng)).round().clamp(0.0, n as f64) as u32;
+    }
+    let log_q = (1.0 - p).ln();
+    let (mut k, mut trials) = (0, 0u64);
+    loop {
+        let u: f64 = 1.0 - rng.gen::<f64>();
+        trials += (u.ln() / log_q).floor() as u64 + 1;
+        if trials > n as u64 {
+            return k;
+        }
+        k += 1;
+    }
+}
+
+// the 14 mutation counts of a base with `count` mutations: insertions, deletions, and the rest
+// spread evenly over the three substitutions of the reference base
+fn split_mutations(rng: &mut StdRng, base: u8, count: u32) -> [u32; 14] {
+    let mut row = [0u32; 14];
+    let ins = binomial(rng, count, INS_SHARE);
+    let del = binomial(rng, count - ins, DEL_SHARE / (1.0 - INS_SHARE));
+    row[12] = ins;
+    row[13] = del;
+    let first = match base {
+        b'A' => 0,
+        b'C' => 3,
+        b'G' => 6,
+        b'T' => 9,
+        _ => {
+            row[13] += count - ins - del;
+            return row;
+        }
+    };
+    let mut rest = count - ins - del;
+    for k in 0..2 {
+        let n = binomial(rng, rest, 1.0 / (3 - k) as f64);
+        row[first + k] = n;
+        rest -= n;
+    }
+    row[first + 2] = rest;
+    row
+}
+
+fn simulate(t: &Transcript, copy: Option<usize>, params: &Params, seed: u64) -> Simulated {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let name = match copy {
+        Some(c) => format!("{}_{}", t.name, c),
+        None => t.name.clone(),
+    };
+    let len = t.seq.len();
+    let unpaired: Vec<u8> = match &t.unpaired {
+        Some(u) => u.clone(),
+        None => (0..len).map(|_| rng.gen_bool(params.unpaired_fraction.clamp(0.0, 1.0)) as u8).collect(),
+    };
+    let depth = params.depth * spread(&mut rng, params.depth_spread);
+
+    let mut out = Simulated {
+        rf_csv: format!("{}\n", name),
+        rf_txt: format!("{}\n", name),
+        pipe: String::new(),
+        labels: String::new(),
+    };
+    let mut table: Vec<[u32; 14]> = Vec::with_capacity(len);
+    for (i, (&base, &state)) in t.seq.iter().zip(&unpaired).enumerate() {
+        let rate = if state == 1 { params.unpaired_rate } else { params.paired_rate };
+        let rate = (rate * spread(&mut rng, params.noise)).min(1.0);
+        let hit = (rate + params.background).min(1.0);
+
+        // countRT: reads stopping at the base among the reads covering it
+        let mean = depth * spread(&mut rng, params.noise);
+        let bd_pipe = poisson(&mut rng, mean);
+        let rt_pipe = binomial(&mut rng, bd_pipe, hit);
+        // rf-count: reads with a mutation at the base
+        let mean = depth * spread(&mut rng, params.noise);
+        let bd_rf = poisson(&mut rng, mean);
+        let mut_rf = binomial(&mut rng, bd_rf, hit);
+        table.push(split_mutations(&mut rng, base, mut_rf));
+
+        let base = base as char;
+        out.rf_csv.push_str(&format!("{},{},{}\n", base, mut_rf, bd_rf));
+        out.pipe.push_str(&format!("{},+,{},{},{},{}\n", name, i + 1, base, rt_pipe, bd_pipe));
+        out.labels.push_str(&format!("{},{},{},{},{:.6}\n", name, i + 1, base, state, rate));
+    }
+    for (k, label) in MUTATIONS.iter().enumerate() {
+        let values: Vec<String> = table.iter().map(|row| row[k].to_string()).collect();
+        out.rf_txt.push_str(&format!("{}\t{}\n", label, values.join(",")));
+    }
+    out
+}
+
+fn main() -> io::Result<()> {
+    let start = Instant::now();
+    let cli = Cli::parse();
+    if cli.fasta.is_none() && cli.structure.is_none() {
+        eprintln!("simulate needs --fasta, --structure or both");
+        std::process::exit(1);
+    }
+    let transcripts = load_transcripts(cli.fasta.as_deref(), cli.structure.as_deref())?;
+    let without = transcripts.iter().filter(|t| t.unpaired.is_none()).count();
+    // the zip tools find transcript lines by their first letter
+    let unreadable: Vec<&str> = transcripts.iter().map(|t| t.name.as_str()).filter(|n| !n.starts_with(|c: char| c.is_ascii_alphabetic())).collect();
+    if !unreadable.is_empty() {
+        eprintln!("warning: {} names do not start with a letter and are not read back by zip_rfcsv and zip_rftxt2: {}", unreadable.len(), unreadable.join(" "));
+    }
+    println!("{} transcripts, {} without a structure, {} copies", transcripts.len(), without, cli.copies);
+    let params = Params {
+        depth: cli.depth,
+        depth_spread: cli.depth_spread,
+        unpaired_rate: cli.unpaired_rate,
+        paired_rate: cli.paired_rate,
+        background: cli.background,
+        noise: cli.noise,
+        unpaired_fraction: cli.unpaired_fraction,
+    };
+
+    let create = |suffix: &str| -> io::Result<BufWriter<File>> { Ok(BufWriter::new(File::create(format!("{}{}", cli.output, suffix))?)) };
+    let mut rf_csv = create(".rf.csv")?;
+    let mut rf_txt = create(".rf.txt")?;
+    let mut pipe = create("_countRT.csv")?;
+    let mut labels = create(".labels.csv")?;
+
+    // countRT header after sed 's/\t/,/g'
+    let pipe_name = format!("{}_countRT.c", cli.output);
+    writeln!(pipe, "@ColNum,6")?;
+    for (i, col) in ["ChrID", "Strand", "ChrPos", "Base", &format!("RT_{}", pipe_name), &format!("BD_{}", pipe_name)].iter().enumerate() {
+        writeln!(pipe, "@{},{}", col, i + 1)?;
+    }
+    let mut block = Block::new("simulate", Cli::command().get_version().unwrap_or("unknown"));
+    if let Some(f) = &cli.fasta {
+        block.input(f);
+    }
+    if let Some(s) = &cli.structure {
+        block.input(s);
+    }
+    for (key, value) in [
+        ("depth", cli.depth), ("depth_spread", cli.depth_spread), ("unpaired_rate", cli.unpaired_rate), ("paired_rate", cli.paired_rate),
+        ("background", cli.background), ("noise", cli.noise), ("unpaired_fraction", cli.unpaired_fraction),
+    ] {
+        block.set("setting", format!("{}={}", key, value));
+    }
+    block.set("setting", format!("copies={}", cli.copies)).set("setting", format!("seed={}", cli.seed));
+    for line in block.lines('#') {
+        writeln!(labels, "{}", line)?;
+    }
+    // modified_string: 1 unpaired, 0 paired; mod_rate: modification rate before background
+    writeln!(labels, "ChrID,ChrPos,Base,modified_string,mod_rate")?;
+
+    // one seed per (copy, transcript), so the output does not depend on --thread
+    let jobs: Vec<(usize, usize)> = (0..cli.copies).flat_map(|c| (0..transcripts.len()).map(move |t| (c, t))).collect();
+    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
+    let mut positions = 0usize;
+    for batch in jobs.chunks(BATCH) {
+        let simulated: Vec<Simulated> = pool.install(|| {
+            batch
+                .par_iter()
+                .map(|&(c, t)| {
+                    let copy = if cli.copies > 1 { Some(c + 1) } else { None };
+                    let seed = cli.seed.wrapping_mul(0x9e37_79b9_7f4a_7c15).wrapping_add((c * transcripts.len() + t) as u64);
+                    simulate(&transcripts[t], copy, &params, seed)
+                })
+                .collect()
+        });
+        for (s, &(_, t)) in simulated.iter().zip(batch) {
+            rf_csv.write_all(s.rf_csv.as_bytes())?;
+            rf_txt.write_all(s.rf_txt.as_bytes())?;
+            pipe.write_all(s.pipe.as_bytes())?;
+            labels.write_all(s.labels.as_bytes())?;
+            positions += transcripts[t].seq.len();
+        }
+    }
+    rf_csv.flush()?;
+    rf_txt.flush()?;
+    pipe.flush()?;
+    labels.flush()?;
+    println!("{} transcripts, {} positions written to {}.*", jobs.len(), positions, cli.output);
+    println!("Total run time: {:?}", start.elapsed());
+    Ok(())
+}
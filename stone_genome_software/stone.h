@@ -0,0 +1,163 @@
+/* STONE signal processing C ABI, generated by cbindgen from stone_ffi.rs; do not edit. */
+
+#ifndef STONE_H
+#define STONE_H
+
+
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define STONE_ABI_VERSION 1
+
+typedef enum StoneStatus {
+  STONE_STATUS_OK = 0,
+  // a pointer argument was NULL
+  STONE_STATUS_ERR_NULL = 1,
+  // the file could not be opened or read
+  STONE_STATUS_ERR_IO = 2,
+  // the file or an argument is not what was expected
+  STONE_STATUS_ERR_INVALID = 3,
+  // an index or buffer length is out of range
+  STONE_STATUS_ERR_RANGE = 4,
+  // internal error, the library is still usable
+  STONE_STATUS_ERR_PANIC = 5,
+} StoneStatus;
+
+// The per-position features of genome_model_output.py, in its order.
+typedef enum StoneFeature {
+  STONE_FEATURE_RATE_A = 0,
+  STONE_FEATURE_RATE_T = 1,
+  STONE_FEATURE_RATE_C = 2,
+  STONE_FEATURE_RATE_G = 3,
+  STONE_FEATURE_RATE_STOP = 4,
+  STONE_FEATURE_RATE_MUT = 5,
+} StoneFeature;
+
+// A merged file read into memory, one profile per transcript in file order.
+typedef struct StoneMerged StoneMerged;
+
+// One transcript (ChrID and strand) of a merged file.
+typedef struct StoneProfile StoneProfile;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// STONE_ABI_VERSION of the loaded library, to compare with the one of the header.
+uint32_t stone_abi_version(void);
+
+// Copies the message of the last failed call on this thread into `buf` (NUL-terminated, cut to
+// `len`) and returns the full message length without the NUL. `buf` may be NULL to get the length.
+//
+// # Safety
+// `buf` is NULL or points to `len` writable bytes.
+size_t stone_last_error(char *buf, size_t len);
+
+// Reads a merge output ('#' lines skipped) into `*out`.
+//
+// # Safety
+// `path` is a NUL-terminated string, `out` points to a writable pointer.
+enum StoneStatus stone_merged_open(const char *path, struct StoneMerged **out);
+
+// Frees a StoneMerged and its profiles, NULL is ignored.
+//
+// # Safety
+// `merged` comes from stone_merged_open and is not used afterwards.
+void stone_merged_free(struct StoneMerged *merged);
+
+// Number of transcripts in the file.
+//
+// # Safety
+// `merged` comes from stone_merged_open, `count` points to a writable size_t.
+enum StoneStatus stone_merged_count(const struct StoneMerged *merged, size_t *count);
+
+// The transcript at `index` (0 .. count-1), for iterating over the file.
+//
+// # Safety
+// `merged` comes from stone_merged_open, `out` points to a writable pointer.
+enum StoneStatus stone_merged_profile(const struct StoneMerged *merged,
+                                      size_t index,
+                                      const struct StoneProfile **out);
+
+// The transcript of `chr_id` on `strand` ('+' or '-').
+//
+// # Safety
+// `merged` comes from stone_merged_open, `chr_id` is a NUL-terminated string, `out` points to a writable pointer.
+enum StoneStatus stone_merged_find(const struct StoneMerged *merged,
+                                   const char *chr_id,
+                                   char strand,
+                                   const struct StoneProfile **out);
+
+// ChrID of a profile, valid as long as its StoneMerged; NULL for a NULL profile.
+//
+// # Safety
+// `profile` comes from stone_merged_profile or stone_merged_find.
+const char *stone_profile_chr_id(const struct StoneProfile *profile);
+
+// Strand of a profile, '+' or '-'; 0 for a NULL profile.
+//
+// # Safety
+// `profile` comes from stone_merged_profile or stone_merged_find.
+char stone_profile_strand(const struct StoneProfile *profile);
+
+// Number of positions of a profile, the length of every array below; 0 for a NULL profile.
+//
+// # Safety
+// `profile` comes from stone_merged_profile or stone_merged_find.
+size_t stone_profile_len(const struct StoneProfile *profile);
+
+// 1-based positions of a profile into `out` of `len` elements.
+//
+// # Safety
+// `profile` comes from stone_merged_profile or stone_merged_find, `out` points to `len` writable values.
+enum StoneStatus stone_profile_positions(const struct StoneProfile *profile,
+                                         uint32_t *out,
+                                         size_t len);
+
+// A feature (a StoneFeature) of every position (count over rf_mutation_Depth, NaN where the position has no RF or
+// pipe data) into `out` of `len` elements.
+//
+// # Safety
+// `profile` comes from stone_merged_profile or stone_merged_find, `out` points to `len` writable values.
+enum StoneStatus stone_profile_feature(const struct StoneProfile *profile,
+                                       uint32_t feature,
+                                       double *out,
+                                       size_t len);
+
+// The score genome_model_output.py gives a feature (a StoneFeature): normalised between the `lower` and `upper`
+// percentiles of the profile (5 and 95 there) and remapped; NaN positions score 0.
+//
+// # Safety
+// `profile` comes from stone_merged_profile or stone_merged_find, `out` points to `len` writable values.
+enum StoneStatus stone_profile_score(const struct StoneProfile *profile,
+                                     uint32_t feature,
+                                     double lower,
+                                     double upper,
+                                     double *out,
+                                     size_t len);
+
+// normalize_by_percentile of genome_model_output.py over `len` values, `out` may be `values`.
+//
+// # Safety
+// `values` and `out` point to `len` values.
+enum StoneStatus stone_normalize_by_percentile(const double *values,
+                                               size_t len,
+                                               double lower,
+                                               double upper,
+                                               double *out);
+
+// remap_values of genome_model_output.py over `len` values, `out` may be `values`.
+//
+// # Safety
+// `values` and `out` point to `len` values.
+enum StoneStatus stone_remap_values(const double *values, size_t len, double *out);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* STONE_H */
@@ -0,0 +1,720 @@
+mod config;
+mod manifest;
+mod provenance;
+mod rc;
+mod serve;
+
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use config::Config;
+use manifest::{FileRecord, Manifest};
+use provenance::Block;
+use serve::{Hits, Index};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Parser)]
+#[command(name="stone", author="hyf", version="1.0", about="STONE genome workflow driver", long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// zip -> merge -> bgsg -> mbreport for every sample of a sample sheet
+    Run(RunArgs),
+    /// print the provenance lines of stone outputs, oldest stage first
+    Info(InfoArgs),
+    /// write the <file>.stidx index stone serve uses, for files too large to index on startup
+    Index(IndexArgs),
+    /// local HTTP API over indexed merged, bgsg and genome_model_output.py files
+    Serve(ServeArgs),
+}
+
+#[derive(Args)]
+struct IndexArgs {
+    #[arg(required = true)]
+    files: Vec<String>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// merged, bgsg or genome_model_output.py csv files, indexed on first use
+    #[arg(required = true)]
+    files: Vec<String>,
+    /// falls back to [serve] host
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    /// falls back to [serve] port, 0 picks a free one
+    #[arg(short,long, default_value_t = 8080)]
+    port: u16,
+    /// requests answered at the same time, falls back to [serve] threads
+    #[arg(short,long, default_value_t = 4)]
+    threads: usize,
+    /// largest number of rows one request returns, falls back to [serve] max_rows
+    #[arg(long, default_value_t = 1000000)]
+    max_rows: usize,
+    /// stone.toml
+    #[arg(long)]
+    config: Option<String>,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    files: Vec<String>,
+    /// rehash the recorded inputs that still exist and report changed or missing ones
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// tab separated: sample rf_csv rf_txt pipe, optional bed species strand depth fasta; rf_csv may be an RNA Framework .rc file
+    #[arg(long)]
+    samples: String,
+    #[arg(short,long)]
+    outdir: String,
+    /// default bed for samples without one, falls back to [run] bed
+    #[arg(short,long)]
+    bed: Option<String>,
+    /// falls back to [bgsg] species
+    #[arg(short,long, default_value = "hu")]
+    species: String,
+    /// falls back to [zip] strand
+    #[arg(long, default_value = "+")]
+    strand: String,
+    /// mbreport minimum depth, falls back to [mbreport] depth
+    #[arg(short,long, default_value_t = 10)]
+    depth: u32,
+    /// indexed reference passed to merge and bgsg, falls back to [reference] fasta
+    #[arg(short,long)]
+    fasta: Option<String>,
+    /// total threads shared by all samples
+    #[arg(short,long, default_value_t = 32)]
+    threads: usize,
+    /// threads of each stage, threads / sample_threads samples run at the same time
+    #[arg(long, default_value_t = 8)]
+    sample_threads: usize,
+    /// directory of the zip_*, merge, bgsg and mbreport binaries, default next to stone or PATH
+    #[arg(long)]
+    bin_dir: Option<String>,
+    /// rerun every stage, also up to date ones or ones run with other parameters
+    #[arg(long)]
+    force: bool,
+    /// stone.toml, fills in options not given on the command line and is passed on to every stage
+    #[arg(long)]
+    config: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    name: String,
+    rf_csv: String,
+    rf_txt: String,
+    pipe: String,
+    bed: Option<String>,
+    species: String,
+    strand: String,
+    depth: u32,
+    fasta: Option<String>,
+}
+
+struct Stage {
+    name: &'static str,
+    program: &'static str,
+    args: Vec<String>,
+    // settings that change the output, thread counts are left out
+    params: Vec<String>,
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+}
+
+enum Check {
+    Fresh,
+    Stale(String),
+    ParamsChanged(String),
+}
+
+struct Outcome {
+    sample: String,
+    status: &'static str,
+    ran: usize,
+    skipped: usize,
+    failed: Option<String>,
+    seconds: f64,
+}
+
+fn read_samples(file_path: &str, args: &RunArgs) -> io::Result<Vec<Sample>> {
+    let reader = BufReader::new(File::open(file_path)?);
+    let mut lines = reader.lines();
+    let header: Vec<String> = match lines.next() {
+        Some(h) => h?.split('\t').map(|s| s.trim().to_string()).collect(),
+        None => return Ok(Vec::new()),
+    };
+    let idx = |name: &str| header.iter().position(|h| h == name);
+    let missing = |what: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: no {} column", file_path, what));
+    let name_idx = idx("sample").ok_or_else(|| missing("sample"))?;
+    let csv_idx = idx("rf_csv").ok_or_else(|| missing("rf_csv"))?;
+    let txt_idx = idx("rf_txt").ok_or_else(|| missing("rf_txt"))?;
+    let pipe_idx = idx("pipe").ok_or_else(|| missing("pipe"))?;
+
+    let mut samples = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split('\t').map(|s| s.trim()).collect();
+        let get = |i: Option<usize>| i.and_then(|i| parts.get(i)).filter(|s| !s.is_empty()).map(|s| s.to_string());
+        let name = get(Some(name_idx)).ok_or_else(|| missing("sample value"))?;
+        let required = |i: usize, what: &str| {
+            get(Some(i)).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {} has no {}", file_path, name, what)))
+        };
+        samples.push(Sample {
+            rf_csv: required(csv_idx, "rf_csv")?,
+            rf_txt: required(txt_idx, "rf_txt")?,
+            pipe: required(pipe_idx, "pipe")?,
+            bed: get(idx("bed")).or_else(|| args.bed.clone()),
+            species: get(idx("species")).unwrap_or_else(|| args.species.clone()),
+            strand: get(idx("strand")).unwrap_or_else(|| args.strand.clone()),
+            depth: get(idx("depth")).and_then(|d| d.parse().ok()).unwrap_or(args.depth),
+            fasta: get(idx("fasta")).or_else(|| args.fasta.clone()),
+            name,
+        });
+    }
+    Ok(samples)
+}
+
+// options the stages read from --config themselves, without the ones stone passes explicitly
+fn config_params(config: &Config, section: &str, passed: &[&str]) -> Vec<String> {
+    config
+        .section(section)
+        .into_iter()
+        .filter(|kv| !passed.iter().any(|key| kv.starts_with(&format!("{}.{}=", section, key))))
+        .collect()
+}
+
+fn stages(sample: &Sample, dir: &Path, threads: usize, config: &Config) -> Vec<Stage> {
+    let p = |name: &str| dir.join(name);
+    let s = |path: &Path| path.display().to_string();
+    let t = threads.to_string();
+    let config_arg: Vec<String> = match &config.path {
+        Some(path) => vec!["--config".into(), path.clone()],
+        None => Vec::new(),
+    };
+    // an RC file goes to merge as it is
+    let rc_input = rc::is_rc(&sample.rf_csv);
+    let rf_csv_zip = if rc_input { PathBuf::from(&sample.rf_csv) } else { p("rf_csv.zip") };
+    let mut stages = vec![
+        Stage {
+            name: "zip_rfcsv",
+            program: "zip_rfcsv",
+            args: vec!["-i".into(), sample.rf_csv.clone(), "-o".into(), s(&p("rf_csv.zip")), "-t".into(), t.clone(), "-s".into(), sample.strand.clone()].into_iter().chain(config_arg.clone()).collect(),
+            params: vec![format!("strand={}", sample.strand)],
+            inputs: vec![PathBuf::from(&sample.rf_csv)],
+            output: p("rf_csv.zip"),
+        },
+        Stage {
+            name: "zip_rftxt",
+            program: "zip_rftxt",
+            args: vec![sample.rf_txt.clone(), s(&p("rf_txt.zip1")), t.clone()],
+            params: vec![],
+            inputs: vec![PathBuf::from(&sample.rf_txt)],
+            output: p("rf_txt.zip1"),
+        },
+        Stage {
+            name: "zip_rftxt2",
+            program: "zip_rftxt2",
+            args: vec!["-i".into(), s(&p("rf_txt.zip1")), "-o".into(), s(&p("rf_txt.zip")), "-t".into(), t.clone(), "-s".into(), sample.strand.clone()].into_iter().chain(config_arg.clone()).collect(),
+            params: vec![format!("strand={}", sample.strand)],
+            inputs: vec![p("rf_txt.zip1")],
+            output: p("rf_txt.zip"),
+        },
+        Stage {
+            name: "zip_pipe",
+            program: "zip_pipe",
+            args: vec![sample.pipe.clone(), s(&p("pipe.zip"))],
+            params: vec![],
+            inputs: vec![PathBuf::from(&sample.pipe)],
+            output: p("pipe.zip"),
+        },
+    ];
+    if rc_input {
+        stages.retain(|stage| stage.name != "zip_rfcsv");
+    }
+    let mut merge_args = vec!["-c".into(), s(&rf_csv_zip), "-p".into(), s(&p("rf_txt.zip")), "-t".into(), s(&p("pipe.zip")), "-o".into(), s(&p("merged.csv"))];
+    let mut merge_inputs = vec![rf_csv_zip, p("rf_txt.zip"), p("pipe.zip")];
+    let fasta_param = format!("fasta={}", sample.fasta.is_some());
+    let mut merge_params = vec![fasta_param.clone()];
+    if rc_input {
+        merge_args.extend(["-s".into(), sample.strand.clone()]);
+        merge_params.push(format!("strand={}", sample.strand));
+        merge_inputs.extend(rc::index_path(&sample.rf_csv));
+    }
+    if let Some(f) = &sample.fasta {
+        merge_args.extend(["-f".into(), f.clone()]);
+        merge_inputs.push(PathBuf::from(f));
+    }
+    merge_args.extend(config_arg.clone());
+    stages.push(Stage { name: "merge", program: "merge", args: merge_args, params: merge_params, inputs: merge_inputs, output: p("merged.csv") });
+
+    // mbreport summarises bgsg output, both need a bed
+    if let Some(bed) = &sample.bed {
+        let mut bgsg_args = vec!["-m".into(), s(&p("merged.csv")), "-b".into(), bed.clone(), "-o".into(), s(&p("bgsg.csv")), "-s".into(), sample.species.clone(), "-t".into(), t.clone()];
+        let mut bgsg_inputs = vec![p("merged.csv"), PathBuf::from(bed)];
+        if let Some(f) = &sample.fasta {
+            bgsg_args.extend(["-f".into(), f.clone()]);
+            bgsg_inputs.push(PathBuf::from(f));
+        }
+        bgsg_args.extend(config_arg.clone());
+        let mut bgsg_params = vec![format!("species={}", sample.species), fasta_param];
+        bgsg_params.extend(config_params(config, "bgsg", &["species", "thread"]));
+        stages.push(Stage { name: "bgsg", program: "bgsg", args: bgsg_args, params: bgsg_params, inputs: bgsg_inputs, output: p("bgsg.csv") });
+        stages.push(Stage {
+            name: "mbreport",
+            program: "mbreport",
+            args: vec!["-i".into(), s(&p("bgsg.csv")), "-d".into(), sample.depth.to_string(), "-o".into(), s(&p("mbreport.csv"))]
+                .into_iter()
+                .chain(config_arg)
+                .collect(),
+            params: [format!("depth={}", sample.depth)].into_iter().chain(config_params(config, "mbreport", &["depth"])).collect(),
+            inputs: vec![p("bgsg.csv")],
+            output: p("mbreport.csv"),
+        });
+    }
+    stages
+}
+
+// compare a stage with the manifest of its previous run
+fn check(stage: &Stage, version: &str) -> Check {
+    let old = match Manifest::read(&manifest::path_for(&stage.output)) {
+        Ok(m) => m,
+        Err(_) => return Check::Stale("no manifest".to_string()),
+    };
+    if old.params != stage.params {
+        return Check::ParamsChanged(format!("{} was made with {} not {}", stage.output.display(), old.params.join(" "), stage.params.join(" ")));
+    }
+    if old.version != version {
+        return Check::Stale(format!("tool version {} -> {}", old.version, version));
+    }
+    if old.inputs.len() != stage.inputs.len() {
+        return Check::Stale("inputs changed".to_string());
+    }
+    for input in &stage.inputs {
+        let recorded = old.input(&input.display().to_string());
+        match (recorded, manifest::file_record(input, recorded)) {
+            (Some(r), Ok(now)) if r.sha256 == now.sha256 => {}
+            _ => return Check::Stale(format!("{} changed", input.display())),
+        }
+    }
+    match (&old.output, manifest::file_record(&stage.output, old.output.as_ref())) {
+        (Some(r), Ok(now)) if r.sha256 == now.sha256 => Check::Fresh,
+        _ => Check::Stale(format!("{} missing or modified", stage.output.display())),
+    }
+}
+
+fn write_manifest(stage: &Stage, version: &str) -> io::Result<()> {
+    let path = manifest::path_for(&stage.output);
+    // hashes of unchanged files are taken from the previous manifest
+    let old = Manifest::read(&path).ok();
+    let cached = |p: &Path| old.as_ref().and_then(|m| m.input(&p.display().to_string()).cloned());
+    let inputs = stage
+        .inputs
+        .iter()
+        .map(|i| manifest::file_record(i, cached(i).as_ref()))
+        .collect::<io::Result<Vec<FileRecord>>>()?;
+    let m = Manifest {
+        stage: stage.name.to_string(),
+        tool: stage.program.to_string(),
+        version: version.to_string(),
+        params: stage.params.clone(),
+        inputs,
+        output: Some(manifest::file_record(&stage.output, None)?),
+        created: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+    };
+    m.write(&path)
+}
+
+// "<name> <version>" from clap's --version, tools without it are "unknown"
+fn tool_version(program: &Path) -> String {
+    Command::new(program)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn find_program(name: &str, bin_dir: Option<&str>) -> PathBuf {
+    if let Some(dir) = bin_dir {
+        return Path::new(dir).join(name);
+    }
+    if let Some(dir) = std::env::current_exe().ok().and_then(|e| e.parent().map(Path::to_path_buf)) {
+        if dir.join(name).is_file() {
+            return dir.join(name);
+        }
+    }
+    PathBuf::from(name)
+}
+
+fn run_sample(sample: &Sample, args: &RunArgs, config: &Config, versions: &HashMap<&str, String>) -> Outcome {
+    let now = Instant::now();
+    let mut outcome = Outcome { sample: sample.name.clone(), status: "ok", ran: 0, skipped: 0, failed: None, seconds: 0.0 };
+    let dir = Path::new(&args.outdir).join(&sample.name);
+    let log = fs::create_dir_all(&dir).and_then(|_| OpenOptions::new().create(true).append(true).open(dir.join(format!("{}.log", sample.name))));
+    let mut log = match log {
+        Ok(l) => l,
+        Err(e) => {
+            outcome.status = "failed";
+            outcome.failed = Some(format!("log: {}", e));
+            return outcome;
+        }
+    };
+
+    for stage in stages(sample, &dir, args.sample_threads, config) {
+        let version = &versions[stage.program];
+        if !args.force {
+            match check(&stage, version) {
+                Check::Fresh => {
+                    let _ = writeln!(log, "== {} up to date, skipped", stage.name);
+                    outcome.skipped += 1;
+                    continue;
+                }
+                Check::ParamsChanged(why) => {
+                    let reason = format!("{}: {}, use --force or another --outdir", stage.name, why);
+                    let _ = writeln!(log, "== {}", reason);
+                    outcome.status = "failed";
+                    outcome.failed = Some(reason);
+                    break;
+                }
+                Check::Stale(why) => {
+                    let _ = writeln!(log, "== {} rerun: {}", stage.name, why);
+                }
+            }
+        }
+        let program = find_program(stage.program, args.bin_dir.as_deref());
+        let _ = writeln!(log, "== {} {} {}", stage.name, program.display(), stage.args.join(" "));
+        let started = Instant::now();
+        let status = log.try_clone().and_then(|out| {
+            let err = out.try_clone()?;
+            Command::new(&program)
+                .args(&stage.args)
+                .env("RAYON_NUM_THREADS", args.sample_threads.to_string())
+                .stdout(Stdio::from(out))
+                .stderr(Stdio::from(err))
+                .status()
+        });
+        match status {
+            Ok(s) if s.success() => {
+                let _ = writeln!(log, "== {} finished in {:?}", stage.name, started.elapsed());
+                if let Err(e) = write_manifest(&stage, version) {
+                    let _ = writeln!(log, "== {} manifest not written: {}", stage.name, e);
+                }
+                outcome.ran += 1;
+            }
+            other => {
+                let reason = match other {
+                    Ok(s) => format!("{} exited with {}", stage.name, s),
+                    Err(e) => format!("{}: {}", stage.name, e),
+                };
+                let _ = writeln!(log, "== {}", reason);
+                // a half written output must not look up to date next time
+                let _ = fs::remove_file(&stage.output);
+                let _ = fs::remove_file(manifest::path_for(&stage.output));
+                outcome.status = "failed";
+                outcome.failed = Some(reason);
+                break;
+            }
+        }
+    }
+    outcome.seconds = now.elapsed().as_secs_f64();
+    outcome
+}
+
+// options left at their default take the value of --config
+fn apply_config(args: &mut RunArgs, matches: &ArgMatches, config: &Config) {
+    let defaulted = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    args.bed = args.bed.take().or(config.str("run", "bed"));
+    args.fasta = args.fasta.take().or(config.str("reference", "fasta"));
+    args.bin_dir = args.bin_dir.take().or(config.str("run", "bin_dir"));
+    if defaulted("species") {
+        args.species = config.str("bgsg", "species").unwrap_or(args.species.clone());
+    }
+    if defaulted("strand") {
+        args.strand = config.str("zip", "strand").unwrap_or(args.strand.clone());
+    }
+    if defaulted("depth") {
+        args.depth = config.usize("mbreport", "depth").map(|d| d as u32).unwrap_or(args.depth);
+    }
+    if defaulted("threads") {
+        args.threads = config.usize("run", "threads").unwrap_or(args.threads);
+    }
+    if defaulted("sample_threads") {
+        args.sample_threads = config.usize("run", "sample_threads").unwrap_or(args.sample_threads);
+    }
+}
+
+fn run(mut args: RunArgs, matches: &ArgMatches) -> io::Result<bool> {
+    let config = Config::load(args.config.as_deref())?;
+    apply_config(&mut args, matches, &config);
+    let samples = read_samples(&args.samples, &args)?;
+    let mut seen = HashSet::new();
+    for s in &samples {
+        if !seen.insert(s.name.clone()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: sample {} listed twice", args.samples, s.name)));
+        }
+    }
+    fs::create_dir_all(&args.outdir)?;
+    let sample_threads = args.sample_threads.clamp(1, args.threads.max(1));
+    let parallel = (args.threads / sample_threads).max(1);
+    println!("{} samples, {} at a time with {} threads each", samples.len(), parallel, sample_threads);
+    let args = RunArgs { sample_threads, ..args };
+    let versions: HashMap<&str, String> = ["zip_rfcsv", "zip_rftxt", "zip_rftxt2", "zip_pipe", "merge", "bgsg", "mbreport"]
+        .into_iter()
+        .map(|p| (p, tool_version(&find_program(p, args.bin_dir.as_deref()))))
+        .collect();
+
+    let pool = ThreadPoolBuilder::new().num_threads(parallel).build().unwrap();
+    let outcomes: Vec<Outcome> = pool.install(|| {
+        samples
+            .par_iter()
+            .map(|s| {
+                let o = run_sample(s, &args, &config, &versions);
+                println!("{}: {} ({} run, {} skipped)", o.sample, o.status, o.ran, o.skipped);
+                o
+            })
+            .collect()
+    });
+
+    let mut summary = BufWriter::new(File::create(Path::new(&args.outdir).join("summary.tsv"))?);
+    let effective = [
+        ("run.bed", args.bed.clone().unwrap_or_else(|| "-".to_string())),
+        ("bgsg.species", args.species.clone()),
+        ("zip.strand", args.strand.clone()),
+        ("mbreport.depth", args.depth.to_string()),
+        ("reference.fasta", args.fasta.clone().unwrap_or_else(|| "-".to_string())),
+        ("run.threads", args.threads.to_string()),
+        ("run.sample_threads", args.sample_threads.to_string()),
+        ("run.bin_dir", args.bin_dir.clone().unwrap_or_else(|| "-".to_string())),
+    ];
+    let mut block = Block::new("stone run", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&args.samples).extend(config::settings(&config, &effective));
+    for line in block.lines('#') {
+        writeln!(summary, "{}", line)?;
+    }
+    writeln!(summary, "sample\tstatus\tstages_run\tstages_skipped\tfailure\tseconds")?;
+    println!("sample\tstatus\tstages_run\tstages_skipped\tfailure\tseconds");
+    for o in &outcomes {
+        let line = format!("{}\t{}\t{}\t{}\t{}\t{:.1}", o.sample, o.status, o.ran, o.skipped, o.failed.as_deref().unwrap_or("-"), o.seconds);
+        writeln!(summary, "{}", line)?;
+        println!("{}", line);
+    }
+    summary.flush()?;
+    let failed = outcomes.iter().filter(|o| o.failed.is_some()).count();
+    println!("{} succeeded, {} failed", outcomes.len() - failed, failed);
+    Ok(failed == 0)
+}
+
+// "ok", "changed" or "missing" for an "input" value "<path>\t<hash>"
+fn check_input(value: &str) -> &'static str {
+    let (path, recorded) = value.split_once('\t').unwrap_or((value, ""));
+    let hash = if recorded.starts_with("fai-sha256:") {
+        provenance::sha256_file(Path::new(&format!("{}.fai", path))).map(|h| format!("fai-sha256:{}", h))
+    } else {
+        provenance::sha256_file(Path::new(path)).map(|h| format!("sha256:{}", h))
+    };
+    match hash {
+        Ok(h) if h == recorded => "ok",
+        Ok(_) => "changed",
+        Err(_) => "missing",
+    }
+}
+
+fn info(args: InfoArgs) -> io::Result<bool> {
+    let mut ok = true;
+    for file in &args.files {
+        let lines = provenance::read(file).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file, e)))?;
+        let blocks = provenance::parse(&lines);
+        if blocks.is_empty() {
+            println!("{}: no provenance lines", file);
+            continue;
+        }
+        println!("{}: {} stages", file, blocks.len());
+        for (i, block) in blocks.iter().enumerate() {
+            for (key, value) in block {
+                match key.as_str() {
+                    "tool" => println!("[{}] {}", i + 1, value),
+                    "input" | "reference" if args.check => {
+                        let state = check_input(value);
+                        ok &= state == "ok";
+                        println!("    {:<10} {}\t{}", key, value, state);
+                    }
+                    _ => println!("    {:<10} {}", key, value),
+                }
+            }
+        }
+    }
+    Ok(ok)
+}
+
+fn index(args: IndexArgs) -> io::Result<bool> {
+    for file in &args.files {
+        let index = Index::build(file)?;
+        index.write()?;
+        println!("{}: {} blocks", file, index.blocks.len());
+    }
+    Ok(true)
+}
+
+enum Format {
+    Json,
+    Csv,
+}
+
+// (status, format, body) of one GET
+fn answer(url: &str, accept: &str, indexes: &[Index], max_rows: usize) -> (u16, Format, String) {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let params: HashMap<String, String> = query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (serve::percent_decode(k, true), serve::percent_decode(v, true)))
+        .collect();
+    let format = match params.get("format").map(|s| s.as_str()) {
+        Some("csv") => Format::Csv,
+        Some(_) => Format::Json,
+        None if accept.contains("text/csv") => Format::Csv,
+        None => Format::Json,
+    };
+    let fail = |status: u16, msg: String| (status, Format::Json, serve::error_json(&msg));
+    let rows = |query: &dyn Fn(&Index) -> Result<Vec<String>, String>, format: Format| {
+        let mut hits = Vec::new();
+        for index in indexes {
+            match query(index) {
+                Ok(rows) if rows.is_empty() => {}
+                Ok(rows) => hits.push(Hits { index, rows }),
+                Err(e) => return fail(413, e),
+            }
+        }
+        if hits.is_empty() {
+            return fail(404, format!("nothing found for {}", serve::percent_decode(path, false)));
+        }
+        match format {
+            Format::Csv => (200, format, serve::rows_csv(&hits)),
+            Format::Json => (200, format, serve::rows_json(&hits)),
+        }
+    };
+    if let Some(id) = path.strip_prefix("/transcript/") {
+        let id = serve::percent_decode(id, false);
+        return rows(&|index: &Index| index.transcript(&id, max_rows), format);
+    }
+    if let Some(region) = path.strip_prefix("/region/") {
+        let (chr, start, end, strand) = match serve::parse_region(&serve::percent_decode(region, false)) {
+            Ok(r) => r,
+            Err(e) => return fail(400, e),
+        };
+        return rows(&|index: &Index| index.region(&chr, start, end, &strand, max_rows), format);
+    }
+    if path == "/genes" {
+        let min_depth = match params.get("min_depth").map(|v| v.parse::<f64>()) {
+            None => 0.0,
+            Some(Ok(d)) => d,
+            Some(Err(_)) => return fail(400, "min_depth should be a number".to_string()),
+        };
+        let genes: Vec<_> = indexes.iter().filter_map(|index| index.genes(min_depth).map(|g| (index, g))).collect();
+        return match format {
+            Format::Csv => (200, format, serve::genes_csv(&genes)),
+            Format::Json => (200, format, serve::genes_json(&genes)),
+        };
+    }
+    fail(404, format!("unknown path {}, use /transcript/<id>, /region/<chr>:<start>-<end>:<strand> or /genes?min_depth=<depth>", path))
+}
+
+fn serve(mut args: ServeArgs, matches: &ArgMatches) -> io::Result<bool> {
+    let config = Config::load(args.config.as_deref())?;
+    let defaulted = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+    if defaulted("host") {
+        args.host = config.str("serve", "host").unwrap_or(args.host);
+    }
+    if defaulted("port") {
+        args.port = config.usize("serve", "port").map(|p| p as u16).unwrap_or(args.port);
+    }
+    if defaulted("threads") {
+        args.threads = config.usize("serve", "threads").unwrap_or(args.threads);
+    }
+    if defaulted("max_rows") {
+        args.max_rows = config.usize("serve", "max_rows").unwrap_or(args.max_rows);
+    }
+    let mut indexes = Vec::new();
+    for file in &args.files {
+        let (index, built) = Index::open(file).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file, e)))?;
+        println!("{}: {} blocks{}", file, index.blocks.len(), if built { ", indexed" } else { "" });
+        indexes.push(index);
+    }
+    let indexes = Arc::new(indexes);
+    let server = tiny_http::Server::http((args.host.as_str(), args.port)).map_err(|e| io::Error::other(format!("{}:{}: {}", args.host, args.port, e)))?;
+    let server = Arc::new(server);
+    // flushed at once so scripts waiting for the line see it
+    println!("listening on http://{}", server.server_addr());
+    io::stdout().flush()?;
+    let workers: Vec<_> = (0..args.threads.max(1))
+        .map(|_| {
+            let (server, indexes) = (Arc::clone(&server), Arc::clone(&indexes));
+            let max_rows = args.max_rows;
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let accept = request.headers().iter().find(|h| h.field.equiv("Accept")).map(|h| h.value.to_string()).unwrap_or_default();
+                    let (status, format, body) = if *request.method() == tiny_http::Method::Get {
+                        answer(request.url(), &accept, &indexes, max_rows)
+                    } else {
+                        (405, Format::Json, serve::error_json("only GET is supported"))
+                    };
+                    let content_type = match format {
+                        Format::Csv => "text/csv; charset=utf-8",
+                        Format::Json => "application/json",
+                    };
+                    let response = tiny_http::Response::from_string(body)
+                        .with_status_code(status)
+                        .with_header(tiny_http::Header::from_bytes("Content-Type", content_type).unwrap())
+                        .with_header(tiny_http::Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap());
+                    if let Err(e) = request.respond(response) {
+                        eprintln!("response failed: {}", e);
+                    }
+                }
+            })
+        })
+        .collect();
+    for w in workers {
+        let _ = w.join();
+    }
+    Ok(true)
+}
+
+fn main() -> io::Result<()> {
+    let now = Instant::now();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let ok = match cli.command {
+        Commands::Run(args) => run(args, matches.subcommand_matches("run").unwrap())?,
+        Commands::Info(args) => info(args)?,
+        Commands::Index(args) => index(args)?,
+        Commands::Serve(args) => serve(args, matches.subcommand_matches("serve").unwrap())?,
+    };
+    println!("Total runtime {:?}", now.elapsed());
+    if !ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
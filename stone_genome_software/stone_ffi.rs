@@ -0,0 +1,356 @@
+// C ABI over the merged-file reader and the model features of signal.rs, for programs that want them
+// in-process (C++ wrappers, Nextflow plugins). Built as a cdylib (libstone_ffi.so); stone.h is made
+// from this file by cbindgen with cbindgen.toml, see readme.txt (18).
+//
+// Rules of the interface:
+// - every call returns a StoneStatus, panics are caught and returned as STONE_STATUS_ERR_PANIC;
+// - the message of the last failed call on a thread is read with stone_last_error;
+// - a StoneMerged is freed with stone_merged_free, the StoneProfile pointers it hands out live as
+//   long as it does and are never freed on their own;
+// - arrays are filled into caller buffers whose length is passed in, never allocated for the caller;
+// - STONE_ABI_VERSION changes whenever a signature or the meaning of a value changes.
+mod signal;
+mod zipped;
+
+use signal::Transcript;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+
+pub const STONE_ABI_VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoneStatus {
+    Ok = 0,
+    /// a pointer argument was NULL
+    ErrNull = 1,
+    /// the file could not be opened or read
+    ErrIo = 2,
+    /// the file or an argument is not what was expected
+    ErrInvalid = 3,
+    /// an index or buffer length is out of range
+    ErrRange = 4,
+    /// internal error, the library is still usable
+    ErrPanic = 5,
+}
+
+/// The per-position features of genome_model_output.py, in its order.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoneFeature {
+    RateA = 0,
+    RateT = 1,
+    RateC = 2,
+    RateG = 3,
+    RateStop = 4,
+    RateMut = 5,
+}
+
+/// One transcript (ChrID and strand) of a merged file.
+pub struct StoneProfile {
+    chr_id: CString,
+    strand: char,
+    positions: Vec<u32>,
+    // indexed by StoneFeature
+    features: Vec<Vec<f64>>,
+}
+
+/// A merged file read into memory, one profile per transcript in file order.
+pub struct StoneMerged {
+    profiles: Vec<StoneProfile>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|e| *e.borrow_mut() = msg);
+}
+
+fn fail(status: StoneStatus, msg: String) -> StoneStatus {
+    set_error(msg);
+    status
+}
+
+// runs `f`, turning a panic into ErrPanic
+fn guard<F: FnOnce() -> StoneStatus>(name: &str, f: F) -> StoneStatus {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(status) => status,
+        Err(e) => {
+            let what = e.downcast_ref::<&str>().map(|s| s.to_string()).or_else(|| e.downcast_ref::<String>().cloned());
+            fail(StoneStatus::ErrPanic, format!("{}: internal error: {}", name, what.unwrap_or_default()))
+        }
+    }
+}
+
+// the feature values of a profile, `feature` being a StoneFeature from C
+unsafe fn feature_values<'a>(profile: *const StoneProfile, feature: u32, name: &str) -> Result<&'a [f64], StoneStatus> {
+    if profile.is_null() {
+        return Err(fail(StoneStatus::ErrNull, format!("{}: profile is NULL", name)));
+    }
+    let profile = &*profile;
+    match profile.features.get(feature as usize) {
+        Some(v) => Ok(v),
+        None => Err(fail(StoneStatus::ErrRange, format!("{}: no feature {}", name, feature))),
+    }
+}
+
+fn io_status(e: &io::Error) -> StoneStatus {
+    match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => StoneStatus::ErrInvalid,
+        _ => StoneStatus::ErrIo,
+    }
+}
+
+fn profile(transcript: Transcript) -> io::Result<StoneProfile> {
+    let positions = transcript
+        .table
+        .num("pipe_truncation_ChrPos")?
+        .iter()
+        .map(|p| *p as u32)
+        .collect();
+    let features = signal::features(&transcript.table)?.into_iter().map(|(_, v)| v).collect();
+    let chr_id = CString::new(transcript.chr_id).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(StoneProfile { chr_id, strand: transcript.strand, positions, features })
+}
+
+// writes `values` to a caller buffer of `len` elements
+unsafe fn fill<T: Copy>(values: &[T], out: *mut T, len: usize) -> StoneStatus {
+    if out.is_null() {
+        return fail(StoneStatus::ErrNull, "output buffer is NULL".to_string());
+    }
+    if len < values.len() {
+        return fail(StoneStatus::ErrRange, format!("buffer of {} for {} values", len, values.len()));
+    }
+    std::ptr::copy(values.as_ptr(), out, values.len());
+    StoneStatus::Ok
+}
+
+/// STONE_ABI_VERSION of the loaded library, to compare with the one of the header.
+#[no_mangle]
+pub extern "C" fn stone_abi_version() -> u32 {
+    STONE_ABI_VERSION
+}
+
+/// Copies the message of the last failed call on this thread into `buf` (NUL-terminated, cut to
+/// `len`) and returns the full message length without the NUL. `buf` may be NULL to get the length.
+///
+/// # Safety
+/// `buf` is NULL or points to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn stone_last_error(buf: *mut c_char, len: usize) -> usize {
+    LAST_ERROR.with(|e| {
+        let msg = e.borrow();
+        let bytes = msg.as_bytes();
+        if !buf.is_null() && len > 0 {
+            let n = bytes.len().min(len - 1);
+            std::ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, buf, n);
+            *buf.add(n) = 0;
+        }
+        bytes.len()
+    })
+}
+
+/// Reads a merge output ('#' lines skipped) into `*out`.
+///
+/// # Safety
+/// `path` is a NUL-terminated string, `out` points to a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn stone_merged_open(path: *const c_char, out: *mut *mut StoneMerged) -> StoneStatus {
+    guard("stone_merged_open", || {
+        if path.is_null() || out.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_merged_open: path or out is NULL".to_string());
+        }
+        *out = std::ptr::null_mut();
+        let path = CStr::from_ptr(path).to_string_lossy().into_owned();
+        let read = signal::read_merged_transcripts(&path).and_then(|ts| ts.into_iter().map(profile).collect::<io::Result<Vec<_>>>());
+        match read {
+            Ok(profiles) => {
+                *out = Box::into_raw(Box::new(StoneMerged { profiles }));
+                StoneStatus::Ok
+            }
+            Err(e) => fail(io_status(&e), format!("{}: {}", path, e)),
+        }
+    })
+}
+
+/// Frees a StoneMerged and its profiles, NULL is ignored.
+///
+/// # Safety
+/// `merged` comes from stone_merged_open and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn stone_merged_free(merged: *mut StoneMerged) {
+    if !merged.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(merged))));
+    }
+}
+
+/// Number of transcripts in the file.
+///
+/// # Safety
+/// `merged` comes from stone_merged_open, `count` points to a writable size_t.
+#[no_mangle]
+pub unsafe extern "C" fn stone_merged_count(merged: *const StoneMerged, count: *mut usize) -> StoneStatus {
+    guard("stone_merged_count", || {
+        if merged.is_null() || count.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_merged_count: merged or count is NULL".to_string());
+        }
+        *count = (*merged).profiles.len();
+        StoneStatus::Ok
+    })
+}
+
+/// The transcript at `index` (0 .. count-1), for iterating over the file.
+///
+/// # Safety
+/// `merged` comes from stone_merged_open, `out` points to a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn stone_merged_profile(merged: *const StoneMerged, index: usize, out: *mut *const StoneProfile) -> StoneStatus {
+    guard("stone_merged_profile", || {
+        if merged.is_null() || out.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_merged_profile: merged or out is NULL".to_string());
+        }
+        let profiles = &(*merged).profiles;
+        match profiles.get(index) {
+            Some(p) => {
+                *out = p;
+                StoneStatus::Ok
+            }
+            None => fail(StoneStatus::ErrRange, format!("stone_merged_profile: index {} of {}", index, profiles.len())),
+        }
+    })
+}
+
+/// The transcript of `chr_id` on `strand` ('+' or '-').
+///
+/// # Safety
+/// `merged` comes from stone_merged_open, `chr_id` is a NUL-terminated string, `out` points to a writable pointer.
+#[no_mangle]
+pub unsafe extern "C" fn stone_merged_find(merged: *const StoneMerged, chr_id: *const c_char, strand: c_char, out: *mut *const StoneProfile) -> StoneStatus {
+    guard("stone_merged_find", || {
+        if merged.is_null() || chr_id.is_null() || out.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_merged_find: merged, chr_id or out is NULL".to_string());
+        }
+        let name = CStr::from_ptr(chr_id);
+        let strand = strand as u8 as char;
+        match (*merged).profiles.iter().find(|p| p.chr_id.as_c_str() == name && p.strand == strand) {
+            Some(p) => {
+                *out = p;
+                StoneStatus::Ok
+            }
+            None => fail(StoneStatus::ErrRange, format!("stone_merged_find: no {}({})", name.to_string_lossy(), strand)),
+        }
+    })
+}
+
+/// ChrID of a profile, valid as long as its StoneMerged; NULL for a NULL profile.
+///
+/// # Safety
+/// `profile` comes from stone_merged_profile or stone_merged_find.
+#[no_mangle]
+pub unsafe extern "C" fn stone_profile_chr_id(profile: *const StoneProfile) -> *const c_char {
+    if profile.is_null() {
+        return std::ptr::null();
+    }
+    (*profile).chr_id.as_ptr()
+}
+
+/// Strand of a profile, '+' or '-'; 0 for a NULL profile.
+///
+/// # Safety
+/// `profile` comes from stone_merged_profile or stone_merged_find.
+#[no_mangle]
+pub unsafe extern "C" fn stone_profile_strand(profile: *const StoneProfile) -> c_char {
+    if profile.is_null() {
+        return 0;
+    }
+    (*profile).strand as u8 as c_char
+}
+
+/// Number of positions of a profile, the length of every array below; 0 for a NULL profile.
+///
+/// # Safety
+/// `profile` comes from stone_merged_profile or stone_merged_find.
+#[no_mangle]
+pub unsafe extern "C" fn stone_profile_len(profile: *const StoneProfile) -> usize {
+    if profile.is_null() {
+        return 0;
+    }
+    (*profile).positions.len()
+}
+
+/// 1-based positions of a profile into `out` of `len` elements.
+///
+/// # Safety
+/// `profile` comes from stone_merged_profile or stone_merged_find, `out` points to `len` writable values.
+#[no_mangle]
+pub unsafe extern "C" fn stone_profile_positions(profile: *const StoneProfile, out: *mut u32, len: usize) -> StoneStatus {
+    guard("stone_profile_positions", || {
+        if profile.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_profile_positions: profile is NULL".to_string());
+        }
+        fill(&(*profile).positions, out, len)
+    })
+}
+
+/// A feature (a StoneFeature) of every position (count over rf_mutation_Depth, NaN where the position has no RF or
+/// pipe data) into `out` of `len` elements.
+///
+/// # Safety
+/// `profile` comes from stone_merged_profile or stone_merged_find, `out` points to `len` writable values.
+#[no_mangle]
+pub unsafe extern "C" fn stone_profile_feature(profile: *const StoneProfile, feature: u32, out: *mut f64, len: usize) -> StoneStatus {
+    guard("stone_profile_feature", || match feature_values(profile, feature, "stone_profile_feature") {
+        Ok(values) => fill(values, out, len),
+        Err(status) => status,
+    })
+}
+
+/// The score genome_model_output.py gives a feature (a StoneFeature): normalised between the `lower` and `upper`
+/// percentiles of the profile (5 and 95 there) and remapped; NaN positions score 0.
+///
+/// # Safety
+/// `profile` comes from stone_merged_profile or stone_merged_find, `out` points to `len` writable values.
+#[no_mangle]
+pub unsafe extern "C" fn stone_profile_score(profile: *const StoneProfile, feature: u32, lower: f64, upper: f64, out: *mut f64, len: usize) -> StoneStatus {
+    guard("stone_profile_score", || match feature_values(profile, feature, "stone_profile_score") {
+        Ok(values) => fill(&signal::remap(&signal::normalize_by_percentile(values, lower, upper)), out, len),
+        Err(status) => status,
+    })
+}
+
+/// normalize_by_percentile of genome_model_output.py over `len` values, `out` may be `values`.
+///
+/// # Safety
+/// `values` and `out` point to `len` values.
+#[no_mangle]
+pub unsafe extern "C" fn stone_normalize_by_percentile(values: *const f64, len: usize, lower: f64, upper: f64, out: *mut f64) -> StoneStatus {
+    guard("stone_normalize_by_percentile", || {
+        if values.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_normalize_by_percentile: values is NULL".to_string());
+        }
+        // computed before `out` is written, which may be `values`
+        let result = signal::normalize_by_percentile(std::slice::from_raw_parts(values, len), lower, upper);
+        fill(&result, out, len)
+    })
+}
+
+/// remap_values of genome_model_output.py over `len` values, `out` may be `values`.
+///
+/// # Safety
+/// `values` and `out` point to `len` values.
+#[no_mangle]
+pub unsafe extern "C" fn stone_remap_values(values: *const f64, len: usize, out: *mut f64) -> StoneStatus {
+    guard("stone_remap_values", || {
+        if values.is_null() {
+            return fail(StoneStatus::ErrNull, "stone_remap_values: values is NULL".to_string());
+        }
+        let result = signal::remap(std::slice::from_raw_parts(values, len));
+        fill(&result, out, len)
+    })
+}
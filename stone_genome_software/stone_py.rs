@@ -0,0 +1,142 @@
+// Python module `stone`: the readers and the model features of signal.rs for notebooks, so the numbers
+// are the ones the CLI tools and genome_model_output.py give. Built as a cdylib with maturin, see
+// readme.txt (16). Numeric columns come back as float64 NumPy arrays that own the Rust buffer (no
+// copy), text columns as lists of str.
+mod provenance;
+mod signal;
+mod zipped;
+
+use numpy::{PyArray1, PyArrayMethods, PyReadonlyArray1};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use signal::{Column, ModelParams, Table};
+use std::io;
+
+fn to_py_err(path: &str, e: io::Error) -> PyErr {
+    match e.kind() {
+        io::ErrorKind::InvalidData => PyValueError::new_err(e.to_string()),
+        _ => PyIOError::new_err(format!("{}: {}", path, e)),
+    }
+}
+
+// {column: array or list} in file order
+fn table_to_dict<'py>(py: Python<'py>, table: Table) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (name, column) in table.columns {
+        match column {
+            Column::Num(v) => dict.set_item(name, PyArray1::from_vec(py, v))?,
+            Column::Text(v) => dict.set_item(name, PyList::new(py, v)?)?,
+        }
+    }
+    Ok(dict)
+}
+
+fn read_table(path: &str, zipped: bool) -> PyResult<Table> {
+    let table = if zipped { signal::read_zipped(path) } else { signal::read_csv(path) };
+    table.map_err(|e| to_py_err(path, e))
+}
+
+/// Any '#'-commented csv of the workflow (merge, bgsg, mbreport, model input).
+#[pyfunction]
+fn read_csv<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    table_to_dict(py, read_table(path, false)?)
+}
+
+/// merge output (ChrID, rf_mutation_*, pipe_truncation_*).
+#[pyfunction]
+fn read_merged<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    table_to_dict(py, read_table(path, false)?)
+}
+
+/// bgsg output, NA read as NaN.
+#[pyfunction]
+fn read_bgsg<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    table_to_dict(py, read_table(path, false)?)
+}
+
+/// zip_rfcsv, zip_rftxt2 or zip_pipe output with the ChrID index resolved and a Strand column.
+#[pyfunction]
+fn read_zipped<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    table_to_dict(py, read_table(path, true)?)
+}
+
+/// rate_A, rate_T, rate_C, rate_G, rate_stop and rate_mut of a model input file.
+#[pyfunction]
+fn features<'py>(py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyDict>> {
+    let table = read_table(path, false)?;
+    let dict = PyDict::new(py);
+    for (name, v) in signal::features(&table).map_err(|e| to_py_err(path, e))? {
+        dict.set_item(name, PyArray1::from_vec(py, v))?;
+    }
+    Ok(dict)
+}
+
+#[pyfunction]
+#[pyo3(signature = (values, lower=5.0, upper=95.0))]
+fn normalize_by_percentile<'py>(py: Python<'py>, values: PyReadonlyArray1<'py, f64>, lower: f64, upper: f64) -> PyResult<Bound<'py, PyArray1<f64>>> {
+    let values = values.as_slice()?;
+    Ok(PyArray1::from_vec(py, signal::normalize_by_percentile(values, lower, upper)))
+}
+
+#[pyfunction]
+#[pyo3(signature = (values, lower=5.0, upper=95.0, outlier=-999.0))]
+fn percentile_normalize<'py>(py: Python<'py>, values: PyReadonlyArray1<'py, f64>, lower: f64, upper: f64, outlier: f64) -> PyResult<Bound<'py, PyArray1<f64>>> {
+    let values = values.as_slice()?;
+    Ok(PyArray1::from_vec(py, signal::percentile_normalize(values, lower, upper, outlier)))
+}
+
+#[pyfunction]
+fn remap_values<'py>(py: Python<'py>, values: PyReadonlyArray1<'py, f64>) -> PyResult<Bound<'py, PyArray1<f64>>> {
+    Ok(PyArray1::from_vec(py, signal::remap(values.as_slice()?)))
+}
+
+// (X, columns, rows)
+type Prepared<'py> = (Bound<'py, PyAny>, Vec<String>, Bound<'py, PyArray1<usize>>);
+
+/// Feature matrix X of genome_model_output.py for a model input file: (X, columns, rows), rows being
+/// the 0-based data lines kept. Defaults are those of the [model] section of stone.toml.
+#[pyfunction]
+#[pyo3(signature = (path, depth_threshold=10.0, rf_mutation_Count=0.0, pipe_truncation_count=0.0, rate_mut=0.25, rate_stop=1.0, acc_threshold=0.0, filter_acc=true))]
+#[allow(non_snake_case, clippy::too_many_arguments)]
+fn prepare<'py>(
+    py: Python<'py>,
+    path: &str,
+    depth_threshold: f64,
+    rf_mutation_Count: f64,
+    pipe_truncation_count: f64,
+    rate_mut: f64,
+    rate_stop: f64,
+    acc_threshold: f64,
+    filter_acc: bool,
+) -> PyResult<Prepared<'py>> {
+    let table = read_table(path, false)?;
+    let params = ModelParams { depth_threshold, rf_mutation_count: rf_mutation_Count, pipe_truncation_count, rate_mut, rate_stop, acc_threshold, filter_acc };
+    let prepared = signal::prepare(&table, &params).map_err(|e| to_py_err(path, e))?;
+    let shape = [prepared.rows.len(), prepared.columns.len()];
+    let matrix = PyArray1::from_vec(py, prepared.matrix).reshape(shape)?.into_any();
+    Ok((matrix, prepared.columns, PyArray1::from_vec(py, prepared.rows)))
+}
+
+/// The #stone provenance blocks of a file, oldest first, as lists of (key, value).
+#[pyfunction]
+#[pyo3(name = "provenance")]
+fn read_provenance(path: &str) -> PyResult<Vec<Vec<(String, String)>>> {
+    let lines = provenance::read(path).map_err(|e| to_py_err(path, e))?;
+    Ok(provenance::parse(&lines))
+}
+
+#[pymodule]
+fn stone(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(read_csv, m)?)?;
+    m.add_function(wrap_pyfunction!(read_merged, m)?)?;
+    m.add_function(wrap_pyfunction!(read_bgsg, m)?)?;
+    m.add_function(wrap_pyfunction!(read_zipped, m)?)?;
+    m.add_function(wrap_pyfunction!(features, m)?)?;
+    m.add_function(wrap_pyfunction!(normalize_by_percentile, m)?)?;
+    m.add_function(wrap_pyfunction!(percentile_normalize, m)?)?;
+    m.add_function(wrap_pyfunction!(remap_values, m)?)?;
+    m.add_function(wrap_pyfunction!(prepare, m)?)?;
+    m.add_function(wrap_pyfunction!(read_provenance, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,93 @@
+// R entry points for the transcript-level pipeline: the zip files merged and turned into the
+// model-input table by the same code as merge and model-input (merged.rs, signal.rs), so
+// merge_outputs_stone() in stone.R gives the columns shapeTM::merge_outputs_auto() gave without a
+// second implementation. Built as a cdylib (libstone_r.so) and called with .C(), which passes every
+// argument as a pointer and gets nothing back but the status it writes; see readme.txt (17).
+mod merged;
+mod provenance;
+mod signal;
+mod zipped;
+
+use merged::{merge_data, parse_file1, parse_file2, parse_file3};
+use provenance::Block;
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::os::raw::{c_char, c_int};
+use std::panic;
+
+pub const STATUS_OK: c_int = 0;
+pub const STATUS_IO: c_int = 1;
+pub const STATUS_INVALID: c_int = 2;
+pub const STATUS_PANIC: c_int = 3;
+
+// first element of an R character vector
+unsafe fn arg(v: *const *const c_char, name: &str) -> io::Result<String> {
+    if v.is_null() || (*v).is_null() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is missing", name)));
+    }
+    Ok(CStr::from_ptr(*v).to_string_lossy().into_owned())
+}
+
+fn merge_transcripts(rf_csv: &str, rf_txt: &str, pipe: &str, strand: &str, output: &str) -> io::Result<()> {
+    let strand = match strand {
+        "+" | "-" => strand.chars().next().unwrap(),
+        s => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("strand must be + or -, not {}", s))),
+    };
+    let rf_csv_data = parse_file1(rf_csv)?;
+    let rf_txt_data = parse_file2(rf_txt)?;
+    let pipe_data = parse_file3(pipe)?;
+    let merged = merge_data(rf_csv_data, rf_txt_data, pipe_data, false);
+
+    let mut out = BufWriter::new(File::create(output)?);
+    let mut block = Block::new("stone_r merge", "1.0");
+    block.input(rf_csv).input(rf_txt).input(pipe);
+    block.set("filter", format!("pipe_truncation_Strand={}", strand));
+    for line in provenance::upstream(&[rf_csv, rf_txt, pipe], '#').iter().chain(&block.lines('#')) {
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out, "{}", signal::MODEL_INPUT_HEADER)?;
+    for entry in merged.iter().filter(|e| e.strand == strand) {
+        let row = merged::row(entry);
+        let parts: Vec<&str> = row.split(',').collect();
+        // modified_string is left empty, shapeTM::add_freature_dot fills it from the known structure
+        writeln!(out, "{}", signal::model_row(&parts, ""))?;
+    }
+    out.flush()
+}
+
+/// .C("stone_r_merge", rf_csv, rf_txt, pipe, strand, output, status = integer(1))
+/// rf_csv, rf_txt and pipe are zip_rfcsv, zip_rftxt2 and zip_pipe outputs; the model-input table of
+/// every position on `strand` is written to `output`. status: 0 ok, 1 io error, 2 invalid input,
+/// 3 internal error, the message goes to stderr.
+///
+/// # Safety
+/// Every argument points to a character vector (or integer vector for status) of length >= 1, as .C passes them.
+#[no_mangle]
+pub unsafe extern "C" fn stone_r_merge(
+    rf_csv: *const *const c_char,
+    rf_txt: *const *const c_char,
+    pipe: *const *const c_char,
+    strand: *const *const c_char,
+    output: *const *const c_char,
+    status: *mut c_int,
+) {
+    let result = panic::catch_unwind(|| -> io::Result<()> {
+        let (rf_csv, rf_txt, pipe) = (arg(rf_csv, "rf_csv")?, arg(rf_txt, "rf_txt")?, arg(pipe, "pipe")?);
+        merge_transcripts(&rf_csv, &rf_txt, &pipe, &arg(strand, "strand")?, &arg(output, "output")?)
+    });
+    let code = match result {
+        Ok(Ok(())) => STATUS_OK,
+        Ok(Err(e)) => {
+            eprintln!("stone_r_merge: {}", e);
+            match e.kind() {
+                io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => STATUS_INVALID,
+                _ => STATUS_IO,
+            }
+        }
+        Err(_) => STATUS_PANIC,
+    };
+    if !status.is_null() {
+        *status = code;
+    }
+}
@@ -0,0 +1,787 @@
+// Golden-file and property tests of the command line tools, run as programs on the small inputs in
+// tests/fixtures (one 60 nt "chromosome" simulated from genome.dot, with a zero-coverage RF line and
+// minus strand countRT rows added by hand). Outputs are compared with tests/golden without their
+// #stone/@stone provenance lines, which hold dates and paths.
+//
+// The binaries are the ones cargo builds for the package holding this file (the [[bin]] targets of
+// ../Cargo.toml, one per tool source), or those in STONE_BIN_DIR when the tools are built as
+// separate projects. After an intended change of output, `UPDATE_GOLDEN=1 cargo test` rewrites
+// the expected files; review the diff before committing it. See readme.txt (21).
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+macro_rules! built_bins {
+    ($($name:literal),*) => {
+        fn built(name: &str) -> Option<&'static str> {
+            match name {
+                $($name => option_env!(concat!("CARGO_BIN_EXE_", $name)),)*
+                _ => None,
+            }
+        }
+    };
+}
+built_bins!(
+    "zip_pipe", "zip_rfcsv", "zip_rftxt", "zip_rftxt2", "merge", "bgsg", "mbreport", "model_input", "simulate", "evaluate", "delta", "fold",
+    "fold_windows", "plot", "stone"
+);
+
+const FIXTURES: [&str; 11] = [
+    "rf.csv", "rf.txt", "countRT.csv", "genes.bed", "genome.len", "genome.dot", "g.fa", "g.fa.fai", "genome.bed", "open.dot", "hairpin.fa",
+];
+
+fn bin(name: &str) -> PathBuf {
+    if let Some(dir) = std::env::var_os("STONE_BIN_DIR") {
+        return Path::new(&dir).join(name);
+    }
+    match built(name) {
+        Some(path) => PathBuf::from(path),
+        None => panic!("{} is not a binary of this package, set STONE_BIN_DIR to the directory of the tools", name),
+    }
+}
+
+fn tests_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+// fresh directory of one test with the fixtures copied in; the tools run inside it on relative
+// names, so the file names they write into their headers do not depend on where the tests run
+fn workdir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("stone-tests-{}", std::process::id())).join(test);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for name in FIXTURES {
+        fs::copy(tests_dir().join("fixtures").join(name), dir.join(name)).unwrap();
+    }
+    dir
+}
+
+// stderr of the run, which must succeed
+fn run_env(dir: &Path, name: &str, args: &[&str], env: &[(&str, &str)]) -> String {
+    let output = Command::new(bin(name))
+        .args(args)
+        .envs(env.iter().copied())
+        .current_dir(dir)
+        .output()
+        .unwrap_or_else(|e| panic!("{}: {}", bin(name).display(), e));
+    assert!(
+        output.status.success(),
+        "{} {} failed ({}):\n{}",
+        name,
+        args.join(" "),
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn run(dir: &Path, name: &str, args: &[&str]) -> String {
+    run_env(dir, name, args, &[])
+}
+
+// a run that may fail
+fn run_output(dir: &Path, name: &str, args: &[&str]) -> Output {
+    Command::new(bin(name)).args(args).current_dir(dir).output().unwrap_or_else(|e| panic!("{}: {}", bin(name).display(), e))
+}
+
+fn is_provenance(line: &str) -> bool {
+    line.starts_with("#stone\t") || line.starts_with("@stone\t")
+}
+
+#[derive(Clone, Copy, Default)]
+struct Canon {
+    // rows written in thread order, compared sorted below the first line
+    sorted: bool,
+    // the random modified_string column of bgsg and model_input
+    drop_last: bool,
+}
+
+fn data_lines(path: &Path, canon: Canon) -> Vec<String> {
+    let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    let mut lines: Vec<String> = text
+        .lines()
+        .filter(|l| !is_provenance(l))
+        .map(|l| match l.rsplit_once(',') {
+            Some((head, _)) if canon.drop_last => head.to_string(),
+            _ => l.to_string(),
+        })
+        .collect();
+    if canon.sorted && !lines.is_empty() {
+        lines[1..].sort();
+    }
+    lines
+}
+
+fn golden(actual: &Path, expected: &str, canon: Canon) {
+    let lines = data_lines(actual, canon);
+    let path = tests_dir().join("golden").join(expected);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, lines.join("\n") + "\n").unwrap();
+        return;
+    }
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {} (UPDATE_GOLDEN=1 writes it)", path.display(), e));
+    let want: Vec<&str> = text.lines().collect();
+    if let Some(i) = (0..want.len().max(lines.len())).find(|&i| want.get(i).copied() != lines.get(i).map(|s| s.as_str())) {
+        panic!(
+            "{} differs from {} at line {}:\n  expected {:?}\n  got      {:?}",
+            actual.display(),
+            path.display(),
+            i + 1,
+            want.get(i),
+            lines.get(i)
+        );
+    }
+}
+
+// the three zip files of the fixtures, as stone run names them
+fn zip_all(dir: &Path) {
+    run(dir, "zip_rfcsv", &["-i", "rf.csv", "-o", "rf_csv.zip", "-t", "2"]);
+    run(dir, "zip_rftxt", &["rf.txt", "rf_txt.zip1", "1"]);
+    run(dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", "rf_txt.zip", "-t", "2"]);
+    run(dir, "zip_pipe", &["countRT.csv", "pipe.zip"]);
+}
+
+// merge reads the zip_rftxt2 output with --pipe and the zip_pipe output with --txt
+fn merge(dir: &Path, csv: &str, rf_txt: &str, pipe: &str, output: &str, env: &[(&str, &str)]) {
+    run_env(dir, "merge", &["-c", csv, "-p", rf_txt, "-t", pipe, "-o", output], env);
+}
+
+#[test]
+fn zip_pipe_golden() {
+    let dir = workdir("zip_pipe_golden");
+    run(&dir, "zip_pipe", &["countRT.csv", "pipe.zip"]);
+    golden(&dir.join("pipe.zip"), "zip_pipe.txt", Canon::default());
+}
+
+#[test]
+fn zip_rfcsv_golden() {
+    let dir = workdir("zip_rfcsv_golden");
+    run(&dir, "zip_rfcsv", &["-i", "rf.csv", "-o", "rf_csv.zip"]);
+    golden(&dir.join("rf_csv.zip"), "zip_rfcsv.txt", Canon::default());
+}
+
+// lines containing ",0,0" (no mutation, no coverage) are left out, the positions after them keep
+// their line number
+#[test]
+fn zip_rfcsv_skips_zero_lines() {
+    let dir = workdir("zip_rfcsv_skips_zero_lines");
+    run(&dir, "zip_rfcsv", &["-i", "rf.csv", "-o", "rf_csv.zip"]);
+    let rows = data_lines(&dir.join("rf_csv.zip"), Canon::default());
+    assert!(fs::read_to_string(dir.join("rf.csv")).unwrap().lines().nth(20) == Some("C,0,0"));
+    assert!(!rows.iter().any(|r| r.starts_with("1,20,")));
+    assert!(rows.iter().any(|r| r.starts_with("1,21,")));
+    assert_eq!(rows.iter().filter(|r| !r.starts_with('@')).count(), 59);
+}
+
+#[test]
+fn zip_rftxt_golden() {
+    let dir = workdir("zip_rftxt_golden");
+    run(&dir, "zip_rftxt", &["rf.txt", "rf_txt.zip1", "1"]);
+    golden(&dir.join("rf_txt.zip1"), "zip_rftxt.txt", Canon::default());
+    run(&dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", "rf_txt.zip"]);
+    golden(&dir.join("rf_txt.zip"), "zip_rftxt2.txt", Canon::default());
+}
+
+// a typo in stone.toml is reported as an error, not a panic
+#[test]
+fn zip_tools_report_bad_config() {
+    let dir = workdir("zip_tools_report_bad_config");
+    fs::write(dir.join("bad.toml"), "[zip]\nthreads = 2\n").unwrap();
+    for (name, input) in [("zip_rfcsv", "rf.csv"), ("zip_rftxt2", "rf.txt")] {
+        let output = run_output(&dir, name, &["-i", input, "-o", "out.zip", "--config", "bad.toml"]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "{}: {}", name, stderr);
+        assert!(stderr.starts_with("bad.toml: unknown key zip.threads") && !stderr.contains("panicked"), "{}: {}", name, stderr);
+    }
+}
+
+#[test]
+fn merge_golden() {
+    let dir = workdir("merge_golden");
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    golden(&dir.join("merged.csv"), "merge.csv", Canon::default());
+}
+
+// RF zip files made with --strand - meet the minus strand countRT rows at 41-50: the RF bases are
+// the plus strand reference, the pipe bases its complement, and neither is flagged against g.fa
+#[test]
+fn merge_minus_strand_golden() {
+    let dir = workdir("merge_minus_strand_golden");
+    run(&dir, "zip_rfcsv", &["-i", "rf.csv", "-o", "rf_csv.zip", "-s", "-"]);
+    run(&dir, "zip_rftxt", &["rf.txt", "rf_txt.zip1", "1"]);
+    run(&dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", "rf_txt.zip", "-s", "-"]);
+    run(&dir, "zip_pipe", &["countRT.csv", "pipe.zip"]);
+    run(&dir, "merge", &["-c", "rf_csv.zip", "-p", "rf_txt.zip", "-t", "pipe.zip", "-o", "merged.csv", "-f", "g.fa"]);
+    golden(&dir.join("merged.csv"), "merge_minus.csv", Canon::default());
+    let rows = data_lines(&dir.join("merged.csv"), Canon::default());
+    // Strand, Position, Base1, Base3 and BaseFlag of the minus strand rows with both signals
+    let both: Vec<Vec<&str>> = rows[1..]
+        .iter()
+        .map(|r| r.split(',').collect::<Vec<&str>>())
+        .filter(|f| f[1] == "-" && f[3] != "N" && f[20] != "N")
+        .collect();
+    assert_eq!(both.iter().map(|f| f[2]).collect::<Vec<_>>(), ["41", "42", "43", "44", "45", "46", "47", "48", "49", "50"]);
+    assert!(both.iter().all(|f| f[24] == "."), "{:?}", both);
+}
+
+#[test]
+fn bgsg_mbreport_golden() {
+    let dir = workdir("bgsg_mbreport_golden");
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    run(&dir, "bgsg", &["-m", "merged.csv", "-b", "genes.bed", "-o", "bgsg.csv", "-s", "hu", "-t", "2"]);
+    golden(&dir.join("bgsg.csv"), "bgsg.csv", Canon { sorted: true, drop_last: true });
+    run(&dir, "mbreport", &["-i", "bgsg.csv", "-o", "mbreport.csv", "-d", "5"]);
+    golden(&dir.join("mbreport.csv"), "mbreport.csv", Canon { sorted: true, drop_last: false });
+}
+
+// bed intervals are 1-based and inclusive: a .len of end - start + 1 keeps every position, one
+// shorter cuts the 3' end and reports the dropped row
+#[test]
+fn bgsg_len_convention() {
+    let dir = workdir("bgsg_len_convention");
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    let bgsg_rows = |len: &str, output: &str| {
+        fs::write(dir.join("t.len"), len).unwrap();
+        let stderr = run(&dir, "bgsg", &["-m", "merged.csv", "-b", "genes.bed", "-o", output, "-s", "hu", "--fill", "-l", "t.len"]);
+        let rows: Vec<String> = data_lines(&dir.join(output), Canon::default()).into_iter().skip(1).collect();
+        (rows, stderr)
+    };
+    let (full, stderr) = bgsg_rows("T1 20\nT2 10\n", "full.csv");
+    assert_eq!(full.len(), 30);
+    assert!(!stderr.contains("dropped"), "{}", stderr);
+    let (short, stderr) = bgsg_rows("T1 19\nT2 10\n", "short.csv");
+    assert_eq!(short.len(), 29);
+    assert!(!short.iter().any(|r| r.starts_with("NC_000001.11,gene1,T1,24,")), "{:?}", short);
+    assert_eq!(stderr.matches("row dropped").count(), 1, "{}", stderr);
+}
+
+#[test]
+fn model_input_golden() {
+    let dir = workdir("model_input_golden");
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    run(&dir, "model_input", &["-l", "genome.len", "-m", "merged.csv", "-o", "mi"]);
+    golden(&dir.join("mi/merged/NC_000001.11_plus.csv"), "model_input.csv", Canon { sorted: false, drop_last: true });
+}
+
+// the fixture chromosome as one transcript (genome.bed), so the downstream tools see bgsg output
+// whose transcript positions are those of genome.dot
+fn whole_bgsg(dir: &Path, output: &str) {
+    zip_all(dir);
+    merge(dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    run(dir, "bgsg", &["-m", "merged.csv", "-b", "genome.bed", "-o", output, "-s", "hu"]);
+}
+
+// the counts are simulated from genome.dot, so both separate its unpaired bases from the paired ones
+#[test]
+fn evaluate_golden() {
+    let dir = workdir("evaluate_golden");
+    whole_bgsg(&dir, "bgsg.csv");
+    let columns = "rf_mutation_Count,pipe_truncation_count";
+    run(&dir, "evaluate", &["-s", "bgsg.csv", "-r", "genome.dot", "-o", "evaluate.csv", "--roc", "roc.csv", "--idcol", "transcriptid", "-c", columns]);
+    golden(&dir.join("evaluate.csv"), "evaluate.csv", Canon::default());
+    golden(&dir.join("roc.csv"), "evaluate_roc.csv", Canon::default());
+    let rows = data_lines(&dir.join("evaluate.csv"), Canon::default());
+    for row in &rows[1..] {
+        let fields: Vec<&str> = row.split(',').collect();
+        assert_eq!(fields[3], "60", "{}", row);
+        assert!(fields[5].parse::<f64>().unwrap() > 0.75, "{}", row);
+    }
+}
+
+// the second condition is simulated from open.dot, genome.dot with the first helix (1-24) opened:
+// every site called lies in it and loses mutations in the first condition
+#[test]
+fn delta_golden() {
+    let dir = workdir("delta_golden");
+    whole_bgsg(&dir, "paired.csv");
+    run(&dir, "simulate", &["-s", "open.dot", "-o", "open", "-d", "30", "--seed", "7"]);
+    run(&dir, "zip_rfcsv", &["-i", "open.rf.csv", "-o", "open_csv.zip"]);
+    run(&dir, "zip_rftxt", &["open.rf.txt", "open_txt.zip1", "1"]);
+    run(&dir, "zip_rftxt2", &["-i", "open_txt.zip1", "-o", "open_txt.zip"]);
+    run(&dir, "zip_pipe", &["open_countRT.csv", "open_pipe.zip"]);
+    merge(&dir, "open_csv.zip", "open_txt.zip", "open_pipe.zip", "open_merged.csv", &[]);
+    run(&dir, "bgsg", &["-m", "open_merged.csv", "-b", "genome.bed", "-o", "open.csv", "-s", "hu"]);
+    run(&dir, "delta", &["--data1", "paired.csv", "--data2", "open.csv", "-c", "mut", "-o", "delta.bed", "--table", "delta.txt"]);
+    golden(&dir.join("delta.bed"), "delta.bed", Canon::default());
+    golden(&dir.join("delta.txt"), "delta.txt", Canon::default());
+    let sites = data_lines(&dir.join("delta.bed"), Canon::default());
+    assert!(!sites.is_empty());
+    for site in &sites {
+        let fields: Vec<&str> = site.split('\t').collect();
+        assert!(fields[2].parse::<usize>().unwrap() <= 24, "{}", site);
+        assert!(fields[4].parse::<f64>().unwrap() < 0.0, "{}", site);
+    }
+}
+
+#[test]
+fn fold_hairpin_golden() {
+    let dir = workdir("fold_hairpin_golden");
+    run(&dir, "fold", &["-f", "hairpin.fa", "-o", "hp", "--partition"]);
+    let dot = data_lines(&dir.join("hp.dot"), Canon::default());
+    assert_eq!(dot[2], "((((....)))) (-4.40)");
+    golden(&dir.join("hp.dot"), "fold_hairpin.dot", Canon::default());
+    golden(&dir.join("hp.ct"), "fold_hairpin.ct", Canon::default());
+    golden(&dir.join("hp_pp.csv"), "fold_hairpin_pp.csv", Canon::default());
+}
+
+// --params reads RNAfold 2.0 files: a mismatch_hairpin table of -1 everywhere lowers the GAAA
+// hairpin by 1 kcal/mol, a stack table without its NS row and column is refused
+#[test]
+fn fold_reads_rnafold_params() {
+    let dir = workdir("fold_reads_rnafold_params");
+    let built_in = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("params/turner2004.par")).unwrap();
+    let blocks = "  -100  -100  -100  -100  -100\n".repeat(5 * 7);
+    fs::write(dir.join("mismatch.par"), built_in.replace("# END", &format!("# mismatch_hairpin\n{}\n# END", blocks))).unwrap();
+    run(&dir, "fold", &["-f", "hairpin.fa", "-o", "hp", "-p", "mismatch.par"]);
+    assert_eq!(data_lines(&dir.join("hp.dot"), Canon::default())[2], "((((....)))) (-5.40)");
+    let six: Vec<&str> = built_in.lines().filter(|l| !l.contains("/* NS */")).collect();
+    fs::write(dir.join("six.par"), six.join("\n")).unwrap();
+    let output = run_output(&dir, "fold", &["-f", "hairpin.fa", "-o", "hp", "-p", "six.par"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success() && stderr.contains("six.par: section stack has 42 values, RNAfold 2.0 files have 49"), "{}", stderr);
+}
+
+// bgsg truncation rates of the whole chromosome folded in 40 nt windows, the sequence from g.fa
+#[test]
+fn fold_windows_golden() {
+    let dir = workdir("fold_windows_golden");
+    whole_bgsg(&dir, "bgsg.csv");
+    run(&dir, "fold_windows", &["-i", "bgsg.csv", "-c", "stop", "-f", "g.fa", "-w", "40", "-s", "10", "--maxspan", "40", "-o", "fw", "-t", "2"]);
+    golden(&dir.join("fw.dot"), "fold_windows.dot", Canon::default());
+    golden(&dir.join("fw_pp.csv"), "fold_windows_pp.csv", Canon::default());
+}
+
+// bgsg of genes.bed without its transcript_position column, so rows are keyed by genomic position,
+// folded with transcript sequences: T1 (5-24) and T2 (41-50, minus strand) line up 5'->3' with the
+// sequences and get the same reactivities as the transcript position rows
+#[test]
+fn fold_windows_genomic_positions() {
+    let dir = workdir("fold_windows_genomic_positions");
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    run(&dir, "bgsg", &["-m", "merged.csv", "-b", "genes.bed", "-o", "bgsg.csv", "-s", "hu"]);
+    let chromosome = fs::read_to_string(dir.join("g.fa")).unwrap().lines().nth(1).unwrap().replace('T', "U");
+    let t2: String = chromosome[40..50]
+        .chars()
+        .rev()
+        .map(|b| match b {
+            'A' => 'U',
+            'U' => 'A',
+            'C' => 'G',
+            _ => 'C',
+        })
+        .collect();
+    fs::write(dir.join("t.fa"), format!(">T1\n{}\n>T2\n{}\n", &chromosome[4..24], t2)).unwrap();
+    let lines = data_lines(&dir.join("bgsg.csv"), Canon::default());
+    let drop = lines[0].split(',').position(|c| c == "transcript_position").unwrap();
+    let genomic: Vec<String> = lines
+        .iter()
+        .map(|l| l.split(',').enumerate().filter(|(i, _)| *i != drop).map(|(_, f)| f).collect::<Vec<_>>().join(","))
+        .collect();
+    fs::write(dir.join("genomic.csv"), genomic.join("\n") + "\n").unwrap();
+    for (input, output) in [("genomic.csv", "genomic"), ("bgsg.csv", "transcript")] {
+        run(&dir, "fold_windows", &["-i", input, "-c", "stop", "-f", "t.fa", "-w", "20", "-s", "10", "--maxspan", "20", "-o", output, "-t", "2"]);
+    }
+    golden(&dir.join("genomic.dot"), "fold_windows_genomic.dot", Canon::default());
+    golden(&dir.join("genomic_pp.csv"), "fold_windows_genomic_pp.csv", Canon::default());
+    assert_eq!(data_lines(&dir.join("genomic.dot"), Canon::default()), data_lines(&dir.join("transcript.dot"), Canon::default()));
+    // Transcript, Base, Reactivity, Pair and PairProb; Position and ChrPos follow the input
+    let columns = |name: &str| -> Vec<String> {
+        data_lines(&dir.join(name), Canon::default())
+            .iter()
+            .map(|l| {
+                let f: Vec<&str> = l.split(',').collect();
+                [f[0], f[4], f[5], f[6], f[7]].join(",")
+            })
+            .collect()
+    };
+    let genomic_rows = columns("genomic_pp.csv");
+    assert_eq!(genomic_rows, columns("transcript_pp.csv"));
+    assert!(genomic_rows[1..].iter().filter(|r| !r.contains(",NA,")).count() >= 25, "{:?}", genomic_rows);
+}
+
+// the fold_windows reactivities under the consensus arcs, coloured by the fold --partition pairs
+#[test]
+fn plot_golden() {
+    let dir = workdir("plot_golden");
+    whole_bgsg(&dir, "bgsg.csv");
+    run(&dir, "fold_windows", &["-i", "bgsg.csv", "-c", "stop", "-f", "g.fa", "-w", "40", "-s", "10", "--maxspan", "40", "-o", "fw", "-t", "2"]);
+    run(&dir, "fold", &["-f", "g.fa", "-o", "pf", "--partition"]);
+    run(&dir, "plot", &["-r", "fw_pp.csv", "-c", "Reactivity", "-s", "fw.dot", "-d", "pf", "-o", "plot"]);
+    golden(&dir.join("plot_NC_000001.11.svg"), "plot.svg", Canon::default());
+    let pdf = fs::read(dir.join("plot_NC_000001.11.pdf")).unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.4\n"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+    assert!(String::from_utf8_lossy(&pdf).contains("%stone\ttool\tplot"));
+}
+
+// sample, status, stages_run, stages_skipped and failure of the summary.tsv rows
+fn summary(dir: &Path) -> Vec<Vec<String>> {
+    data_lines(&dir.join("out/summary.tsv"), Canon::default())[1..]
+        .iter()
+        .map(|l| l.split('\t').take(5).map(String::from).collect())
+        .collect()
+}
+
+// a rerun skips every stage whose manifest still matches, one with other parameters is refused
+#[test]
+fn stone_run_skips_and_refuses() {
+    let dir = workdir("stone_run_skips_and_refuses");
+    fs::write(dir.join("samples.tsv"), "sample\trf_csv\trf_txt\tpipe\ns1\trf.csv\trf.txt\tcountRT.csv\n").unwrap();
+    let bin_dir = bin("stone").parent().unwrap().display().to_string();
+    let args = ["run", "--samples", "samples.tsv", "-o", "out", "-b", "genes.bed", "-d", "5", "-t", "2", "--sample-threads", "1", "--bin-dir", &bin_dir];
+    run(&dir, "stone", &args);
+    assert_eq!(summary(&dir), [["s1", "ok", "7", "0", "-"]]);
+    golden(&dir.join("out/s1/merged.csv"), "merge.csv", Canon::default());
+    golden(&dir.join("out/s1/bgsg.csv"), "bgsg.csv", Canon { sorted: true, drop_last: true });
+    golden(&dir.join("out/s1/mbreport.csv"), "mbreport.csv", Canon { sorted: true, drop_last: false });
+
+    run(&dir, "stone", &args);
+    assert_eq!(summary(&dir), [["s1", "ok", "0", "7", "-"]]);
+
+    let output = run_output(&dir, "stone", &[&args[..], &["-s", "mm"]].concat());
+    assert!(!output.status.success());
+    let rows = summary(&dir);
+    assert_eq!(rows[0][..4], ["s1", "failed", "0", "5"]);
+    assert!(rows[0][4].starts_with("bgsg: out/s1/bgsg.csv was made with species=hu"), "{:?}", rows);
+    assert!(rows[0][4].ends_with("use --force or another --outdir"), "{:?}", rows);
+}
+
+// one GET over a fresh connection; (status line, body)
+fn http_get(addr: &str, path: &str) -> (String, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (head.lines().next().unwrap().to_string(), body.to_string())
+}
+
+// stone info lists the stages behind a stone run output and notices a changed input, stone index
+// writes the .stidx files and stone serve answers from them
+#[test]
+fn stone_info_index_serve() {
+    let dir = workdir("stone_info_index_serve");
+    fs::write(dir.join("samples.tsv"), "sample\trf_csv\trf_txt\tpipe\ns1\trf.csv\trf.txt\tcountRT.csv\n").unwrap();
+    let bin_dir = bin("stone").parent().unwrap().display().to_string();
+    run(&dir, "stone", &["run", "--samples", "samples.tsv", "-o", "out", "-b", "genes.bed", "-d", "5", "--sample-threads", "1", "--bin-dir", &bin_dir]);
+
+    let info = run_output(&dir, "stone", &["info", "--check", "out/s1/mbreport.csv"]);
+    assert!(info.status.success());
+    let text = String::from_utf8_lossy(&info.stdout).into_owned();
+    assert!(text.starts_with("out/s1/mbreport.csv: 7 stages"), "{}", text);
+    let stages: Vec<&str> = text.lines().filter(|l| l.starts_with('[')).collect();
+    let tools = ["zip_rfcsv", "zip_rftxt", "zip_rftxt2", "zip_pipe", "merge", "bgsg", "mbreport"];
+    for (i, (stage, tool)) in stages.iter().zip(tools).enumerate() {
+        assert!(stage.starts_with(&format!("[{}] {} ", i + 1, tool)), "{}", stage);
+    }
+    let mut countrt = fs::OpenOptions::new().append(true).open(dir.join("countRT.csv")).unwrap();
+    writeln!(countrt).unwrap();
+    let info = run_output(&dir, "stone", &["info", "--check", "out/s1/mbreport.csv"]);
+    assert!(!info.status.success());
+    let text = String::from_utf8_lossy(&info.stdout).into_owned();
+    let changed: Vec<&str> = text.lines().filter(|l| l.ends_with("\tchanged")).collect();
+    assert!(changed.len() == 1 && changed[0].contains("input      countRT.csv\t"), "{}", text);
+
+    run(&dir, "stone", &["index", "out/s1/merged.csv", "out/s1/bgsg.csv"]);
+    assert!(dir.join("out/s1/merged.csv.stidx").is_file() && dir.join("out/s1/bgsg.csv.stidx").is_file());
+
+    let mut server = Command::new(bin("stone"))
+        .args(["serve", "-p", "0", "-t", "2", "out/s1/merged.csv", "out/s1/bgsg.csv"])
+        .current_dir(&dir)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(server.stdout.take().unwrap()).lines();
+    let addr = lines
+        .by_ref()
+        .map(|l| l.unwrap())
+        .find_map(|l| l.strip_prefix("listening on http://").map(String::from))
+        .expect("stone serve did not start");
+    let (status, transcript) = http_get(&addr, "/transcript/T2?format=csv");
+    let (region_status, region) = http_get(&addr, "/region/NC_000001.11:41-43:-");
+    let (missing, _) = http_get(&addr, "/transcript/T9");
+    let _ = server.kill();
+    let _ = server.wait();
+
+    assert!(status.ends_with(" 200 OK"), "{}", status);
+    fs::write(dir.join("t2.csv"), &transcript).unwrap();
+    golden(&dir.join("t2.csv"), "serve_transcript.csv", Canon { sorted: false, drop_last: true });
+    assert!(region_status.ends_with(" 200 OK"), "{}", region_status);
+    assert_eq!(region.matches("\"Position\":").count(), 3, "{}", region);
+    assert!(region.contains("\"Strand\":\"-\",\"Position\":41,"), "{}", region);
+    assert!(missing.contains(" 404 "), "{}", missing);
+}
+
+#[test]
+fn simulate_golden() {
+    let dir = workdir("simulate_golden");
+    run(&dir, "simulate", &["-s", "genome.dot", "-o", "sim", "-d", "30", "--seed", "7"]);
+    for suffix in [".rf.csv", ".rf.txt", "_countRT.csv", ".labels.csv"] {
+        golden(&dir.join(format!("sim{}", suffix)), &format!("simulate{}", suffix), Canon::default());
+    }
+}
+
+// simulated transcripts for the property tests: 40 copies of the fixture chromosome
+fn simulated(dir: &Path, seed: u64) {
+    let seed = seed.to_string();
+    run(dir, "simulate", &["-s", "genome.dot", "-o", "sim", "-c", "40", "--seed", &seed, "-n", "0.8"]);
+}
+
+// the rf-count table of sim.rf.txt: transcript -> 14 rows of counts
+fn rf_table(path: &Path) -> Vec<(String, Vec<Vec<String>>)> {
+    let mut table: Vec<(String, Vec<Vec<String>>)> = Vec::new();
+    for line in fs::read_to_string(path).unwrap().lines() {
+        match line.split_once('\t') {
+            Some((_, values)) => table.last_mut().unwrap().1.push(values.split(',').map(String::from).collect()),
+            None => table.push((line.to_string(), Vec::new())),
+        }
+    }
+    table
+}
+
+#[test]
+fn zip_rfcsv_round_trip() {
+    for seed in 1..=3 {
+        let dir = workdir(&format!("zip_rfcsv_round_trip_{}", seed));
+        simulated(&dir, seed);
+        run(&dir, "zip_rfcsv", &["-i", "sim.rf.csv", "-o", "rf_csv.zip", "-t", "3"]);
+        let mut expected = BTreeSet::new();
+        let mut transcript = 0;
+        let mut index = 0;
+        for line in fs::read_to_string(dir.join("sim.rf.csv")).unwrap().lines() {
+            if !line.contains(',') {
+                transcript += 1;
+                index = 0;
+                continue;
+            }
+            index += 1;
+            if !line.contains(",0,0") {
+                expected.insert(format!("{},{},{}", transcript, index, line));
+            }
+        }
+        let rows: BTreeSet<String> = data_lines(&dir.join("rf_csv.zip"), Canon::default()).into_iter().filter(|l| !l.starts_with('@')).collect();
+        assert_eq!(rows, expected, "seed {}", seed);
+    }
+}
+
+// zip_rftxt run-length encodes the rows, zip_rftxt2 expands them back to one line per position with
+// any count
+#[test]
+fn zip_rftxt_round_trip() {
+    for seed in 1..=3 {
+        let dir = workdir(&format!("zip_rftxt_round_trip_{}", seed));
+        simulated(&dir, seed);
+        run(&dir, "zip_rftxt", &["sim.rf.txt", "rf_txt.zip1", "1"]);
+        run(&dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", "rf_txt.zip", "-t", "3"]);
+        let mut expected = BTreeSet::new();
+        for (transcript, (_, rows)) in rf_table(&dir.join("sim.rf.txt")).into_iter().enumerate() {
+            for i in 0..rows[0].len() {
+                let values: Vec<&str> = rows.iter().map(|r| r[i].as_str()).collect();
+                if values.iter().any(|v| *v != "0") {
+                    expected.insert(format!("{},{},{}", transcript + 1, i + 1, values.join(",")));
+                }
+            }
+        }
+        let rows: BTreeSet<String> = data_lines(&dir.join("rf_txt.zip"), Canon::default()).into_iter().filter(|l| !l.starts_with('@')).collect();
+        assert_eq!(rows, expected, "seed {}", seed);
+    }
+}
+
+#[test]
+fn zip_rfcsv_rftxt2_thread_count() {
+    let dir = workdir("zip_rfcsv_rftxt2_thread_count");
+    simulated(&dir, 4);
+    run(&dir, "zip_rftxt", &["sim.rf.txt", "rf_txt.zip1", "1"]);
+    for threads in ["1", "3", "8"] {
+        run(&dir, "zip_rfcsv", &["-i", "sim.rf.csv", "-o", &format!("rf_csv.{}", threads), "-t", threads]);
+        run(&dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", &format!("rf_txt.{}", threads), "-t", threads]);
+    }
+    for name in ["rf_csv", "rf_txt"] {
+        let one = data_lines(&dir.join(format!("{}.1", name)), Canon::default());
+        for threads in ["3", "8"] {
+            assert_eq!(one, data_lines(&dir.join(format!("{}.{}", name, threads)), Canon::default()), "{} with {} threads", name, threads);
+        }
+    }
+}
+
+#[test]
+fn zip_rftxt_thread_count() {
+    let dir = workdir("zip_rftxt_thread_count");
+    simulated(&dir, 5);
+    run(&dir, "zip_rftxt", &["sim.rf.txt", "rf_txt.1", "1"]);
+    let one = data_lines(&dir.join("rf_txt.1"), Canon::default());
+    for threads in ["2", "3", "7", "16"] {
+        run(&dir, "zip_rftxt", &["sim.rf.txt", &format!("rf_txt.{}", threads), threads]);
+        assert_eq!(one, data_lines(&dir.join(format!("rf_txt.{}", threads)), Canon::default()), "{} threads", threads);
+    }
+}
+
+// zip files of the earlier format (tests/fixtures/v1, the zip outputs before zipped.rs) merge to the
+// same file as the current ones
+#[test]
+fn merge_reads_v1_zip_files() {
+    let dir = workdir("merge_reads_v1_zip_files");
+    for name in ["rf_csv.zip", "rf_txt.zip", "pipe.zip"] {
+        fs::copy(tests_dir().join("fixtures/v1").join(name), dir.join(format!("v1.{}", name))).unwrap();
+    }
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    merge(&dir, "v1.rf_csv.zip", "v1.rf_txt.zip", "v1.pipe.zip", "v1.csv", &[]);
+    assert_eq!(data_lines(&dir.join("merged.csv"), Canon::default()), data_lines(&dir.join("v1.csv"), Canon::default()));
+}
+
+// every zip file says its kind, merge refuses one given in the place of another
+#[test]
+fn merge_rejects_swapped_zip_files() {
+    let dir = workdir("merge_rejects_swapped_zip_files");
+    zip_all(&dir);
+    let output = Command::new(bin("merge"))
+        .args(["-c", "rf_csv.zip", "-p", "pipe.zip", "-t", "rf_txt.zip", "-o", "merged.csv"])
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("pipe.zip: a pipe zip file where rf_txt was expected"), "{}", stderr);
+}
+
+// transcript -> (base code, count, coverage) of every position
+type RcTranscripts = Vec<(String, Vec<(u8, u32, u32)>)>;
+
+// a malformed row fails the merge with its file and line number instead of dropping the position
+#[test]
+fn merge_reports_bad_rows() {
+    let dir = workdir("merge_reports_bad_rows");
+    zip_all(&dir);
+    let text = fs::read_to_string(dir.join("pipe.zip")).unwrap();
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let first_row = lines.iter().position(|l| !l.starts_with('@')).unwrap();
+    for (name, (offset, bad)) in [("depth.zip", (4, "1,5,C,0,x")), ("short.zip", (9, "1,10,A,2"))] {
+        let saved = std::mem::replace(&mut lines[first_row + offset], bad.to_string());
+        fs::write(dir.join(name), lines.join("\n") + "\n").unwrap();
+        lines[first_row + offset] = saved;
+        let output = run_output(&dir, "merge", &["-c", "rf_csv.zip", "-p", "rf_txt.zip", "-t", name, "-o", "merged.csv"]);
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let want = format!("{}: line {}: ", name, first_row + offset + 1);
+        assert!(stderr.contains(&want) && stderr.contains(bad), "{}", stderr);
+    }
+}
+
+// RNA Framework RC file of an rf-count csv (see rc.rs) and, when `rci` is given, its index with the
+// transcripts listed last to first, so reading in index order seeks around the file
+fn write_rc(csv: &Path, rc: &Path, rci: Option<&Path>) {
+    let text = fs::read_to_string(csv).unwrap();
+    let mut transcripts: RcTranscripts = Vec::new();
+    for line in text.lines() {
+        let fields: Vec<&str> = line.split(',').collect();
+        match fields[..] {
+            [base, count, coverage] => transcripts.last_mut().unwrap().1.push((
+                b"ACGTN".iter().position(|b| *b == base.as_bytes()[0].to_ascii_uppercase()).unwrap_or(4) as u8,
+                count.parse().unwrap(),
+                coverage.parse().unwrap(),
+            )),
+            _ => transcripts.push((line.to_string(), Vec::new())),
+        }
+    }
+    let (mut data, mut index) = (Vec::new(), Vec::new());
+    for (name, rows) in &transcripts {
+        let mut entry = (name.len() as u32 + 1).to_le_bytes().to_vec();
+        entry.extend(name.as_bytes());
+        entry.push(0);
+        index.push((entry.clone(), data.len() as u64));
+        data.extend(entry);
+        data.extend((rows.len() as u32).to_le_bytes());
+        data.extend(rows.chunks(2).map(|pair| pair[0].0 << 4 | pair.get(1).map(|r| r.0).unwrap_or(0)));
+        data.extend(rows.iter().flat_map(|r| r.1.to_le_bytes()));
+        data.extend(rows.iter().flat_map(|r| r.2.to_le_bytes()));
+    }
+    data.extend(1000u64.to_le_bytes());
+    data.extend(b"[eofrc]");
+    fs::write(rc, data).unwrap();
+    if let Some(rci) = rci {
+        let bytes: Vec<u8> = index.into_iter().rev().flat_map(|(entry, offset)| entry.into_iter().chain(offset.to_le_bytes())).collect();
+        fs::write(rci, bytes).unwrap();
+    }
+}
+
+// merge takes the RC file in place of the zip_rfcsv output, read through its index or scanned
+#[test]
+fn merge_reads_rc() {
+    let dir = workdir("merge_reads_rc");
+    zip_all(&dir);
+    write_rc(&dir.join("rf.csv"), &dir.join("rf.rc"), Some(&dir.join("rf.rc.rci")));
+    write_rc(&dir.join("rf.csv"), &dir.join("scanned.rc"), None);
+    merge(&dir, "rf.rc", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    golden(&dir.join("merged.csv"), "merge.csv", Canon::default());
+    merge(&dir, "scanned.rc", "rf_txt.zip", "pipe.zip", "scanned.csv", &[]);
+    golden(&dir.join("scanned.csv"), "merge.csv", Canon::default());
+}
+
+#[test]
+fn merge_rc_many_transcripts() {
+    let dir = workdir("merge_rc_many_transcripts");
+    simulated(&dir, 8);
+    run(&dir, "zip_rfcsv", &["-i", "sim.rf.csv", "-o", "rf_csv.zip"]);
+    run(&dir, "zip_rftxt", &["sim.rf.txt", "rf_txt.zip1", "1"]);
+    run(&dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", "rf_txt.zip"]);
+    run(&dir, "zip_pipe", &["sim_countRT.csv", "pipe.zip"]);
+    write_rc(&dir.join("sim.rf.csv"), &dir.join("sim.rc"), Some(&dir.join("sim.rci")));
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "zip.csv", &[]);
+    merge(&dir, "sim.rc", "rf_txt.zip", "pipe.zip", "rc.csv", &[]);
+    assert_eq!(data_lines(&dir.join("zip.csv"), Canon::default()), data_lines(&dir.join("rc.csv"), Canon::default()));
+}
+
+// rows reversed, order of the input lines does not change the merged file
+#[test]
+fn merge_input_order() {
+    let dir = workdir("merge_input_order");
+    simulated(&dir, 6);
+    run(&dir, "zip_rfcsv", &["-i", "sim.rf.csv", "-o", "rf_csv.zip"]);
+    run(&dir, "zip_rftxt", &["sim.rf.txt", "rf_txt.zip1", "1"]);
+    run(&dir, "zip_rftxt2", &["-i", "rf_txt.zip1", "-o", "rf_txt.zip"]);
+    run(&dir, "zip_pipe", &["sim_countRT.csv", "pipe.zip"]);
+    for name in ["rf_csv.zip", "rf_txt.zip", "pipe.zip"] {
+        let text = fs::read_to_string(dir.join(name)).unwrap();
+        let (header, rows): (Vec<&str>, Vec<&str>) = text.lines().partition(|l| l.starts_with('@'));
+        let shuffled: Vec<&str> = header.into_iter().chain(rows.into_iter().rev()).collect();
+        fs::write(dir.join(format!("rev.{}", name)), shuffled.join("\n") + "\n").unwrap();
+    }
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "merged.csv", &[]);
+    merge(&dir, "rev.rf_csv.zip", "rev.rf_txt.zip", "rev.pipe.zip", "rev.csv", &[]);
+    assert_eq!(data_lines(&dir.join("merged.csv"), Canon::default()), data_lines(&dir.join("rev.csv"), Canon::default()));
+}
+
+#[test]
+fn merge_thread_count() {
+    let dir = workdir("merge_thread_count");
+    zip_all(&dir);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "one.csv", &[("RAYON_NUM_THREADS", "1")]);
+    merge(&dir, "rf_csv.zip", "rf_txt.zip", "pipe.zip", "many.csv", &[("RAYON_NUM_THREADS", "8")]);
+    assert_eq!(data_lines(&dir.join("one.csv"), Canon::default()), data_lines(&dir.join("many.csv"), Canon::default()));
+}
+
+// the same seed gives the same files for any thread count
+#[test]
+fn simulate_thread_count() {
+    let dir = workdir("simulate_thread_count");
+    run(&dir, "simulate", &["-s", "genome.dot", "-o", "one", "-c", "600", "-t", "1"]);
+    run(&dir, "simulate", &["-s", "genome.dot", "-o", "many", "-c", "600", "-t", "8"]);
+    for suffix in [".rf.csv", ".rf.txt", ".labels.csv"] {
+        assert_eq!(
+            data_lines(&dir.join(format!("one{}", suffix)), Canon::default()),
+            data_lines(&dir.join(format!("many{}", suffix)), Canon::default()),
+            "{}",
+            suffix
+        );
+    }
+}
@@ -1,7 +1,13 @@
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Result, Write};
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Result, Write};
+use std::path::Path;
+
+mod provenance;
+mod zipped;
+use provenance::Block;
+use zipped::{Header, Kind};
 
 fn main() -> Result<()> {
     // Read command line arguments
@@ -17,56 +23,43 @@ fn main() -> Result<()> {
     let file = File::open(input_file)?;
     let reader = BufReader::new(file);
 
-    let mut chrid_strand_list = Vec::new();
-    let mut file_header = Vec::new();
-    let mut optimized_lines = Vec::new();
-    let mut strand_info = Vec::new(); // New vector to store strand information
+    // the countRT header only names its columns, the zip file has its own (zipped.rs)
+    let mut chrid_strand_index: HashMap<(String, String), usize> = HashMap::new();
+    let mut header = Header::new(Kind::Pipe, Path::new(input_file).file_name().and_then(|n| n.to_str()).unwrap_or(input_file));
+    let mut optimized_lines: Vec<u8> = Vec::new();
 
     for line in reader.lines() {
         let line = line?;
-        if line.starts_with('@') {
-            file_header.push(line.clone()); // Clone the line for the header
-            if line.starts_with("@ChrID_Index") {
-                file_header.push("@ChrID_Strand".to_string()); // Add @ChrID_Strand after @ChrID_Index
-            }
-        } else {
-            let parts: Vec<String> = line.split(',').map(|s| s.to_string()).collect();
-
-            let chrid = &parts[0];
-            let strand = &parts[1];
-            let chrid_strand = format!("{}({})", chrid, strand);
-
-            if !chrid_strand_list.contains(&chrid_strand) {
-                chrid_strand_list.push(chrid_strand.clone());
-                strand_info.push(strand.to_string()); // Store strand information
-            }
-
-            let chrid_index = chrid_strand_list.iter().position(|r| r == &chrid_strand).unwrap() + 1;
-            let chrid_index_str = chrid_index.to_string();
-
-            let mut new_parts = parts.clone();
-            new_parts[0] = chrid_index_str;
-            new_parts.remove(1); // Remove the strand information
-            optimized_lines.push(new_parts.join(","));
+        if line.starts_with('@') || line.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(',').collect();
+        if parts.len() < 6 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}: not a countRT line: {}", input_file, line)));
         }
-    }
-
-    let mut optimized_file = File::create(output_file)?;
 
-    for line in &file_header {
-        writeln!(optimized_file, "{}", line)?;
+        let chrid_strand = (parts[0].to_string(), parts[1].to_string());
+        let next = chrid_strand_index.len();
+        let chrid_index = *chrid_strand_index.entry(chrid_strand).or_insert_with(|| {
+            header.transcripts.push((parts[0].to_string(), parts[1].chars().next().unwrap_or('+')));
+            next
+        });
+        let position = parts[2].parse::<usize>().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{}: ChrPos {} is not a position", input_file, parts[2])))?;
+        zipped::write_row(&mut optimized_lines, chrid_index, position, &parts[3..])?;
     }
 
-    // Write the index section
-    writeln!(optimized_file, "@ChrID_Index\t{}", chrid_strand_list.iter().map(|s| s.split('(').next().unwrap()).collect::<Vec<&str>>().join("\t"))?;
+    let mut optimized_file = BufWriter::new(File::create(output_file)?);
 
-    // Write the strand information section
-    writeln!(optimized_file, "@ChrID_Strand\t{}", strand_info.join("\t"))?;
+    // Provenance of the input and of this run
+    let mut block = Block::new("zip_pipe", "1.0");
+    block.input(input_file);
+    header.provenance = provenance::upstream(&[input_file], '@');
+    header.provenance.extend(block.lines('@'));
+    header.write(&mut optimized_file)?;
 
     // Write the optimized data lines
-    for line in optimized_lines {
-        writeln!(optimized_file, "{}", line)?;
-    }
+    optimized_file.write_all(&optimized_lines)?;
+    optimized_file.flush()?;
 
     Ok(())
 }
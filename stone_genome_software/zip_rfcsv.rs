@@ -1,6 +1,6 @@
 use clap::Parser;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::sync::{Arc, Mutex};
 use memmap2::Mmap;
 use rayon::prelude::*;
@@ -8,6 +8,14 @@ use rayon::ThreadPoolBuilder;
 use std::path::Path;
 use std::time::Instant;
 
+mod config;
+mod provenance;
+mod zipped;
+use clap::CommandFactory;
+use config::Config;
+use provenance::Block;
+use zipped::{Header, Kind};
+
 #[derive(Parser)]
 #[command(name="zip_rfcsv", author="hyf", version="1.0", about="to zip part of RNA framework output file", long_about = None)]
 struct Cli {
@@ -15,10 +23,15 @@ struct Cli {
     input: String,
     #[arg(short,long)]
     output: String,
+    /// falls back to [zip] thread in --config, then 8
     #[arg(short,long)]
-    thread: usize,
+    thread: Option<usize>,
+    /// falls back to [zip] strand in --config, then +
     #[arg(short,long)]
-    strand: char,
+    strand: Option<char>,
+    /// stone.toml
+    #[arg(long)]
+    config: Option<String>,
 }
 
 const BAR_LAB: &str = "-\\|/";
@@ -26,6 +39,12 @@ const BAR_LAB: &str = "-\\|/";
 fn main() {
     let start = Instant::now();
     let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let thread = cli.thread.or(config.usize("zip", "thread")).unwrap_or(8);
+    let strand = cli.strand.or(config.str("zip", "strand").and_then(|s| s.chars().next())).unwrap_or('+');
     let file = File::open(&cli.input).expect("Cannot open input file please check filename\n");
     let mmap = unsafe { Mmap::map(&file).expect("Cannot mmap file") };
     let mmap = Arc::new(mmap);
@@ -71,38 +90,31 @@ fn main() {
 
     println!("Processing data in parallel...");
 
-    let total_result = Arc::new(Mutex::new(Vec::new()));
-    let titleset = Arc::new(Mutex::new(Vec::new()));
-    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
-    pool.install(|| {
+    // transcripts come back in input order, their rows point at the table entry by that number
+    let pool = ThreadPoolBuilder::new().num_threads(thread).build().unwrap();
+    let final_result: Vec<(String, Vec<u8>)> = pool.install(|| {
         let tchunkinfo = Arc::new(chunkinfo);
         let clen = tchunkinfo.len();
         let tregions = Arc::new(regions);
-        let shared_result = Arc::clone(&total_result);
-        let shared_titleset = Arc::clone(&titleset);
         let count = Arc::new(Mutex::new(0));
-        tchunkinfo.par_iter().for_each(|&(start, end)|{
+        tchunkinfo.par_iter().enumerate().map(|(transcript, &(start, end))|{
             let counter = Arc::clone(&count);
-            let pside:usize =(start - 1) as usize;
-            let qside:usize = end as usize;
-            let mut local_result: Vec<String> = Vec::new();
+            let pside:usize = start - 1;
+            let qside:usize = end;
+            let mut local_result: Vec<u8> = Vec::new();
             let mut title = String::new();
             for (index,content) in tregions[pside..qside].iter().enumerate(){
+                let line = String::from_utf8_lossy(&mmap[content.0..=content.1]);
                 if index == 0 {
-                    title = String::from_utf8_lossy(&mmap[content.0..=content.1]).to_string();
-                    let mut titleset = shared_titleset.lock().unwrap();
-                    titleset.push(title.clone());
+                    title = line.trim().to_string();
                 } else {
-                    if String::from_utf8_lossy(&mmap[content.0..=content.1]).to_string().contains(",0,0") {
+                    if line.contains(",0,0") {
                         continue;
                     }
-                    local_result.push(title.clone().replace('\n', ",")+ &index.clone().to_string() + "," + &String::from_utf8_lossy(&mmap[content.0..=content.1]).to_string().replace('\n', ""));
+                    zipped::write_row(&mut local_result, transcript, index, line.trim_end().split(',')).expect("unable to format value");
                 }
-                
             }
-            
-            let mut total_result = shared_result.lock().unwrap();
-            total_result.push(local_result);
+
             let mut num = counter.lock().unwrap();
             *num += 1;
  
@@ -112,46 +124,26 @@ fn main() {
                 BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
                 ((*num * 100) / clen)
             );
-
-        });
-
+            (title, local_result)
+        }).collect()
     });
 
-    let final_result = total_result.lock().unwrap();
-    let final_titleset = titleset.lock().unwrap();
-
     println!("\nOutput data...");
     //add the file title and output
 
-    let mut file = File::create(cli.output).expect("Unable to create file");
-    
-    let formatted_titles: String = final_titleset.iter()
-        .map(|s| s.replace('\n', ""))
-        .collect::<Vec<_>>()
-        .join(" ");
-    let strand_info: String = final_titleset.iter()
-        .map(|_| cli.strand.to_string())
-        .collect::<Vec<_>>()
-        .join(" ");
+    let mut file = BufWriter::new(File::create(&cli.output).expect("Unable to create file"));
     let input_file_name = Path::new(&cli.input).file_name().unwrap().to_str().unwrap();
-    let headers = vec![
-        "@ColNum 5".to_string(),
-        "@ChrID 1".to_string(),
-        "@ChrPos 2".to_string(),
-        "@Base 3".to_string(),
-        format!("@RT_{} 4", input_file_name),
-        format!("@BD_{} 5", input_file_name),
-        format!("@ChrID_index {}",formatted_titles),
-        format!("@ChrID_Strand {}", strand_info),
-    ];
-    for header in headers {
-        writeln!(file, "{}", header).expect("Unable to write header");
-    }
-    for result in final_result.iter() {
-        for line in result {
-            writeln!(file, "{}", line).expect("Unable to write data");
-        }
+    let mut header = Header::new(Kind::RfCsv, input_file_name);
+    header.transcripts = final_result.iter().map(|(title, _)| (title.clone(), strand)).collect();
+    let effective = [("zip.thread", thread.to_string()), ("zip.strand", strand.to_string())];
+    let mut block = Block::new("zip_rfcsv", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&cli.input).extend(config::settings(&config, &effective));
+    header.provenance = block.lines('@');
+    header.write(&mut file).expect("Unable to write header");
+    for (_, rows) in final_result.iter() {
+        file.write_all(rows).expect("Unable to write data");
     }
+    file.flush().expect("Unable to write data");
     let duration = start.elapsed();
     println!("Total run time: {:?}",duration);
 }
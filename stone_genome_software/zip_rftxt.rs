@@ -1,7 +1,9 @@
+mod provenance;
+
+use provenance::Block;
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::thread;
 
 fn process_line(line: &str) -> String {
@@ -34,6 +36,67 @@ fn process_line(line: &str) -> String {
     }
 }
 
+// (values, sum) of the comma separated counts of a line, runs "vxN" counted N times
+fn line_sums(values: &str) -> (u64, u64) {
+    let mut n = 0u64;
+    let mut sum = 0u64;
+    for v in values.split(',') {
+        let (v, times) = match v.split_once('x') {
+            Some((v, times)) => (v, times.trim().parse::<u64>().unwrap_or(1)),
+            None => (v, 1),
+        };
+        n += times;
+        sum = sum.wrapping_add(v.trim().parse::<u64>().unwrap_or(0).wrapping_mul(times));
+    }
+    (n, sum)
+}
+
+#[derive(Default, Clone, Copy, PartialEq)]
+struct Tally {
+    records: u64,
+    values: u64,
+    sum: u64,
+}
+
+impl Tally {
+    fn add(&mut self, line: &str) {
+        match line.split_once('\t') {
+            Some((_, values)) => {
+                let (n, sum) = line_sums(values);
+                self.values += n;
+                self.sum = self.sum.wrapping_add(sum);
+            }
+            None => self.records += 1,
+        }
+    }
+}
+
+// offset of the first transcript line (a line without a tab) at or after `offset`, the file size
+// when there is none
+fn record_start(path: &str, offset: u64, file_size: u64) -> io::Result<u64> {
+    if offset == 0 {
+        return Ok(0);
+    }
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset - 1))?;
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    // the rest of the line holding offset - 1, nothing when offset is a line start
+    let mut pos = offset - 1 + reader.read_until(b'\n', &mut line)? as u64;
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line)? as u64;
+        if n == 0 {
+            return Ok(file_size);
+        }
+        let text = String::from_utf8_lossy(&line);
+        if !text.trim().is_empty() && !text.contains('\t') {
+            return Ok(pos);
+        }
+        pos += n;
+    }
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
     if args.len() != 4 {
@@ -47,69 +110,101 @@ fn main() -> io::Result<()> {
         eprintln!("Invalid number of threads");
         std::process::exit(1);
     });
+    let num_threads = num_threads.max(1);
 
-    let input_file = File::open(input_path)?;
-    let output_file = File::create(output_path)?;
-    let writer = Arc::new(Mutex::new(BufWriter::new(output_file)));
-
-    let file_size = input_file.metadata()?.len();
+    let file_size = File::open(input_path)?.metadata()?.len();
     let chunk_size = file_size / num_threads as u64;
 
-    let mut handles = Vec::new();
-    let result = Arc::new(Mutex::new(Vec::new()));
+    // 每块从转录本行开始, 一个转录本的14行不会被拆到两块里
+    let mut bounds = vec![0];
+    for i in 1..num_threads {
+        let start = record_start(input_path, i as u64 * chunk_size, file_size)?;
+        if start > *bounds.last().unwrap() {
+            bounds.push(start);
+        }
+    }
+    bounds.push(file_size);
+
+    // 输入的计数单独顺序读一遍, 不依赖分块; 漏读或重读的块会让输入输出对不上
+    let tally_path = input_path.clone();
+    let input_handle = thread::spawn(move || -> io::Result<(Tally, u64)> {
+        let mut reader = BufReader::new(File::open(tally_path)?);
+        let mut tally = Tally::default();
+        let mut bytes = 0u64;
+        let mut line = String::new();
+        loop {
+            let n = reader.read_line(&mut line)?;
+            if n == 0 {
+                break;
+            }
+            bytes += n as u64;
+            let trimmed = line.trim_end_matches(['\n', '\r']);
+            if !trimmed.trim().is_empty() {
+                tally.add(trimmed);
+            }
+            line.clear();
+        }
+        Ok((tally, bytes))
+    });
 
-    for i in 0..num_threads {
+    let mut handles = Vec::new();
+    for (start, end) in bounds.iter().zip(&bounds[1..]).map(|(&a, &b)| (a, b)) {
         let input_path = input_path.clone();
-        let result = Arc::clone(&result);
-
-        let handle = thread::spawn(move || -> io::Result<()> {
+        let handle = thread::spawn(move || -> io::Result<(Vec<String>, u64)> {
             let mut input_file = File::open(input_path)?;
-            input_file.seek(SeekFrom::Start(i as u64 * chunk_size))?;
-            let mut reader = BufReader::new(input_file);
+            input_file.seek(SeekFrom::Start(start))?;
+            let mut reader = BufReader::new(input_file).take(end - start);
 
             let mut local_lines = Vec::new();
-            let mut processed_size = 0;
-
-            if i != 0 {
-                // 如果不是第一个块，跳过第一个完整的行，以避免跨块重复
-                let mut first_line = String::new();
-                reader.read_line(&mut first_line)?;
-                processed_size += first_line.len() as u64;
-            }
-
-            for line in reader.lines() {
-                let line = line?;
-                if line.trim().is_empty() {
-                    continue;
-                }
-                local_lines.push((i as u64 * chunk_size + processed_size, process_line(&line)));
-                processed_size += line.len() as u64;
-
-                if processed_size >= chunk_size {
+            let mut bytes = 0u64;
+            let mut line = String::new();
+            loop {
+                let n = reader.read_line(&mut line)?;
+                if n == 0 {
                     break;
                 }
+                bytes += n as u64;
+                let trimmed = line.trim_end_matches(['\n', '\r']);
+                if !trimmed.trim().is_empty() {
+                    local_lines.push(process_line(trimmed));
+                }
+                line.clear();
             }
-
-            let mut result = result.lock().unwrap();
-            result.extend(local_lines);
-
-            Ok(())
+            Ok((local_lines, bytes))
         });
         handles.push(handle);
     }
 
+    // 按块的顺序输出, 与线程数无关; zip_rftxt2 跳过开头的 @ 行
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    let mut block = Block::new("zip_rftxt", "1.0");
+    block.input(input_path).set("thread", num_threads.to_string());
+    for line in block.lines('@') {
+        writeln!(writer, "{}", line)?;
+    }
+    let mut output = Tally::default();
+    let mut chunk_bytes = 0u64;
     for handle in handles {
-        handle.join().unwrap()?;
+        let (lines, bytes) = handle.join().unwrap()?;
+        chunk_bytes += bytes;
+        for line in lines {
+            output.add(&line);
+            writeln!(writer, "{}", line)?;
+        }
     }
+    writer.flush()?;
+    let (input, input_bytes) = input_handle.join().unwrap()?;
 
-    let mut result = result.lock().unwrap();
-    result.sort_by_key(|&(idx, _)| idx);
-
-    let mut writer = writer.lock().unwrap();
-    for (_, line) in result.iter() {
-        writeln!(writer, "{}", line)?;
+    if chunk_bytes != input_bytes {
+        eprintln!("chunks of {} cover {} of its {} bytes", input_path, chunk_bytes, input_bytes);
+        std::process::exit(1);
     }
 
+    println!("records: {} in, {} out", input.records, output.records);
+    println!("values: {} in, {} out; checksum: {} in, {} out", input.values, output.values, input.sum, output.sum);
+    if input != output {
+        eprintln!("checksum mismatch between {} and {}", input_path, output_path);
+        std::process::exit(1);
+    }
     Ok(())
 }
-
@@ -1,13 +1,21 @@
 use clap::Parser;
 use std::fs::File;
-use std::io::Write;
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
 use memmap2::Mmap;
 use rayon::prelude::*;
 use rayon::ThreadPoolBuilder;
 use std::path::Path;
 use std::time::Instant;
 
+mod config;
+mod provenance;
+mod zipped;
+use clap::CommandFactory;
+use config::Config;
+use provenance::Block;
+use zipped::{Header, Kind};
+
 #[derive(Parser)]
 #[command(name="zip_rftxt2", author="hyf", version="1.0", about="to reduction previous step of RNA framework output file", long_about = None)]
 struct Cli {
@@ -15,10 +23,15 @@ struct Cli {
     input: String,
     #[arg(short,long)]
     output: String,
+    /// falls back to [zip] thread in --config, then 8
     #[arg(short,long)]
-    thread: usize,
+    thread: Option<usize>,
+    /// falls back to [zip] strand in --config, then +
     #[arg(short,long)]
-    strand: char,
+    strand: Option<char>,
+    /// stone.toml
+    #[arg(long)]
+    config: Option<String>,
 }
 
 const BAR_LAB: &str = "-\\|/";
@@ -27,20 +40,30 @@ const BAR_LAB: &str = "-\\|/";
 fn main() {
     let start = Instant::now();
     let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let thread = cli.thread.or(config.usize("zip", "thread")).unwrap_or(8);
+    let strand = cli.strand.or(config.str("zip", "strand").and_then(|s| s.chars().next())).unwrap_or('+');
     println!("loading file...");
     let file = File::open(&cli.input).expect("Cannot open input file");
     let mmap = unsafe { Mmap::map(&file).expect("Cannot mmap file") };
-    let mmap = Arc::new(mmap);
 
     let mut chra:Vec<usize>=Vec::new();
     let mut chrb:Vec<usize>=Vec::new();
 
     println!("Cutting file...");
+    // the @stone lines zip_rftxt writes in front of the transcripts
+    let mut body = 0;
+    while mmap.get(body) == Some(&b'@') {
+        body = mmap[body..].iter().position(|&b| b == b'\n').map(|p| body + p + 1).unwrap_or(mmap.len());
+    }
     let mut regions = Vec::new();
     let mut region_start = 0;
     let mut in_region = false;
     let mut c = 0;
-    for (i, &byte) in mmap.iter().enumerate() {
+    for (i, &byte) in mmap.iter().enumerate().skip(body) {
         if !in_region && (byte as char).is_ascii_alphabetic() {
             region_start = i;
             in_region = true;
@@ -70,106 +93,64 @@ fn main() {
         chunkinfo.push((chra[i],chrb[i]));
     }
 
-    println!("Processing data in parallel...");
-    let total_result = Arc::new(Mutex::new(Vec::new()));
-    let titleset = Arc::new(Mutex::new(Vec::new()));
-    let pool = ThreadPoolBuilder::new().num_threads(cli.thread).build().unwrap();
-
-
-    pool.install(|| {
-        let count = Arc::new(Mutex::new(0));
-        let tchunkinfo = Arc::new(chunkinfo);
-        let clen = tchunkinfo.len();
-        let tregions = Arc::new(regions);
-        let shared_result = Arc::clone(&total_result);
-        let shared_titleset = Arc::clone(&titleset);
-        tchunkinfo.par_iter().for_each(|&(start, end)|{
-            let counter = Arc::clone(&count);
-            let pside:usize =(start - 1) as usize;
-            let qside:usize = end as usize;
-            let mut outkv:Vec<Vec<(i32,(usize,usize))>> = Vec::new();
-            let mut kv :Vec<(i32,(usize,usize))> = Vec::new();
-            let mut title = String::new();
-            for (index,content) in tregions[pside..qside].iter().enumerate(){
-                if index == 0 {
-                    title = String::from_utf8_lossy(&mmap[content.0..=content.1]).to_string();
-                    let mut titleset = shared_titleset.lock().unwrap();
-                    titleset.push(title.clone());
-                } else {
-                    println!("{:?}",content.1);
-                    
-                    kv = tokv(&mmap[content.0..=content.1]);
-                    outkv.push(kv);
-                }
-            }
-
-            let delzero:Vec<Vec<(i32, (usize, usize))>> = outkv.into_iter().map(
-                |inner_vector| {
-                    inner_vector
-                        .into_iter()
-                        .filter(|(i, _)| *i != 0)
-                        .collect()
-                }).collect();
-                let mut outputlines:Vec<String> = Vec::new();
-            outputlines = outputline(delzero, &title);
-            
-            let mut total_result = shared_result.lock().unwrap();
-            total_result.push(outputlines);
-            let mut num = counter.lock().unwrap();
-            *num += 1;
- 
-            print!(
-                "\r{}{}{}%",
-                "#".repeat(((*num * 100) / clen)/ 2),
-                BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
-                ((*num * 100) / clen)
-            );
+    // the titles are the first line of each transcript, so the header goes out before the rows
+    let mut file = BufWriter::new(File::create(&cli.output).expect("unable to create file"));
+    let input_file_name = Path::new(&cli.input).file_name().unwrap().to_str().unwrap();
+    let mut header = Header::new(Kind::RfTxt, input_file_name);
+    header.transcripts = chunkinfo
+        .iter()
+        .map(|&(start, _)| {
+            let (a, b) = regions[start - 1];
+            (String::from_utf8_lossy(&mmap[a..=b]).trim().to_string(), strand)
+        })
+        .collect();
+    let effective = [("zip.thread", thread.to_string()), ("zip.strand", strand.to_string())];
+    let mut block = Block::new("zip_rftxt2", Cli::command().get_version().unwrap_or("unknown"));
+    block.input(&cli.input).extend(config::settings(&config, &effective));
+    header.provenance = provenance::upstream(&[&cli.input], '@');
+    header.provenance.extend(block.lines('@'));
+    header.write(&mut file).expect("Unable to write header");
 
+    println!("Processing data in parallel...");
+    // a batch of transcripts at a time, written in input order before the next batch is decoded,
+    // so only one batch of rows is held in memory; rows point at the table entry by that number
+    let pool = ThreadPoolBuilder::new().num_threads(thread).build().unwrap();
+    let batch = thread.max(1) * 16;
+    let clen = chunkinfo.len();
+    let count = Mutex::new(0);
+    for (b, transcripts) in chunkinfo.chunks(batch).enumerate() {
+        let rows: Vec<Vec<u8>> = pool.install(|| {
+            transcripts.par_iter().enumerate().map(|(i, &(start, end))| {
+                let outkv: Vec<Vec<(i32, (usize, usize))>> = regions[start..end].iter().map(|content| tokv(&mmap[content.0..=content.1])).collect();
+
+                let delzero:Vec<Vec<(i32, (usize, usize))>> = outkv.into_iter().map(
+                    |inner_vector| {
+                        inner_vector
+                            .into_iter()
+                            .filter(|(i, _)| *i != 0)
+                            .collect()
+                    }).collect();
+                let mut outputlines:Vec<u8> = Vec::new();
+                outputline(&delzero, b * batch + i, &mut outputlines).expect("unable to format value");
+
+                let mut num = count.lock().unwrap();
+                *num += 1;
+
+                print!(
+                    "\r{}{}{}%",
+                    "#".repeat(((*num * 100) / clen)/ 2),
+                    BAR_LAB.chars().nth(((*num * 100) / clen) % 4).unwrap(),
+                    ((*num * 100) / clen)
+                );
+                outputlines
+            }).collect()
         });
-    });
-    let final_result = total_result.lock().unwrap();
-    let final_titleset = titleset.lock().unwrap();
-    println!("\nOutput data...");
-
-    let mut file = File::create(cli.output).expect("unable to create file");
-    let formatted_titles: String = final_titleset.iter()
-        .map(|s| s.replace('\n', ""))
-        .collect::<Vec<_>>()
-        .join(" ");
-    let strand_info: String = final_titleset.iter()
-        .map(|_| cli.strand.to_string())
-        .collect::<Vec<_>>()
-        .join(" ");
-    let input_file_name = Path::new(&cli.input).file_name().unwrap().to_str().unwrap();
-    let headers = vec![
-        "@ColNum 8".to_string(),
-        "@ChrID 1".to_string(),
-        "@ChrPos 2".to_string(),
-        format!("@AC_{} 3", input_file_name),
-        format!("@AG_{} 4", input_file_name),
-        format!("@AT_{} 5", input_file_name),
-        format!("@CA_{} 6", input_file_name),
-        format!("@CG_{} 7", input_file_name),
-        format!("@CT_{} 8", input_file_name),
-        format!("@GA_{} 9", input_file_name),
-        format!("@GC_{} 10", input_file_name),
-        format!("@GT_{} 11", input_file_name),
-        format!("@TA_{} 12", input_file_name),
-        format!("@TC_{} 13", input_file_name),
-        format!("@TG_{} 14", input_file_name),
-        format!("@ins_{} 15", input_file_name),
-        format!("@del_{} 16", input_file_name),
-        format!("@ChrID_index {}",formatted_titles),
-        format!("@ChrID_Strand {}", strand_info),
-    ];
-    for header in headers {
-        writeln!(file, "{}", header).expect("Unable to write header");
-    }
-    for result in final_result.iter() {
-        for line in result {
-            writeln!(file,"{}",line).expect("unable to write value")
+        for row in &rows {
+            file.write_all(row).expect("unable to write value");
         }
     }
+    println!("\nOutput data...");
+    file.flush().expect("unable to write value");
     let duration = start.elapsed();
     println!("Total run time: {:?}",duration);
 }
@@ -196,54 +177,41 @@ fn tokv(slice: &[u8])->Vec<(i32,(usize,usize))>{
             sign = b + 1;
         } else {
             kvlist.push((each.parse::<i32>().expect("one word fail to i32"),(sign,sign)));
-            sign = sign + 1;
+            sign += 1;
         }
     }
     kvlist
 }
 
-fn outputline(input:Vec<Vec<(i32, (usize, usize))>>,title:&str) -> Vec<String>{
-    let mut intervals: Vec<(usize, usize)> = Vec::new();
-    for inner_vector in &input {
-        for &(_, (start, end)) in inner_vector {
-            intervals.push((start, end));
-        }
-    }
-    intervals.sort_by(|a, b| a.0.cmp(&b.0)); 
-    let mut merged_intervals: Vec<(usize, usize)> = Vec::new();
-    for interval in intervals {
-        if let Some(last) = merged_intervals.last_mut() {
-            if interval.0 <= last.1 {
-                last.1 = last.1.max(interval.1);
-            } else {
-                merged_intervals.push(interval);
-            }
-        } else {
-            merged_intervals.push(interval);
-        }
-    }
-    let mut output_vector: Vec<(usize, Vec<i32>)> = Vec::new();
-
-    for &(start, end) in &merged_intervals {
-        for i in start..=end {
-            let mut row: Vec<i32> = vec![0; input.len()];
-
-            for (col_idx, inner_vector) in input.iter().enumerate() {
-                for &(value, (start, end)) in inner_vector {
-                    if i >= start && i <= end {
-                        row[col_idx] = value;
+// The nonzero runs of the 14 columns are walked in lockstep: every column keeps a cursor on its
+// current run, a position is written when some run covers it and the sweep jumps over the stretches
+// no run covers, so the work is the positions written times 14 plus the runs.
+fn outputline(input: &[Vec<(i32, (usize, usize))>], transcript: usize, out: &mut impl Write) -> io::Result<()> {
+    let mut cursor = vec![0usize; input.len()];
+    let mut row: Vec<i32> = vec![0; input.len()];
+    let next = |cursor: &[usize], from: usize| {
+        input.iter().zip(cursor).filter_map(|(runs, &c)| runs.get(c).map(|&(_, (start, _))| start.max(from))).min()
+    };
+    let mut position = match next(&cursor, 0) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    loop {
+        for (col, runs) in input.iter().enumerate() {
+            row[col] = match runs.get(cursor[col]) {
+                Some(&(value, (start, end))) if start <= position => {
+                    if end == position {
+                        cursor[col] += 1;
                     }
+                    value
                 }
-            }
-            output_vector.push((i, row));
+                _ => 0,
+            };
         }
+        zipped::write_row(out, transcript, position + 1, &row)?;
+        position = match next(&cursor, position + 1) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
     }
-    let mut formatted_output: Vec<String> = Vec::new();
-    for (index, row) in output_vector.iter() {
-        let row_str = row.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
-        let formatted_row = format!("{}, {}, {}", title.trim(), index + 1, row_str);
-        formatted_output.push(formatted_row);
-    }
-    formatted_output
 }
-
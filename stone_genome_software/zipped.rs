@@ -0,0 +1,238 @@
+#![allow(dead_code)]
+// The zip file format written by zip_rfcsv, zip_rftxt2 and zip_pipe and read by merge, stone.R and
+// the Python module. Version 2:
+//
+//   @stone-zip<TAB>2
+//   @kind<TAB>rf_csv | rf_txt | pipe
+//   @source<TAB><input file name>
+//   @columns<TAB>ChrID<TAB>ChrPos<TAB>...            names of the row fields
+//   @ChrID_Index<TAB><name 1><TAB><name 2>...        transcript table
+//   @ChrID_Strand<TAB>+<TAB>-...                     strand of each table entry
+//   @stone<TAB>key<TAB>value                         provenance, see provenance.rs
+//   1,17,A,3,120                                     rows
+//
+// Header lines start with '@' and come before the rows, their fields are separated by tabs, unknown
+// keys are skipped. Row fields are separated by ',' without spaces; ChrID is the 1-based number of
+// the (name, strand) entry of the transcript table and ChrPos is 1-based.
+//
+// Files of the earlier tools (version 1) are read as well: zip_rfcsv and zip_rftxt2 wrote the
+// ChrID name in the rows (zip_rftxt2 with ", "), a space-separated "@ChrID_index" table and
+// "@<column>_<file> <n>" lines; zip_pipe wrote the table as "@ChrID_Index" with tabs.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+
+pub const VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// zip_rfcsv: RNA Framework base, mutation count and depth
+    RfCsv,
+    /// zip_rftxt2: the 12 substitution, insertion and deletion counts of RNA Framework
+    RfTxt,
+    /// zip_pipe: icSHAPE-pipe RT stops and depth
+    Pipe,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::RfCsv => "rf_csv",
+            Kind::RfTxt => "rf_txt",
+            Kind::Pipe => "pipe",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Kind> {
+        [Kind::RfCsv, Kind::RfTxt, Kind::Pipe].into_iter().find(|k| k.name() == name)
+    }
+
+    pub fn columns(self) -> &'static [&'static str] {
+        match self {
+            Kind::RfCsv | Kind::Pipe => &["ChrID", "ChrPos", "Base", "RT", "BD"],
+            Kind::RfTxt => &["ChrID", "ChrPos", "AC", "AG", "AT", "CA", "CG", "CT", "GA", "GC", "GT", "TA", "TC", "TG", "ins", "del"],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u32,
+    pub kind: Kind,
+    pub source: String,
+    pub columns: Vec<String>,
+    /// (name, strand), row ChrID n is entry n - 1
+    pub transcripts: Vec<(String, char)>,
+    /// "@stone..." lines
+    pub provenance: Vec<String>,
+    // version 1 rf rows carry the name instead of the number
+    by_name: HashMap<String, usize>,
+}
+
+/// One row with the transcript resolved; `fields` are the columns after ChrID and ChrPos.
+pub struct Row<'a> {
+    pub chr_id: &'a str,
+    pub strand: char,
+    pub position: u32,
+    pub fields: Vec<&'a str>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Header {
+    pub fn new(kind: Kind, source: &str) -> Header {
+        Header {
+            version: VERSION,
+            kind,
+            source: source.to_string(),
+            columns: kind.columns().iter().map(|s| s.to_string()).collect(),
+            transcripts: Vec::new(),
+            provenance: Vec::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let names: Vec<&str> = self.transcripts.iter().map(|(name, _)| name.as_str()).collect();
+        let strands: Vec<String> = self.transcripts.iter().map(|(_, strand)| strand.to_string()).collect();
+        writeln!(out, "@stone-zip\t{}", VERSION)?;
+        writeln!(out, "@kind\t{}", self.kind.name())?;
+        writeln!(out, "@source\t{}", self.source)?;
+        writeln!(out, "@columns\t{}", self.columns.join("\t"))?;
+        writeln!(out, "@ChrID_Index\t{}", names.join("\t"))?;
+        writeln!(out, "@ChrID_Strand\t{}", strands.join("\t"))?;
+        for line in &self.provenance {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Errors unless the file is of `kind`, so a swapped input is caught before merging.
+    pub fn expect(&self, kind: Kind, path: &str) -> io::Result<()> {
+        if self.kind != kind {
+            return Err(invalid(format!("{}: a {} zip file where {} was expected", path, self.kind.name(), kind.name())));
+        }
+        Ok(())
+    }
+
+    /// A data line of the file; errors on a wrong field count, position or ChrID.
+    pub fn row<'a>(&'a self, line: &'a str) -> io::Result<Row<'a>> {
+        let parts: Vec<&str> = line.split(',').map(|s| s.trim()).collect();
+        if parts.len() != self.columns.len() {
+            return Err(invalid(format!("{} fields where {} were expected: {}", parts.len(), self.columns.len(), line)));
+        }
+        let position = parts[1].parse::<u32>().map_err(|_| invalid(format!("ChrPos {} is not a position", parts[1])))?;
+        let entry = match self.by_name.get(parts[0]) {
+            Some(&i) if self.version < 2 => Some(i),
+            _ => parts[0].parse::<usize>().ok().filter(|i| *i >= 1 && *i <= self.transcripts.len()).map(|i| i - 1),
+        };
+        let (chr_id, strand) = match entry {
+            Some(i) => (self.transcripts[i].0.as_str(), self.transcripts[i].1),
+            // version 1 rf rows of a transcript missing from the table
+            None if self.version < 2 && self.kind != Kind::Pipe => (parts[0], '+'),
+            None => return Err(invalid(format!("ChrID {} not in @ChrID_Index", parts[0]))),
+        };
+        Ok(Row { chr_id, strand, position, fields: parts[2..].to_vec() })
+    }
+}
+
+/// One row: `transcript` is the 0-based entry of the transcript table, `position` 1-based.
+pub fn write_row<T: Display>(out: &mut impl Write, transcript: usize, position: usize, fields: impl IntoIterator<Item = T>) -> io::Result<()> {
+    write!(out, "{},{}", transcript + 1, position)?;
+    for field in fields {
+        write!(out, ",{}", field)?;
+    }
+    writeln!(out)
+}
+
+/// The header of a zip file of either version and its data lines; errors name the file.
+pub fn open(path: &str) -> io::Result<(Header, impl Iterator<Item = io::Result<String>> + Send)> {
+    let (header, data) = open_numbered(path)?;
+    Ok((header, data.map(|(_, line)| line)))
+}
+
+/// As `open`, every data line with its 1-based line number in the file.
+pub fn open_numbered(path: &str) -> io::Result<(Header, impl Iterator<Item = (usize, io::Result<String>)> + Send)> {
+    let with_path = |e: io::Error| io::Error::new(e.kind(), format!("{}: {}", path, e));
+    let mut lines = BufReader::new(File::open(path).map_err(with_path)?).lines().enumerate().map(|(i, line)| (i + 1, line));
+    let mut header_lines = Vec::new();
+    let mut first = None;
+    for (number, line) in lines.by_ref() {
+        let line = line.map_err(with_path)?;
+        if line.starts_with('@') {
+            header_lines.push(line);
+        } else if !line.trim().is_empty() {
+            first = Some((number, line));
+            break;
+        }
+    }
+    let header = parse_header(&header_lines).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+    let data = first.map(|(number, line)| (number, Ok(line))).into_iter().chain(lines).filter(|(_, line)| match line {
+        Ok(line) => !line.starts_with('@') && !line.trim().is_empty(),
+        Err(_) => true,
+    });
+    Ok((header, data))
+}
+
+fn parse_header(lines: &[String]) -> Result<Header, String> {
+    let mut version = 1;
+    let mut kind = None;
+    let mut source = String::new();
+    let mut columns = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    let mut strands: Vec<char> = Vec::new();
+    let mut provenance = Vec::new();
+    let mut legacy_rf_txt = false;
+    for line in lines {
+        let h = &line[1..];
+        let (key, rest) = h.split_once('\t').unwrap_or((h, ""));
+        match key {
+            "stone-zip" => version = rest.trim().parse::<u32>().map_err(|_| format!("bad version {}", rest))?,
+            "kind" => kind = Some(Kind::parse(rest.trim()).ok_or_else(|| format!("unknown kind {}", rest))?),
+            "source" => source = rest.to_string(),
+            "columns" => columns = rest.split('\t').map(String::from).collect(),
+            "stone" => provenance.push(line.clone()),
+            // zip_pipe version 1, tabs
+            "ChrID_Index" => {
+                names = rest.split('\t').map(String::from).collect();
+                kind = kind.or(Some(Kind::Pipe));
+            }
+            _ => {
+                // zip_rfcsv and zip_rftxt2 version 1, spaces
+                if let Some(rest) = h.strip_prefix("ChrID_index ") {
+                    names = rest.split(' ').filter(|s| !s.is_empty()).map(String::from).collect();
+                } else if let Some(rest) = h.strip_prefix("ChrID_Strand") {
+                    strands = rest.split_whitespace().filter_map(|s| s.chars().next()).collect();
+                } else if h.starts_with("AC_") {
+                    legacy_rf_txt = true;
+                }
+            }
+        }
+    }
+    if version > VERSION {
+        return Err(format!("zip file version {} is newer than this reader ({})", version, VERSION));
+    }
+    let kind = match kind {
+        Some(kind) => kind,
+        None if version >= 2 => return Err("no @kind line".to_string()),
+        None if legacy_rf_txt => Kind::RfTxt,
+        None => Kind::RfCsv,
+    };
+    if columns.is_empty() {
+        columns = kind.columns().iter().map(|s| s.to_string()).collect();
+    }
+    if columns.len() < 2 {
+        return Err("@columns needs ChrID and ChrPos".to_string());
+    }
+    let transcripts: Vec<(String, char)> = names.into_iter().enumerate().map(|(i, name)| (name, strands.get(i).copied().unwrap_or('+'))).collect();
+    let mut by_name = HashMap::new();
+    if version < 2 && kind != Kind::Pipe {
+        for (i, (name, _)) in transcripts.iter().enumerate() {
+            by_name.entry(name.clone()).or_insert(i);
+        }
+    }
+    Ok(Header { version, kind, source, columns, transcripts, provenance, by_name })
+}